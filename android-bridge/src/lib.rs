@@ -1,53 +1,70 @@
 use jni::objects::{JClass, JObject, JString, JValue};
 use jni::sys::{jint, jlong, jstring, jboolean};
 use jni::JNIEnv;
-use std::sync::{Arc, Mutex};
+use std::panic::AssertUnwindSafe;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
 use once_cell::sync::Lazy;
-use rust_core::{messages::{ClientMessage, ServerMessage, ControlCommand}, clock::ClockOffset, pid::PidController};
-use futures::{SinkExt, StreamExt};
-use tokio_tungstenite::{connect_async, tungstenite::protocol::Message};
+use rust_core::{messages::{capability, ClientKind, ClientMessage, ErrorCode, ServerMessage, ControlCommand, Role}, clock::{Clock, MonotonicClock}};
+use rust_core::track::{SourceKind, Track};
+use sonicsync_client::{Client, ClientConfig, ConnectionState, Event, SyncQuality};
 use url::Url;
-use std::time::{SystemTime, UNIX_EPOCH};
 
 // Global state for simple JNI access
 static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().unwrap());
-static CLIENT_STATE: Lazy<Arc<Mutex<ClientState>>> = Lazy::new(|| Arc::new(Mutex::new(ClientState::new())));
-static WS_SENDER: Lazy<Arc<Mutex<Option<tokio::sync::mpsc::Sender<ClientMessage>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+static LOCAL_CLOCK: Lazy<MonotonicClock> = Lazy::new(MonotonicClock::new);
+// The current connection; a second connect() replaces rather than duplicates it
+static CLIENT: Lazy<Mutex<Option<Client>>> = Lazy::new(|| Mutex::new(None));
+// That connection's Java callback, for requests that fail before reaching the server
+static CALLBACK: Lazy<Mutex<Option<jni::objects::GlobalRef>>> = Lazy::new(|| Mutex::new(None));
 
 // Server Handle for stopping/controlling
 static SERVER_HANDLE: Lazy<Arc<Mutex<Option<tokio::task::JoinHandle<()>>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+static SERVER_STATE: Lazy<Arc<Mutex<Option<server::app_state::SharedState>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
 
+// Recovers from poisoning: a panic elsewhere must not turn every later JNI call into one
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
 
+fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
 
+fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
 
-static SERVER_STATE: Lazy<Arc<Mutex<Option<server::app_state::SharedState>>>> = Lazy::new(|| Arc::new(Mutex::new(None)));
+fn client_slot() -> MutexGuard<'static, Option<Client>> {
+    lock(&CLIENT)
+}
 
-struct ClientState {
-    offset: i64,
-    rtt: u64,
-    drift: f64,
-    pid: PidController,
+fn server_state() -> MutexGuard<'static, Option<server::app_state::SharedState>> {
+    lock(&SERVER_STATE)
 }
 
-impl ClientState {
-    fn new() -> Self {
-        Self {
-            offset: 0,
-            rtt: 0,
-            drift: 0.0,
-            pid: PidController::new(0.005, 0.0001, 0.001), // Tuned for audio
-        }
-    }
+// Unwinding into the JVM aborts the app: log the panic and hand Java `fallback` instead
+fn guarded<R>(name: &str, fallback: R, body: impl FnOnce() -> R) -> R {
+    std::panic::catch_unwind(AssertUnwindSafe(body)).unwrap_or_else(|_| {
+        log::error!("{} panicked", name);
+        fallback
+    })
+}
+
+fn current_client() -> Option<Client> {
+    client_slot().clone()
 }
 
 // Initialize logger
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_initLogger(_env: JNIEnv, _class: JClass) {
-    android_logger::init_once(
-        android_logger::Config::default().with_max_level(log::LevelFilter::Info),
-    );
-    log::info!("Rust Logger Initialized");
+    guarded("initLogger", (), || {
+        android_logger::init_once(
+            android_logger::Config::default().with_max_level(log::LevelFilter::Info),
+        );
+        log::info!("Rust Logger Initialized");
+    })
 }
 
 // --- HOST MODE METHODS ---
@@ -58,17 +75,19 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_startServer(
     _class: JClass,
     port: jint
 ) {
-    log::info!("Starting Embedded Server on port {}", port);
-    
-    // Create new AppState
-    let state = server::app_state::AppState::new();
-    *SERVER_STATE.lock().unwrap() = Some(state.clone());
-    
-    let handle = RUNTIME.spawn(async move {
-        server::run(port as u16, state).await;
-    });
-    
-    *SERVER_HANDLE.lock().unwrap() = Some(handle);
+    guarded("startServer", (), || {
+        log::info!("Starting Embedded Server on port {}", port);
+
+        // Create new AppState
+        let state = server::app_state::AppState::new();
+        *server_state() = Some(state.clone());
+
+        let handle = RUNTIME.spawn(async move {
+            server::run(port as u16, state).await;
+        });
+
+        *lock(&SERVER_HANDLE) = Some(handle);
+    })
 }
 
 #[no_mangle]
@@ -76,11 +95,13 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_stopServer(
     _env: JNIEnv,
     _class: JClass
 ) {
-    log::info!("Stopping Embedded Server...");
-    if let Some(handle) = SERVER_HANDLE.lock().unwrap().take() {
-        handle.abort(); // crude but effective for now
-    }
-    *SERVER_STATE.lock().unwrap() = None;
+    guarded("stopServer", (), || {
+        log::info!("Stopping Embedded Server...");
+        if let Some(handle) = lock(&SERVER_HANDLE).take() {
+            handle.abort(); // crude but effective for now
+        }
+        *server_state() = None;
+    })
 }
 
 #[no_mangle]
@@ -89,16 +110,20 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_hostFile(
     _class: JClass,
     j_path: JString
 ) {
-    let path_res: Result<String, _> = env.get_string(&j_path).map(|s| s.into());
-    if let Ok(path) = path_res {
-        log::info!("Hosting file: {}", path);
-        if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-            let mut guard = state.hosted_file_path.write().unwrap();
-            *guard = Some(path);
-        } else {
-            log::error!("Cannot host file: Server not running");
+    guarded("hostFile", (), || {
+        let path_res: Result<String, _> = env.get_string(&j_path).map(|s| s.into());
+        if let Ok(path) = path_res {
+            log::info!("Hosting file: {}", path);
+            if let Some(state) = server_state().as_ref() {
+                // The embedded host always serves the default room
+                let room = state.default_room();
+                let file = server::app_state::HostedFile::new(path);
+                *write(&room.hosted_file) = Some(file);
+            } else {
+                log::error!("Cannot host file: Server not running");
+            }
         }
-    }
+    })
 }
 
 #[no_mangle]
@@ -106,17 +131,10 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_getLocalIpAddress(
     env: JNIEnv, 
     _class: JClass
 ) -> jstring {
-    // This is hard to do reliably in Rust on Android without permissions/netlink.
-    // It's often easier to do in Java. 
-    // But if requested, we can try `local_ip_address` crate if available, or just return empty and let Java handle it.
-    // For now implementation is stubbed or we use a basic heuristic if possible.
-    // Actually, `get_if_addrs` crate is common.
-    // Given the constraints, I will return "0.0.0.0" and let the user do it in Java or add the crate.
-    // The directive says Expose "getLocalIpAddress". I'll try to implement it using `std::net` if possible, but identifying the *correct* IP (wlan0) is tricky.
-    // Let's defer to returning a placeholder or letting Java logic prevail if this returns null.
-    // I'll return "UNKNOWN" for now to hint using Java's WifiManager is better.
-    let output = env.new_string("USE_JAVA_WIFIMANAGER").unwrap();
-    output.into_raw()
+    guarded("getLocalIpAddress", std::ptr::null_mut(), || {
+        // Java's WifiManager is authoritative for the device's address; this only points there
+        env.new_string("USE_JAVA_WIFIMANAGER").map_or(std::ptr::null_mut(), |s| s.into_raw())
+    })
 }
 
 // Playback Controls (Broadcast via Server)
@@ -127,12 +145,16 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_sendPlay(
     _class: JClass,
     start_pos_ms: jlong
 ) {
-    // We need to inject a Play command into the SERVER, not the client.
-    // Using `server::control::process_control_command`.
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-        let cmd = ControlCommand::Play { start_at_ms: start_pos_ms as u64, delay_ms: 500 }; // Default params
-        server::control::process_control_command(state, cmd);
-    }
+    guarded("sendPlay", (), || {
+        // We need to inject a Play command into the SERVER, not the client.
+        // Using `server::control::process_control_command`.
+        if let Some(state) = server_state().as_ref() {
+            let cmd = ControlCommand::Play { start_at_ms: start_pos_ms as u64, delay_ms: 500 }; // Default params
+            if let Err(e) = server::control::process_control_command(&state.default_room(), Role::Host, cmd) {
+                log::error!("sendPlay failed: {}", e);
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -140,10 +162,14 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_sendPause(
     _env: JNIEnv, 
     _class: JClass
 ) {
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-        let cmd = ControlCommand::Pause;
-        server::control::process_control_command(state, cmd);
-    }
+    guarded("sendPause", (), || {
+        if let Some(state) = server_state().as_ref() {
+            let cmd = ControlCommand::Pause;
+            if let Err(e) = server::control::process_control_command(&state.default_room(), Role::Host, cmd) {
+                log::error!("sendPause failed: {}", e);
+            }
+        }
+    })
 }
 
 #[no_mangle]
@@ -152,10 +178,14 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_sendSeek(
     _class: JClass,
     position_ms: jlong
 ) {
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-        let cmd = ControlCommand::Seek { position_ms: position_ms as u64 };
-        server::control::process_control_command(state, cmd);
-    }
+    guarded("sendSeek", (), || {
+        if let Some(state) = server_state().as_ref() {
+            let cmd = ControlCommand::Seek { position_ms: position_ms as u64 };
+            if let Err(e) = server::control::process_control_command(&state.default_room(), Role::Host, cmd) {
+                log::error!("sendSeek failed: {}", e);
+            }
+        }
+    })
 }
 
 // --- LIVE STREAMING METHODS ---
@@ -165,26 +195,33 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_startLiveStream(
     _env: JNIEnv, 
     _class: JClass
 ) {
-    log::info!("Starting Live Stream");
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-         // Set track_url to "live" to signal clients
-         // Use existing control logic or custom?
-         // Custom logic to clear buffer and set state
-         let mut pb_guard = state.playback_state.write().unwrap();
-         pb_guard.is_playing = true;
-         pb_guard.track_url = "live".to_string();
-         pb_guard.position_ms = 0;
-         pb_guard.last_update_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
-         
-         // Notify clients
-         let msg = ServerMessage::PlayCommand { 
-             track_url: "live".to_string(), 
-             start_at_server_time: 0, 
-             start_at_position_ms: 0, 
-             server_time_at_broadcast: pb_guard.last_update_time 
-         };
-         let _ = state.tx.send(msg);
-    }
+    guarded("startLiveStream", (), || {
+        log::info!("Starting Live Stream");
+        if let Some(state) = server_state().as_ref() {
+             // Set track_url to "live" to signal clients
+             // Use existing control logic or custom?
+             // Custom logic to clear buffer and set state
+             let room = state.default_room();
+             let mut pb_guard = write(&room.playback_state);
+             pb_guard.is_playing = true;
+             pb_guard.load("live", Track::from_url("live", SourceKind::Live));
+             let mut queue = lock(&room.queue);
+             queue.cancel_advance();
+             pb_guard.position_ms = 0;
+             pb_guard.last_update_time = state.clock.now_micros();
+
+             // Notify clients
+             let msg = ServerMessage::PlayCommand { 
+                 track_url: "live".to_string(), 
+                 start_at_server_time: 0, 
+                 start_at_position_ms: 0, 
+                 server_time_at_broadcast: pb_guard.last_update_time,
+                 track: pb_guard.track.clone(),
+             };
+             let _ = room.tx.send(msg);
+             server::queue::detach_queue(&room, &mut queue);
+        }
+    })
 }
 
 #[no_mangle]
@@ -192,16 +229,19 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_stopLiveStream(
     _env: JNIEnv, 
     _class: JClass
 ) {
-    log::info!("Stopping Live Stream");
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-         let mut pb_guard = state.playback_state.write().unwrap();
-         pb_guard.is_playing = false;
-         
-         let msg = ServerMessage::PauseCommand { 
-             server_time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64 
-         };
-         let _ = state.tx.send(msg);
-    }
+    guarded("stopLiveStream", (), || {
+        log::info!("Stopping Live Stream");
+        if let Some(state) = server_state().as_ref() {
+             let room = state.default_room();
+             let mut pb_guard = write(&room.playback_state);
+             pb_guard.is_playing = false;
+
+             let msg = ServerMessage::PauseCommand { 
+                 server_time: state.clock.now_micros()
+             };
+             let _ = room.tx.send(msg);
+        }
+    })
 }
 
 #[no_mangle]
@@ -210,16 +250,18 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_sendAudioChunk(
     _class: JClass,
     j_data: jni::objects::JByteArray
 ) {
-    // Convert Java byte array to Rust Vec<u8>
-    let data_res = env.convert_byte_array(j_data);
-    if let Ok(data) = data_res {
-        // Send to server's audio broadcast channel
-        // We need to access the AppState and check if there is an audio channel
-        // For now, let's assume we need to ADD an audio channel to AppState
-        if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-             let _ = state.audio_tx.send(data);
+    guarded("sendAudioChunk", (), || {
+        // Convert Java byte array to Rust Vec<u8>
+        let data_res = env.convert_byte_array(j_data);
+        if let Ok(data) = data_res {
+            // Send to server's audio broadcast channel
+            // We need to access the AppState and check if there is an audio channel
+            // For now, let's assume we need to ADD an audio channel to AppState
+            if let Some(state) = server_state().as_ref() {
+                 let _ = state.default_room().audio_tx.send(data);
+            }
         }
-    }
+    })
 }
 
 #[no_mangle]
@@ -227,150 +269,226 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_isLiveStreaming(
     _env: JNIEnv, 
     _class: JClass
 ) -> jboolean {
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-         // Check if track_url is "live"
-         let pb_guard = state.playback_state.read().unwrap();
-         return if pb_guard.track_url == "live" { 1 } else { 0 };
-    }
-    0
+    guarded("isLiveStreaming", 0, || {
+        if let Some(state) = server_state().as_ref() {
+             // Check if track_url is "live"
+             let room = state.default_room();
+             let pb_guard = read(&room.playback_state);
+             return if pb_guard.track_url == "live" { 1 } else { 0 };
+        }
+        0
+    })
 }
 
 
 // --- CLIENT MODE METHODS (Existing) ---
+// Thin adapters over sonicsync_client::Client
+
+/// Tell Java our role in the room (onRoleChanged is optional on the callback)
+fn notify_role(jvm: &jni::JavaVM, callback: &jni::objects::GlobalRef, role: Role) {
+    if let Ok(mut env) = jvm.attach_current_thread() {
+        let Ok(role_jstr) = env.new_string(format!("{:?}", role)) else {
+            return;
+        };
+        if env.call_method(callback, "onRoleChanged", "(Ljava/lang/String;)V", &[JValue::Object(&role_jstr)]).is_err() {
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+/// Tell Java where the connection supervisor is (onConnectionState is optional on the callback)
+fn notify_state(jvm: &jni::JavaVM, callback: &jni::objects::GlobalRef, state: ConnectionState) {
+    if let Ok(mut env) = jvm.attach_current_thread() {
+        let Ok(state_jstr) = env.new_string(format!("{:?}", state)) else {
+            return;
+        };
+        if env.call_method(callback, "onConnectionState", "(Ljava/lang/String;)V", &[JValue::Object(&state_jstr)]).is_err() {
+            let _ = env.exception_clear();
+        }
+    }
+}
 
-// Connect to WebSocket
+/// Tell Java how well we're synced (onSyncQuality is optional on the callback)
+fn notify_sync(jvm: &jni::JavaVM, callback: &jni::objects::GlobalRef, quality: SyncQuality) {
+    if let Ok(mut env) = jvm.attach_current_thread() {
+        let est = quality.estimate;
+        let args = [
+            JValue::Long(est.offset),
+            JValue::Long(est.rtt as i64),
+            JValue::Long(est.error_bound as i64),
+            JValue::Bool(quality.settled as u8),
+        ];
+        if env.call_method(callback, "onSyncQuality", "(JJJZ)V", &args).is_err() {
+            let _ = env.exception_clear();
+        }
+    }
+}
+
+/// Forward client events to the Java callback until the client goes away
+async fn forward_events(mut events: broadcast::Receiver<Event>, jvm: jni::JavaVM, callback_ref: jni::objects::GlobalRef) {
+    loop {
+        match events.recv().await {
+            Ok(event) => dispatch(&jvm, &callback_ref, event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => log::warn!("Dropped {} client events", missed),
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+fn dispatch(jvm: &jni::JavaVM, callback_ref: &jni::objects::GlobalRef, event: Event) {
+    let server_msg = match event {
+        Event::State(state) => return notify_state(jvm, callback_ref, state),
+        Event::Role(role) => return notify_role(jvm, callback_ref, role),
+        Event::Sync(quality) => return notify_sync(jvm, callback_ref, quality),
+        Event::Message(msg) => msg,
+    };
+    match server_msg {
+        ServerMessage::PlayCommand { track_url, start_at_server_time, start_at_position_ms, .. } => {
+            log::info!("Received PlayCommand: {} @ {} pos={}", track_url, start_at_server_time, start_at_position_ms);
+            
+            // Call Java callback
+            if let Ok(mut env) = jvm.attach_current_thread() {
+                 let url_jstr = match env.new_string(&track_url) {
+                     Ok(s) => s,
+                     Err(_) => {
+                         log::error!("Failed to create Java string for URL");
+                         return;
+                     }
+                 };
+                 let offset = current_client().map_or(0, |client| client.offset());
+                 
+                 let res = env.call_method(
+                     callback_ref,
+                     "onPlayCommand",
+                     "(Ljava/lang/String;JJJ)V",
+                     &[
+                         JValue::Object(&url_jstr),
+                         JValue::Long(start_at_server_time as i64),
+                         JValue::Long(start_at_position_ms as i64),
+                         JValue::Long(offset)
+                     ]
+                 );
+                 if res.is_err() {
+                     let _ = env.exception_clear();
+                 }
+            }
+        }
+        ServerMessage::PauseCommand { server_time } => {
+             log::info!("Received PauseCommand: @ {}", server_time);
+             if let Ok(mut env) = jvm.attach_current_thread() {
+                 let res = env.call_method(
+                     callback_ref,
+                     "onPauseCommand",
+                     "(J)V",
+                     &[JValue::Long(server_time as i64)]
+                 );
+                 if res.is_err() {
+                     let _ = env.exception_clear();
+                 }
+             }
+        }
+        ServerMessage::Incompatible { reason, server_version, legacy_version } => {
+            log::error!("Server rejected client: {} (server speaks v{} and v{})", reason, legacy_version, server_version);
+        }
+        ServerMessage::Ack { id } => {
+            log::info!("Request #{} applied", id);
+            if let Ok(mut env) = jvm.attach_current_thread() {
+                if env.call_method(callback_ref, "onAck", "(J)V", &[JValue::Long(id as i64)]).is_err() {
+                    let _ = env.exception_clear();
+                }
+            }
+        }
+        ServerMessage::Error { id, code, message } => {
+            log::error!("Request #{} failed: {:?}: {}", id, code, message);
+            if let Ok(mut env) = jvm.attach_current_thread() {
+                let (Ok(code_jstr), Ok(msg_jstr)) = (env.new_string(format!("{:?}", code)), env.new_string(&message)) else {
+                    return;
+                };
+                let res = env.call_method(
+                    callback_ref,
+                    "onError",
+                    "(JLjava/lang/String;Ljava/lang/String;)V",
+                    &[JValue::Long(id as i64), JValue::Object(&code_jstr), JValue::Object(&msg_jstr)]
+                );
+                if res.is_err() {
+                    let _ = env.exception_clear();
+                }
+            }
+        }
+        ServerMessage::PeerList { peers } => {
+            log::debug!("Room roster: {} device(s)", peers.len());
+        }
+        ServerMessage::PeerLeft { session_id, reason } => {
+            log::debug!("Peer {} left: {:?}", session_id, reason);
+        }
+        // Welcome and RoleChanged arrive as Event::Role; TimeResponse never leaves the client
+        _ => {}
+    }
+}
+
+// Connect to WebSocket. Replaces any existing connection; reconnects on its own until
+// the server refuses us or the retries run out. Never panics back into Java.
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_connect(
     mut env: JNIEnv,
     _class: JClass,
     j_url: JString,
+    j_pin: JString,
     j_callback: JObject 
 ) {
-    let url_str: String = env.get_string(&j_url).expect("Couldn't get java string!").into();
-    log::info!("Connecting to: {}", url_str);
-    
-    // Create global ref for callback to use in thread
-    let jvm = env.get_java_vm().unwrap();
-    let callback_ref = env.new_global_ref(j_callback).unwrap();
-
-    RUNTIME.spawn(async move {
-        match connect_async(Url::parse(&url_str).unwrap()).await {
-            Ok((ws_stream, _)) => {
-                log::info!("WebSocket Connected");
-                let (mut write, mut read) = ws_stream.split();
-                
-                // Create a channel for outgoing messages
-                let (tx, mut rx) = tokio::sync::mpsc::channel::<ClientMessage>(32);
-                *WS_SENDER.lock().unwrap() = Some(tx.clone());
-
-                // Join
-                let join_msg = ClientMessage::Join {
-                     device_id: format!("ANDROID-{}", uuid::Uuid::new_v4()),
-                };
-                let _ = write.send(Message::Binary(bincode::serialize(&join_msg).unwrap())).await;
-                
-                // WebSocket Write/Read split logic
-                let mut write_half = write;
-
-                loop {
-                    tokio::select! {
-                        // Handle outgoing messages from channel
-                        Some(client_msg) = rx.recv() => {
-                            if let Ok(bytes) = bincode::serialize(&client_msg) {
-                                if write_half.send(Message::Binary(bytes)).await.is_err() {
-                                    break;
-                                }
-                            }
-                        }
-
-                        // Handle incoming messages from socket
-                        msg = read.next() => {
-                            let msg = match msg {
-                                Some(Ok(m)) => m,
-                                Some(Err(e)) => {
-                                    log::error!("WebSocket read error: {:?}", e);
-                                    break;
-                                },
-                                None => { // Stream ended
-                                    log::info!("WebSocket stream ended.");
-                                    break;
-                                }
-                            };
-
-                            match msg {
-                                Message::Binary(bytes) => {
-                                    if let Ok(server_msg) = bincode::deserialize::<ServerMessage>(&bytes) {
-                                        match server_msg {
-                                            ServerMessage::TimeResponse { t0, t1, t2, .. } => {
-                                                let t3 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
-                                                let stats = ClockOffset::calculate(t0, t1, t2, t3);
-                                                
-                                                let mut state = CLIENT_STATE.lock().unwrap();
-                                                state.offset = stats.offset;
-                                                state.rtt = stats.rtt;
-                                                log::debug!("Sync Updated: Offset={}us RTT={}us", stats.offset, stats.rtt);
-                                            }
-                                            ServerMessage::PlayCommand { track_url, start_at_server_time, start_at_position_ms, .. } => {
-                                                log::info!("Received PlayCommand: {} @ {} pos={}", track_url, start_at_server_time, start_at_position_ms);
-                                                
-                                                // Call Java callback
-                                                if let Ok(mut env) = jvm.attach_current_thread() {
-                                                     let url_jstr = match env.new_string(&track_url) {
-                                                         Ok(s) => s,
-                                                         Err(_) => {
-                                                             log::error!("Failed to create Java string for URL");
-                                                             continue;
-                                                         }
-                                                     };
-                                                     let offset = match CLIENT_STATE.lock() {
-                                                         Ok(guard) => guard.offset,
-                                                         Err(_) => 0,
-                                                     };
-                                                     
-                                                     let _ = env.call_method(
-                                                         &callback_ref,
-                                                         "onPlayCommand",
-                                                         "(Ljava/lang/String;JJJ)V",
-                                                         &[
-                                                             JValue::Object(&url_jstr),
-                                                             JValue::Long(start_at_server_time as i64),
-                                                             JValue::Long(start_at_position_ms as i64),
-                                                             JValue::Long(offset)
-                                                         ]
-                                                     );
-                                                }
-                                            }
-                                            ServerMessage::PauseCommand { server_time } => {
-                                                 log::info!("Received PauseCommand: @ {}", server_time);
-                                                 if let Ok(mut env) = jvm.attach_current_thread() {
-                                                     let _ = env.call_method(
-                                                         &callback_ref,
-                                                         "onPauseCommand",
-                                                         "(J)V",
-                                                         &[JValue::Long(server_time as i64)]
-                                                     );
-                                                 }
-                                            }
-                                            ServerMessage::Welcome { .. } | ServerMessage::SyncRequired => {}
-                                            // _ => {} // Removed wildcard to ensure exhaustive matching in future
-                                        }
-                                    }
-                                }
-                                Message::Close(_) => {
-                                    log::info!("WebSocket received close frame.");
-                                    break;
-                                },
-                                _ => {}
-                            }
-                        }
-                    }
-                }
-                *WS_SENDER.lock().unwrap() = None;
-                log::info!("WebSocket Disconnected");
-            },
-            Err(e) => log::error!("Connection failed: {:?}", e),
+    guarded("connect", (), || {
+        let (Ok(jvm), Ok(callback_ref)) = (env.get_java_vm(), env.new_global_ref(j_callback)) else {
+            log::error!("connect: couldn't hold on to the callback");
+            return;
+        };
+        let url = match env.get_string(&j_url).map(String::from) {
+            Ok(s) => Url::parse(&s).map_err(|e| format!("invalid URL {:?}: {}", s, e)),
+            Err(_) => Err("invalid URL string from Java".to_string()),
+        };
+        let url = match url {
+            Ok(url) => url,
+            Err(e) => {
+                log::error!("connect: {}", e);
+                notify_state(&jvm, &callback_ref, ConnectionState::Failed);
+                return;
+            }
+        };
+        // Empty string means "no PIN"
+        let pin: Option<String> = env.get_string(&j_pin).ok().map(String::from).filter(|p| !p.is_empty());
+        log::info!("Connecting to: {}", url);
+
+        let mut config = ClientConfig::new(url, ClientKind::Android);
+        config.pin = pin;
+        config.capabilities = capability::HOSTED_STREAM | capability::LIVE_STREAM | capability::DRIFT_CORRECTION;
+        let previous = client_slot().take();
+        // Keep our session if this is a reconnect to the same server
+        config.resume_token = previous.as_ref().and_then(Client::resume_token);
+
+        let client = Client::new(config);
+        let events = client.subscribe();
+        *client_slot() = Some(client.clone());
+        *lock(&CALLBACK) = Some(callback_ref.clone());
+        RUNTIME.spawn(async move {
+            if let Some(previous) = previous {
+                previous.disconnect().await;
+            }
+            RUNTIME.spawn(forward_events(events, jvm, callback_ref));
+            if let Err(e) = client.connect().await {
+                log::error!("connect failed: {}", e);
+            }
+        });
+    })
+}
+
+// Stop the connection supervisor, if any
+#[no_mangle]
+pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_disconnect(_env: JNIEnv, _class: JClass) {
+    guarded("disconnect", (), || {
+        lock(&CALLBACK).take();
+        if let Some(client) = client_slot().take() {
+            RUNTIME.spawn(async move { client.disconnect().await });
         }
-    });
+    })
 }
 
 // Host Broadcast Play - Directly broadcasts to all connected clients via SERVER_STATE
@@ -381,89 +499,97 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_broadcastPlay(
     j_url: JString,
     delay_ms: jlong
 ) {
-    let url_res: Result<String, _> = env.get_string(&j_url).map(|s| s.into());
-    let url = match url_res {
-        Ok(s) => s,
-        Err(_) => {
-            log::error!("broadcastPlay: Invalid URL string from Java");
-            return;
-        }
-    };
-
-    log::info!("Host broadcastPlay: {} (delay={}ms)", url, delay_ms);
+    guarded("broadcastPlay", (), || {
+        let url_res: Result<String, _> = env.get_string(&j_url).map(|s| s.into());
+        let url = match url_res {
+            Ok(s) => s,
+            Err(_) => {
+                log::error!("broadcastPlay: Invalid URL string from Java");
+                return;
+            }
+        };
 
-    if let Some(state) = SERVER_STATE.lock().unwrap().as_ref() {
-        // Set the track URL in server playback state
-        {
-            let mut pb_guard = state.playback_state.write().unwrap();
-            pb_guard.track_url = url.clone();
+        log::info!("Host broadcastPlay: {} (delay={}ms)", url, delay_ms);
+
+        if let Some(state) = server_state().as_ref() {
+            // The media proxy and queue timers spawn onto the server's runtime
+            let _runtime = RUNTIME.enter();
+            let room = state.default_room();
+            // Set the track URL in server playback state
+            {
+                let mut pb_guard = write(&room.playback_state);
+                let track = Track::from_url(&url, SourceKind::Url);
+                let track_url = room.media.proxy(&url, &track);
+                pb_guard.load(track_url, track);
+                server::queue::detach_queue(&room, &mut lock(&room.queue));
+            }
+            // Use process_control_command to broadcast Play to all clients
+            let cmd = rust_core::messages::ControlCommand::Play {
+                start_at_ms: 0,
+                delay_ms: delay_ms as u64,
+            };
+            match server::control::process_control_command(&room, Role::Host, cmd) {
+                Ok(()) => log::info!("Host broadcastPlay: PlayCommand sent to all clients"),
+                Err(e) => log::error!("Host broadcastPlay failed: {}", e),
+            }
+        } else {
+            log::error!("broadcastPlay: Server not running! Start the server first.");
         }
-        // Use process_control_command to broadcast Play to all clients
-        let cmd = rust_core::messages::ControlCommand::Play {
-            start_at_ms: 0,
-            delay_ms: delay_ms as u64,
-        };
-        server::control::process_control_command(state, cmd);
-        log::info!("Host broadcastPlay: PlayCommand sent to all clients");
-    } else {
-        log::error!("broadcastPlay: Server not running! Start the server first.");
-    }
+    })
 }
 
 // Request Play (Client Request to Host - Existing)
+// Returns the request id echoed in onAck/onError, or -1 if not connected
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_requestPlay(
     mut env: JNIEnv, 
     _class: JClass, 
     j_url: JString,
     delay_ms: jlong
-) {
-    let url_res: Result<String, _> = env.get_string(&j_url).map(|s| s.into());
-    let url = match url_res {
-        Ok(s) => s,
-        Err(_) => {
-            log::error!("requestPlay: Invalid URL string from Java");
-            return;
-        }
-    };
-
-    let msg = ClientMessage::PlayRequest {
-        track_url: url,
-        delay_ms: delay_ms as u64,
-    };
+) -> jlong {
+    guarded("requestPlay", -1, || {
+        let url_res: Result<String, _> = env.get_string(&j_url).map(|s| s.into());
+        let url = match url_res {
+            Ok(s) => s,
+            Err(_) => {
+                log::error!("requestPlay: Invalid URL string from Java");
+                return -1;
+            }
+        };
 
-    if let Ok(guard) = WS_SENDER.lock() {
-        if let Some(tx) = guard.as_ref() {
-            let tx = tx.clone();
-            RUNTIME.spawn(async move {
-                let _ = tx.send(msg).await;
-            });
-        } else {
+        let (Some(client), Some(callback_ref), Ok(jvm)) = (current_client(), lock(&CALLBACK).clone(), env.get_java_vm()) else {
             log::error!("Cannot send PlayRequest: Not connected to server");
-        }
-    } else {
-        log::error!("WS_SENDER lock poisoned");
-    }
+            return -1;
+        };
+        // Don't hold up the Java thread on the socket; a failed send arrives as onError
+        let id = client.next_request_id();
+        RUNTIME.spawn(async move {
+            let request = ClientMessage::PlayRequest { id, track_url: url, delay_ms: delay_ms as u64 };
+            if let Err(e) = client.send(request).await {
+                let error = ServerMessage::Error { id, code: ErrorCode::Internal, message: format!("couldn't send request: {}", e) };
+                dispatch(&jvm, &callback_ref, Event::Message(error));
+            }
+        });
+        id as jlong
+    })
 }
 
-// Trigger Sync
+// Trigger Sync (the client keeps syncing in the background; this forces a burst now)
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_sendSyncRequest(_env: JNIEnv, _class: JClass) {
-    let t0 = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
-    let msg = ClientMessage::TimeRequest { t0, seq: 0 };
-    
-    if let Some(tx) = WS_SENDER.lock().unwrap().as_ref() {
-        let tx = tx.clone();
-        RUNTIME.spawn(async move {
-            let _ = tx.send(msg).await;
-        });
-    }
+    guarded("sendSyncRequest", (), || {
+        if let Some(client) = current_client() {
+            client.resync();
+        }
+    })
 }
 
 // Get Offset
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_getOffset(_env: JNIEnv, _class: JClass) -> jlong {
-    CLIENT_STATE.lock().unwrap().offset
+    guarded("getOffset", 0, || {
+        current_client().map_or(0, |client| client.offset())
+    })
 }
 
 // Calculate drift correction
@@ -475,24 +601,18 @@ pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_calculateCorrectio
     drift_ms: jlong,
     dt_seconds: f64
 ) -> f64 {
-    let mut state = CLIENT_STATE.lock().unwrap();
-    state.drift = drift_ms as f64; // Store last drift
-    
-    // Target is 0 drift. Error = 0 - drift.
-    let drift = state.drift;
-    let correction = state.pid.next(-drift, dt_seconds);
-
-    
-    // Base speed 1.0 + correction
-    // Clamp to safe limits (0.95 to 1.05) to avoid audio artifacts, though PID should be tighter
-    let speed = 1.0 + correction;
-    speed.clamp(0.95, 1.05)
+    guarded("calculateCorrection", 1.0, || {
+        current_client().map_or(1.0, |client| client.correction(drift_ms, dt_seconds))
+    })
 }
 
 // Current Client Timestamp (in Server Time approximation)
 #[no_mangle]
 pub extern "system" fn Java_com_sonicsync_app_SonicSyncEngine_getServerTime(_env: JNIEnv, _class: JClass) -> jlong {
-    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_micros() as u64;
-    let offset = CLIENT_STATE.lock().unwrap().offset;
-    (now as i64 + offset) as jlong
+    guarded("getServerTime", 0, || {
+        match current_client() {
+            Some(client) => client.server_time() as jlong,
+            None => LOCAL_CLOCK.now_micros() as jlong,
+        }
+    })
 }
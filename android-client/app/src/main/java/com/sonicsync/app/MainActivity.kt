@@ -191,7 +191,6 @@ class MainActivity : ComponentActivity() {
                 startService(intent)
                 SonicSyncEngine.stopServer()
                 discoveryManager.unregisterService()
-                stopSyncHeartbeat()
             } catch (e: Exception) {}
             
             hostLayout.visibility = android.view.View.GONE
@@ -396,7 +395,6 @@ class MainActivity : ComponentActivity() {
             // Stop client stuff
             player?.stop()
             discoveryManager.stopDiscovery()
-            stopSyncHeartbeat()
             
             clientLayout.visibility = android.view.View.GONE
             modeSelectionLayout.visibility = android.view.View.VISIBLE
@@ -454,25 +452,6 @@ class MainActivity : ComponentActivity() {
         return layout
     }
 
-    private val syncHandler = android.os.Handler(android.os.Looper.getMainLooper())
-    private val syncRunnable = object : Runnable {
-        override fun run() {
-            if (SonicSyncEngine.isNativeAvailable()) {
-                SonicSyncEngine.sendSyncRequest()
-            }
-            syncHandler.postDelayed(this, 2000)
-        }
-    }
-
-    private fun startSyncHeartbeat() {
-        syncHandler.removeCallbacks(syncRunnable)
-        syncHandler.post(syncRunnable)
-    }
-
-    private fun stopSyncHeartbeat() {
-        syncHandler.removeCallbacks(syncRunnable)
-    }
-
     private val driftCorrectionRunnable = object : Runnable {
         override fun run() {
             if (!SonicSyncEngine.isNativeAvailable()) {
@@ -560,8 +539,9 @@ class MainActivity : ComponentActivity() {
                                 player = ExoPlayer.Builder(this@MainActivity).build()
                             }
                             
-                            // Load media if different
-                            val mediaItem = MediaItem.fromUri(url)
+                            // Load media if different. Relative URLs ("stream", "media/<id>") are served by the server itself.
+                            val mediaUrl = if (url.contains("://")) url else wsUrl.replace("ws://", "http://").replace("/ws", "/$url")
+                            val mediaItem = MediaItem.fromUri(mediaUrl)
                             player?.setMediaItem(mediaItem)
                             player?.prepare()
                             
@@ -600,11 +580,19 @@ class MainActivity : ComponentActivity() {
                         updateStatus("Status: Paused (Synced)")
                     }
                 }
+
+                override fun onConnectionState(state: String) {
+                    when (state) {
+                        "Connected" -> updateStatus("Status: Connected to $wsUrl")
+                        "Reconnecting" -> updateStatus("Status: Connection lost, reconnecting...")
+                        "Failed" -> updateStatus("Status: Could not connect to $wsUrl")
+                    }
+                }
+
+                override fun onSyncQuality(offsetUs: Long, rttUs: Long, errorBoundUs: Long, settled: Boolean) {
+                    Log.d("SonicSync", "Sync: offset=${offsetUs}us rtt=${rttUs}us +/-${errorBoundUs}us settled=$settled")
+                }
             })
-            if (SonicSyncEngine.isNativeAvailable()) {
-                updateStatus("Status: Connected to $wsUrl")
-                startSyncHeartbeat()
-            }
         } catch (e: Exception) {
             updateStatus("Status: Connection failed - ${e.message}")
             Log.e("SonicSync", "Connection error", e)
@@ -24,6 +24,16 @@ object SonicSyncEngine {
     interface SyncCallback {
         fun onPlayCommand(url: String, startAtServerTime: Long, startAtPositionMs: Long, currentServerOffset: Long)
         fun onPauseCommand(serverTime: Long)
+        // Server applied the request with this id (see requestPlay)
+        fun onAck(requestId: Long) {}
+        // Server refused a request; requestId is 0 if not tied to one
+        fun onError(requestId: Long, code: String, message: String) {}
+        // Our role in the room: "Host", "CoHost" or "Listener"
+        fun onRoleChanged(role: String) {}
+        // "Connecting", "Connected", "Reconnecting" or "Failed" (gave up; call connect again to retry)
+        fun onConnectionState(state: String) {}
+        // After each background sync round; settled once the offset has held still between probes
+        fun onSyncQuality(offsetUs: Long, rttUs: Long, errorBoundUs: Long, settled: Boolean) {}
     }
 
     private var callback: SyncCallback? = null
@@ -36,9 +46,10 @@ object SonicSyncEngine {
         }
     }
 
-    fun safeConnect(url: String, cb: SyncCallback) {
+    // pin: the room's PIN, or null. The first device into a room sets it.
+    fun safeConnect(url: String, cb: SyncCallback, pin: String? = null) {
         if (nativeLoaded) {
-            try { connect(url, cb) } catch (e: Exception) {
+            try { connect(url, pin ?: "", cb) } catch (e: Exception) {
                 Log.e("SonicSync", "connect failed", e)
             }
         } else {
@@ -46,12 +57,22 @@ object SonicSyncEngine {
         }
     }
 
-    fun safeRequestPlay(url: String, delayMs: Long = 2000) {
+    fun safeDisconnect() {
         if (nativeLoaded) {
-            try { requestPlay(url, delayMs) } catch (e: Exception) {
+            try { disconnect() } catch (e: Exception) {
+                Log.e("SonicSync", "disconnect failed", e)
+            }
+        }
+    }
+
+    // Returns the request id reported back via onAck/onError, or -1
+    fun safeRequestPlay(url: String, delayMs: Long = 2000): Long {
+        if (nativeLoaded) {
+            try { return requestPlay(url, delayMs) } catch (e: Exception) {
                 Log.e("SonicSync", "requestPlay failed", e)
             }
         }
+        return -1
     }
 
     fun safeBroadcastPlay(url: String, delayMs: Long = 2000) {
@@ -68,11 +89,14 @@ object SonicSyncEngine {
     @JvmStatic
     private external fun initLogger()
     @JvmStatic
-    private external fun connect(url: String, callback: SyncCallback)
+    private external fun connect(url: String, pin: String, callback: SyncCallback)
+    @JvmStatic
+    private external fun disconnect()
     @JvmStatic
-    private external fun requestPlay(url: String, delayMs: Long)
+    private external fun requestPlay(url: String, delayMs: Long): Long
     @JvmStatic
     private external fun broadcastPlay(url: String, delayMs: Long)
+    // Sync runs in the background while connected; this forces an immediate full resync
     @JvmStatic
     external fun sendSyncRequest()
     @JvmStatic
@@ -90,7 +114,7 @@ object SonicSyncEngine {
     @JvmStatic
     external fun hostFile(path: String)
     @JvmStatic
-    external fun getLocalIpAddress(): String
+    external fun getLocalIpAddress(): String?
     
     // Host Controls
     @JvmStatic
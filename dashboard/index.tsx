@@ -6,14 +6,52 @@ import {
 
 // --- Types ---
 
+// Must match rust_core::messages::PROTOCOL_VERSION
+const PROTOCOL_VERSION = 12;
+
 interface SyncNode {
   id: string;
   type: 'host' | 'client';
   latency: number; 
   offset: number;  
-  status: 'idle' | 'syncing' | 'ready' | 'playing';
+  drift: number;
+  status: string;
+  syncState: SyncState;
+  speaker: boolean; // Plays audio, so must be locked before a broadcast
+}
+
+// rust_core::messages::SyncState
+type SyncState = 'Unsynced' | 'Converging' | 'Locked';
+
+// rust_core::messages::PeerInfo (times in microseconds)
+interface PeerInfo {
+  session_id: string;
+  device_id: string;
+  addr: string;
+  client_kind: string;
+  role: 'Host' | 'CoHost' | 'Listener';
+  connected_at: number;
+  last_seen: number;
+  offset: number;
+  rtt: number;
+  drift: number; // ms
+  status: string;
+  sync_state: SyncState;
+  drift_history: number[];
+  online: boolean;
 }
 
+const toNode = (peer: PeerInfo): SyncNode => ({
+  id: peer.session_id,
+  type: peer.role === 'Host' ? 'host' : 'client',
+  latency: peer.rtt / 1000,
+  offset: peer.offset / 1000,
+  drift: peer.drift,
+  status: peer.online ? (peer.status || 'idle') : 'offline',
+  syncState: peer.sync_state,
+  speaker: peer.online && peer.client_kind !== 'Dashboard',
+});
+
 interface LogEntry {
   timestamp: string;
   source: string;
@@ -26,6 +64,8 @@ const App = () => {
   const [logs, setLogs] = useState<LogEntry[]>([]);
   const [isPlaying, setIsPlaying] = useState(false);
   const [socket, setSocket] = useState<WebSocket | null>(null);
+  const nextRequestId = useRef(1);
+  const sessionId = useRef<string | null>(null);
 
   const addLog = (message: string, source: string = 'SYSTEM', type: 'info' | 'warn' | 'error' | 'success' = 'info') => {
     setLogs(prev => [{
@@ -38,11 +78,21 @@ const App = () => {
 
   useEffect(() => {
     // Connect to Real Rust Server
-    const ws = new WebSocket('ws://' + window.location.hostname + ':3000/ws?mode=dashboard');
+    // Join the same room as the page (?room=...&pin=...), or the server's default room
+    const params = new URLSearchParams(window.location.search);
+    const room = params.get('room');
+    const pin = params.get('pin');
+    const roomQuery = room ? '&room=' + encodeURIComponent(room) : '';
+    // Survives a page reload, so a refresh reattaches to the same session
+    const resumeKey = 'sonicsync.resume.' + (room || 'default');
+    const ws = new WebSocket('ws://' + window.location.hostname + ':3000/ws?type=dashboard' + roomQuery);
     
     ws.onopen = () => {
       addLog('Connected to SONICSYNC Rust Core', 'NETWORK', 'success');
-      ws.send(JSON.stringify({ Join: { device_id: 'DASHBOARD' } }));
+      ws.send(JSON.stringify({
+        Join: { device_id: 'DASHBOARD', protocol_version: PROTOCOL_VERSION, client_kind: 'Dashboard', capabilities: 0, pin,
+                resume_token: sessionStorage.getItem(resumeKey) }
+      }));
       setSocket(ws);
     };
 
@@ -51,16 +101,51 @@ const App = () => {
         const msg = JSON.parse(event.data);
         
         if (msg.Welcome) {
-            addLog(`Session Established: ${msg.Welcome.session_id}`, 'AUTH');
+            sessionId.current = msg.Welcome.session_id;
+            sessionStorage.setItem(resumeKey, msg.Welcome.resume_token);
+            addLog(`Session ${msg.Welcome.resumed ? 'Resumed' : 'Established'}: ${msg.Welcome.session_id} as ${msg.Welcome.role} (protocol v${msg.Welcome.protocol_version})`, 'AUTH');
+        }
+
+        if (msg.RoleChanged && msg.RoleChanged.session_id === sessionId.current) {
+            addLog(`Role changed: now ${msg.RoleChanged.role}`, 'AUTH');
+        }
+
+        if (msg.Ack) {
+            addLog(`Request #${msg.Ack.id} applied`, 'CMD', 'success');
+        }
+
+        if (msg.Error) {
+            const ref = msg.Error.id ? `Request #${msg.Error.id}` : 'Server';
+            addLog(`${ref} failed (${msg.Error.code}): ${msg.Error.message}`, 'CMD', 'error');
+        }
+
+        if (msg.Incompatible) {
+            addLog(`Server rejected dashboard: ${msg.Incompatible.reason}`, 'AUTH', 'error');
         }
         
         if (msg.PlayCommand) {
-            addLog(`PLAY BROADCAST: ${msg.PlayCommand.track_url} @ ${msg.PlayCommand.start_at_server_time}`, 'CORE', 'info');
+            addLog(`PLAY BROADCAST: ${msg.PlayCommand.track?.title ?? msg.PlayCommand.track_url} @ ${msg.PlayCommand.start_at_server_time}`, 'CORE', 'info');
             setIsPlaying(true);
         }
         
-        // Note: Real Telemetry updates would require the server to broadcast 'PeerUpdate' messages.
-        // For Phase 3, we act as a "Host" that can trigger play.
+        if (msg.PeerList) {
+            setNodes((msg.PeerList.peers as PeerInfo[]).map(toNode));
+        }
+
+        if (msg.PeerLeft) {
+            addLog(`Device ${msg.PeerLeft.session_id} left (${msg.PeerLeft.reason})`, 'NETWORK', msg.PeerLeft.reason === 'Closed' ? 'info' : 'warn');
+        }
+
+        if (msg.QueueUpdate) {
+            const { entries, current_entry, repeat } = msg.QueueUpdate;
+            const position = entries.findIndex((e: { entry_id: number }) => e.entry_id === current_entry);
+            addLog(`Queue: ${entries.length} tracks${position >= 0 ? `, playing #${position + 1}` : ''} (repeat ${repeat})`, 'CORE');
+        }
+
+        if (msg.PeerUpdate) {
+            const updated = toNode(msg.PeerUpdate.peer as PeerInfo);
+            setNodes(prev => prev.map(n => n.id === updated.id ? updated : n));
+        }
         
       } catch (e) {
         console.error("Parse error", e);
@@ -77,11 +162,20 @@ const App = () => {
 
   const handlePlay = () => {
     if (!socket) return;
+
+    // Starting before every speaker has a tight clock estimate is audibly out of sync
+    const unlocked = nodes.filter(n => n.speaker && n.syncState !== 'Locked');
+    if (unlocked.length > 0) {
+      addLog(`Playback held: ${unlocked.map(n => `${n.id} ${n.syncState}`).join(', ')}`, 'CMD', 'warn');
+      return;
+    }
     
-    addLog('Requesting Cluster Playback...', 'CMD');
+    const id = nextRequestId.current++;
+    addLog(`Requesting Cluster Playback... (#${id})`, 'CMD');
     // Send PlayRequest to Rust Server
     const req = {
         PlayRequest: {
+            id,
             track_url: 'https://www.soundhelix.com/examples/mp3/SoundHelix-Song-1.mp3',
             delay_ms: 3000
         }
@@ -132,11 +226,27 @@ const App = () => {
              </div>
         </aside>
 
-        <section className="flex-1 flex flex-col bg-black items-center justify-center text-slate-600">
-             <Server size={64} className="mb-4 opacity-20" />
-             <p className="font-mono text-xs">Awaiting Global Telemetry Stream...</p>
-             <p className="text-[10px] mt-2 max-w-md text-center">To see live devices, the Server needs to implement a `PeerList` broadcast. This dashboard currently supports initiating playback and logging server events.</p>
-        </section>
+        {nodes.length === 0 ? (
+          <section className="flex-1 flex flex-col bg-black items-center justify-center text-slate-600">
+               <Server size={64} className="mb-4 opacity-20" />
+               <p className="font-mono text-xs">Awaiting Global Telemetry Stream...</p>
+          </section>
+        ) : (
+          <section className="flex-1 bg-black p-6 overflow-y-auto">
+               <div className="grid grid-cols-3 gap-4">
+                 {nodes.map(node => (
+                   <div key={node.id} className="border border-white/5 rounded-xl p-4 font-mono text-[10px] bg-[#0a0a0c]">
+                     <div className="flex items-center gap-2 mb-2">
+                       {node.type === 'host' ? <Server size={14} className="text-cyan-400" /> : <Smartphone size={14} className="text-emerald-400" />}
+                       <span className="text-slate-300 truncate">{node.id}</span>
+                     </div>
+                     <div className="text-slate-500">RTT {node.latency.toFixed(1)}ms · OFFSET {node.offset.toFixed(1)}ms · DRIFT {node.drift}ms</div>
+                     <div className="text-slate-400 uppercase mt-1">{node.status} · {node.syncState}</div>
+                   </div>
+                 ))}
+               </div>
+          </section>
+        )}
       </main>
     </div>
   );
@@ -1,3 +1,76 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of timestamps (micros) for everything that goes on the wire.
+/// Implementations must never go backwards.
+pub trait Clock: Send + Sync {
+    fn now_micros(&self) -> u64;
+}
+
+/// Wall clock read once at construction, then advanced by a monotonic
+/// `Instant`. Timestamps look like Unix micros but are immune to NTP steps
+/// or the user changing the device clock mid-song.
+#[derive(Debug, Clone, Copy)]
+pub struct MonotonicClock {
+    anchor_wall: u64,
+    anchor: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MonotonicClock {
+    pub fn new() -> Self {
+        let anchor_wall = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_micros() as u64)
+            .unwrap_or(0);
+        Self {
+            anchor_wall,
+            anchor: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_micros(&self) -> u64 {
+        self.anchor_wall + self.anchor.elapsed().as_micros() as u64
+    }
+}
+
+/// Manually driven clock for tests and simulations
+#[derive(Debug, Default)]
+pub struct FakeClock {
+    now: AtomicU64,
+}
+
+impl FakeClock {
+    pub fn new(start_micros: u64) -> Self {
+        Self {
+            now: AtomicU64::new(start_micros),
+        }
+    }
+
+    pub fn advance(&self, micros: u64) {
+        self.now.fetch_add(micros, Ordering::SeqCst);
+    }
+
+    /// Jump to the given time. Ignored if it would move the clock backwards.
+    pub fn set(&self, micros: u64) {
+        self.now.fetch_max(micros, Ordering::SeqCst);
+    }
+}
+
+impl Clock for FakeClock {
+    fn now_micros(&self) -> u64 {
+        self.now.load(Ordering::SeqCst)
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct ClockOffset {
     pub offset: i64,
@@ -11,7 +84,7 @@ impl ClockOffset {
     /// t2: Server transmit time
     /// t3: Client receive time
     pub fn calculate(t0: u64, t1: u64, t2: u64, t3: u64) -> Self {
-        let rtt = (t3 - t0) - (t2 - t1);
+        let rtt = t3.saturating_sub(t0).saturating_sub(t2.saturating_sub(t1));
         let offset = ((t1 as i64 - t0 as i64) + (t2 as i64 - t3 as i64)) / 2;
         
         Self {
@@ -21,6 +94,268 @@ impl ClockOffset {
     }
 }
 
+/// Samples whose RTT exceeds `min_rtt * OUTLIER_RTT_FACTOR` are discarded
+const OUTLIER_RTT_FACTOR: u64 = 2;
+/// ...unless they are within this many micros of the best RTT (LAN jitter floor)
+const OUTLIER_RTT_FLOOR_US: u64 = 2_000;
+
+/// Highest RTT a sample may have and still be trusted, given the window's best
+fn outlier_cutoff(min_rtt: u64) -> u64 {
+    (min_rtt * OUTLIER_RTT_FACTOR).max(min_rtt + OUTLIER_RTT_FLOOR_US)
+}
+
+/// (local time, offset) of the samples in a window that survive outlier rejection
+pub(crate) fn trusted_offsets(points: &VecDeque<(u64, ClockOffset)>) -> Vec<(u64, i64)> {
+    let Some(min_rtt) = points.iter().map(|(_, s)| s.rtt).min() else {
+        return Vec::new();
+    };
+    let max_rtt = outlier_cutoff(min_rtt);
+    points
+        .iter()
+        .filter(|(_, s)| s.rtt <= max_rtt)
+        .map(|(t, s)| (*t, s.offset))
+        .collect()
+}
+
+/// Combined estimate produced by `ClockFilter`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClockEstimate {
+    pub offset: i64,      // Server time - client time (micros)
+    pub rtt: u64,         // RTT of the best (lowest-delay) sample
+    pub error_bound: u64, // Offset is accurate to +/- this many micros
+    pub samples: usize,   // Samples that survived outlier rejection
+}
+
+/// Sliding-window clock filter (after NTP's clock filter algorithm).
+/// Keeps the last N samples, rejects high-RTT outliers and weights the
+/// remaining offsets toward the lowest-RTT samples, since those have the
+/// least room for path asymmetry.
+#[derive(Debug, Clone)]
+pub struct ClockFilter {
+    samples: VecDeque<ClockOffset>,
+    window: usize,
+}
+
+impl Default for ClockFilter {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW)
+    }
+}
+
+impl ClockFilter {
+    pub const DEFAULT_WINDOW: usize = 8;
+
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            samples: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Calculate a sample from NTP timestamps and add it to the window
+    pub fn add_timestamps(&mut self, t0: u64, t1: u64, t2: u64, t3: u64) -> ClockOffset {
+        let sample = ClockOffset::calculate(t0, t1, t2, t3);
+        self.add_sample(sample);
+        sample
+    }
+
+    pub fn add_sample(&mut self, sample: ClockOffset) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Current best estimate, or None if no samples have been collected yet
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        let mut sorted: Vec<ClockOffset> = self.samples.iter().copied().collect();
+        sorted.sort_by_key(|s| s.rtt);
+
+        let best = *sorted.first()?;
+        let max_rtt = outlier_cutoff(best.rtt);
+        sorted.retain(|s| s.rtt <= max_rtt);
+
+        // Weight 1/2, 1/4, 1/8... by RTT rank, normalised over kept samples
+        let mut weight = 1.0;
+        let mut weight_sum = 0.0;
+        let mut weighted = 0.0;
+        for s in &sorted {
+            weight /= 2.0;
+            weight_sum += weight;
+            weighted += weight * s.offset as f64;
+        }
+        let offset = (weighted / weight_sum).round() as i64;
+
+        // Jitter: RMS distance of the kept offsets from the chosen offset
+        let sq_sum: f64 = sorted
+            .iter()
+            .map(|s| {
+                let d = (s.offset - offset) as f64;
+                d * d
+            })
+            .sum();
+        let jitter = (sq_sum / sorted.len() as f64).sqrt();
+
+        Some(ClockEstimate {
+            offset,
+            rtt: best.rtt,
+            error_bound: best.rtt / 2 + jitter.ceil() as u64,
+            samples: sorted.len(),
+        })
+    }
+}
+
+/// Skew is only fitted once the window spans at least this long (1s)
+const MIN_SKEW_SPAN_US: u64 = 1_000_000;
+
+/// Least-squares line through (local time, offset) points. Stays flat until there are
+/// three points spanning `MIN_SKEW_SPAN_US`; shorter baselines can't show skew, so
+/// there is no slope until then.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct OffsetTrend {
+    base: u64,   // Earliest time; the sums work relative to it to stay well-conditioned
+    mean_x: f64, // Mean time, relative to `base`
+    mean_y: f64, // Mean offset
+    slope: Option<f64>, // Offset change per micro of local time, once it can be fitted
+}
+
+impl OffsetTrend {
+    pub(crate) fn fit(points: &[(u64, i64)]) -> Option<Self> {
+        let base = points.iter().map(|(t, _)| *t).min()?;
+        let span = points.iter().map(|(t, _)| *t).max()? - base;
+        let n = points.len() as f64;
+        let mean_x = points.iter().map(|(t, _)| (t - base) as f64).sum::<f64>() / n;
+        let mean_y = points.iter().map(|(_, o)| *o as f64).sum::<f64>() / n;
+
+        let slope = if points.len() >= 3 && span >= MIN_SKEW_SPAN_US {
+            let (mut sxy, mut sxx) = (0.0, 0.0);
+            for (t, o) in points {
+                let dx = (t - base) as f64 - mean_x;
+                sxy += dx * (*o as f64 - mean_y);
+                sxx += dx * dx;
+            }
+            Some(sxy / sxx)
+        } else {
+            None
+        };
+        Some(Self { base, mean_x, mean_y, slope })
+    }
+
+    /// Local time the line is centred on
+    pub(crate) fn mean_time(&self) -> u64 {
+        self.base + self.mean_x.round() as u64
+    }
+
+    /// Fitted offset at local time `t` (the mean offset while there is no slope)
+    pub(crate) fn at(&self, t: u64) -> f64 {
+        self.mean_y + self.slope.unwrap_or(0.0) * (t as f64 - self.base as f64 - self.mean_x)
+    }
+}
+
+/// Linear model of the server clock relative to the local clock:
+/// offset(local) = offset + skew_ppm * 1e-6 * (local - reference_time)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SkewEstimate {
+    pub offset: i64,         // Offset at reference_time (micros)
+    pub reference_time: u64, // Local time the fit is centred on
+    pub skew_ppm: f64,       // Server clock rate relative to local, in ppm
+}
+
+impl SkewEstimate {
+    /// Offset extrapolated to the given local time
+    pub fn offset_at(&self, local_micros: u64) -> i64 {
+        let dt = local_micros as f64 - self.reference_time as f64;
+        self.offset + (dt * self.skew_ppm / 1_000_000.0).round() as i64
+    }
+
+    /// Server time corresponding to the given local time
+    pub fn server_time_at(&self, local_micros: u64) -> u64 {
+        (local_micros as i64 + self.offset_at(local_micros)).max(0) as u64
+    }
+}
+
+/// Estimates clock frequency drift by fitting offset against local time
+/// (least squares) over a sliding window of samples. Lets clients
+/// extrapolate server time between syncs instead of holding a constant offset.
+#[derive(Debug, Clone)]
+pub struct SkewEstimator {
+    points: VecDeque<(u64, ClockOffset)>, // (local time of sample, sample)
+    window: usize,
+}
+
+impl Default for SkewEstimator {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW)
+    }
+}
+
+impl SkewEstimator {
+    pub const DEFAULT_WINDOW: usize = 32;
+
+    pub fn new(window: usize) -> Self {
+        let window = window.max(2);
+        Self {
+            points: VecDeque::with_capacity(window),
+            window,
+        }
+    }
+
+    /// Add a sample from NTP timestamps. The offset is attributed to the
+    /// midpoint of the exchange on the local clock.
+    pub fn add_timestamps(&mut self, t0: u64, t1: u64, t2: u64, t3: u64) -> ClockOffset {
+        let sample = ClockOffset::calculate(t0, t1, t2, t3);
+        self.add_sample(t0 / 2 + t3 / 2, sample);
+        sample
+    }
+
+    pub fn add_sample(&mut self, local_time: u64, sample: ClockOffset) {
+        if self.points.len() == self.window {
+            self.points.pop_front();
+        }
+        self.points.push_back((local_time, sample));
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.points.clear();
+    }
+
+    /// The fitted drift model, or None until the window spans long enough to show skew
+    pub fn estimate(&self) -> Option<SkewEstimate> {
+        let trend = OffsetTrend::fit(&trusted_offsets(&self.points))?;
+        Some(SkewEstimate {
+            offset: trend.mean_y.round() as i64,
+            reference_time: trend.mean_time(),
+            skew_ppm: trend.slope? * 1_000_000.0,
+        })
+    }
+
+    /// Server time at the given local time, extrapolated with the fitted skew
+    pub fn server_time_at(&self, local_micros: u64) -> Option<u64> {
+        self.estimate().map(|e| e.server_time_at(local_micros))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -58,4 +393,145 @@ mod tests {
         assert_eq!(result.rtt, 100);
         assert_eq!(result.offset, 500);
     }
+
+    fn sample(offset: i64, rtt: u64) -> ClockOffset {
+        ClockOffset { offset, rtt }
+    }
+
+    #[test]
+    fn test_filter_empty() {
+        let filter = ClockFilter::default();
+        assert!(filter.estimate().is_none());
+    }
+
+    #[test]
+    fn test_filter_rejects_high_rtt_outliers() {
+        let mut filter = ClockFilter::new(8);
+        for _ in 0..5 {
+            filter.add_sample(sample(500, 4_000));
+        }
+        // Congested samples with a skewed offset should be thrown out
+        filter.add_sample(sample(20_000, 80_000));
+        filter.add_sample(sample(-15_000, 60_000));
+
+        let est = filter.estimate().unwrap();
+        assert_eq!(est.offset, 500);
+        assert_eq!(est.rtt, 4_000);
+        assert_eq!(est.samples, 5);
+        assert_eq!(est.error_bound, 2_000);
+    }
+
+    #[test]
+    fn test_filter_prefers_low_rtt() {
+        let mut filter = ClockFilter::new(8);
+        filter.add_sample(sample(1_000, 5_000));
+        filter.add_sample(sample(1_400, 6_000));
+        filter.add_sample(sample(0, 3_000));
+
+        let est = filter.estimate().unwrap();
+        // Best sample carries the most weight
+        assert!(est.offset < 700, "offset {} should lean toward min-RTT sample", est.offset);
+        assert_eq!(est.rtt, 3_000);
+    }
+
+    #[test]
+    fn test_filter_window_slides() {
+        let mut filter = ClockFilter::new(3);
+        filter.add_sample(sample(-9_000, 1_000)); // Will be evicted
+        for _ in 0..3 {
+            filter.add_sample(sample(250, 4_000));
+        }
+        assert_eq!(filter.len(), 3);
+        assert_eq!(filter.estimate().unwrap().offset, 250);
+    }
+
+    #[test]
+    fn test_monotonic_clock_never_goes_back() {
+        let clock = MonotonicClock::new();
+        let mut last = clock.now_micros();
+        for _ in 0..1000 {
+            let now = clock.now_micros();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+
+    #[test]
+    fn test_fake_clock() {
+        let clock = FakeClock::new(1_000);
+        clock.advance(500);
+        assert_eq!(clock.now_micros(), 1_500);
+        clock.set(1_200); // Backwards: ignored
+        assert_eq!(clock.now_micros(), 1_500);
+        clock.set(9_000);
+        assert_eq!(clock.now_micros(), 9_000);
+    }
+
+    /// Server clock running `ppm` fast relative to the client, `offset` ahead at t=0
+    fn drifting_exchange(local_t0: u64, offset: i64, ppm: f64, up: u64, down: u64) -> (u64, u64, u64, u64) {
+        let server = |local: u64| (local as f64 * (1.0 + ppm / 1_000_000.0)) as i64 + offset;
+        let t1 = server(local_t0 + up) as u64;
+        let t2 = t1 + 50;
+        let t3 = local_t0 + up + 50 + down;
+        (local_t0, t1, t2, t3)
+    }
+
+    #[test]
+    fn test_skew_needs_span() {
+        let mut skew = SkewEstimator::default();
+        let (t0, t1, t2, t3) = drifting_exchange(1_000_000, 2_000, 50.0, 3_000, 3_000);
+        skew.add_timestamps(t0, t1, t2, t3);
+        assert_eq!(skew.estimate(), None);
+        assert_eq!(skew.server_time_at(2_000_000), None);
+    }
+
+    #[test]
+    fn test_zero_skew_is_still_a_fit() {
+        let mut skew = SkewEstimator::default();
+        for i in 0..10u64 {
+            let (t0, t1, t2, t3) = drifting_exchange(i * 1_000_000, 5_000, 0.0, 3_000, 3_000);
+            skew.add_timestamps(t0, t1, t2, t3);
+        }
+        let est = skew.estimate().unwrap();
+        assert_eq!(est.skew_ppm, 0.0);
+        assert_eq!(est.offset_at(60_000_000), 5_000);
+    }
+
+    #[test]
+    fn test_skew_recovers_drift() {
+        let mut skew = SkewEstimator::default();
+        // One sample every 2s for a minute, server 40ppm fast
+        for i in 0..30u64 {
+            let (t0, t1, t2, t3) = drifting_exchange(i * 2_000_000, -7_000, 40.0, 4_000, 4_000);
+            skew.add_timestamps(t0, t1, t2, t3);
+        }
+        let est = skew.estimate().unwrap();
+        assert!((est.skew_ppm - 40.0).abs() < 1.0, "skew {}ppm", est.skew_ppm);
+
+        // Extrapolate 30s past the last sample; constant offset would be 1.2ms off
+        let later = 90_000_000u64;
+        let truth = (later as f64 * (1.0 + 40.0 / 1_000_000.0)) as i64 - 7_000;
+        let predicted = skew.server_time_at(later).unwrap() as i64;
+        assert!((predicted - truth).abs() < 100, "error {}us", predicted - truth);
+    }
+
+    #[test]
+    fn test_skew_ignores_congested_samples() {
+        let mut skew = SkewEstimator::default();
+        let mut seed: u64 = 42;
+        for i in 0..30u64 {
+            // Deterministic jitter 0..1ms
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            let jitter = (seed >> 33) % 1_000;
+            let (up, down) = if i % 7 == 3 {
+                (60_000, 2_000) // Congested, asymmetric: would bias the fit
+            } else {
+                (3_000 + jitter, 3_000 + jitter)
+            };
+            let (t0, t1, t2, t3) = drifting_exchange(i * 2_000_000, 10_000, -25.0, up, down);
+            skew.add_timestamps(t0, t1, t2, t3);
+        }
+        let est = skew.estimate().unwrap();
+        assert!((est.skew_ppm + 25.0).abs() < 2.0, "skew {}ppm", est.skew_ppm);
+    }
 }
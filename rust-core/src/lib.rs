@@ -1,3 +1,6 @@
 pub mod messages;
 pub mod clock;
 pub mod pid;
+pub mod udp_time;
+pub mod quality;
+pub mod track;
@@ -1,53 +1,411 @@
+use crate::track::Track;
 use serde::{Deserialize, Serialize};
 
+/// Wire protocol version. Bump on any change to message layout:
+/// bincode is position-sensitive, so even appending a variant breaks old decoders.
+pub const PROTOCOL_VERSION: u16 = 12;
+/// The only older protocol the server still speaks: the pre-versioning v1 layout.
+/// It is not a lower bound; versions between it and `PROTOCOL_VERSION` have no
+/// downgrade path and are refused.
+pub const LEGACY_PROTOCOL_VERSION: u16 = v1::VERSION;
 
+/// Versions the server can actually encode: exactly `PROTOCOL_VERSION` or `LEGACY_PROTOCOL_VERSION`
+pub fn is_supported_version(version: u16) -> bool {
+    version == PROTOCOL_VERSION || version == LEGACY_PROTOCOL_VERSION
+}
+
+/// Correlates a request with its Ack/Error. Clients number from 1;
+/// 0 means the message wasn't tied to a request.
+pub type RequestId = u32;
+pub const NO_REQUEST: RequestId = 0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    MalformedMessage, // Frame could not be decoded
+    InvalidRequest,   // Decoded, but not valid in the current state
+    NotFound,         // Referenced track/file doesn't exist
+    Internal,
+    Unauthorized,     // Missing or wrong room PIN / control token
+    Forbidden,        // Sender's role isn't allowed to do this
+}
+
+/// What a member may do in its room. The first member of a room is its Host.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Host,
+    CoHost,   // Can control playback, but not hand out roles
+    Listener, // Playback only
+}
+
+impl Role {
+    pub fn can_control(self) -> bool {
+        matches!(self, Role::Host | Role::CoHost)
+    }
+
+    pub fn can_assign_roles(self) -> bool {
+        self == Role::Host
+    }
+}
+
+/// Capability bits exchanged in Join/Welcome. Unknown bits are ignored.
+pub mod capability {
+    /// Can play the host's file from the relative "stream" URL
+    pub const HOSTED_STREAM: u32 = 1 << 0;
+    /// Can play the host's live capture ("live" URL)
+    pub const LIVE_STREAM: u32 = 1 << 1;
+    /// Corrects playback drift locally (speed adjustment)
+    pub const DRIFT_CORRECTION: u32 = 1 << 2;
+
+    /// Everything this build of the server supports
+    pub const SERVER: u32 = HOSTED_STREAM | LIVE_STREAM | DRIFT_CORRECTION;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientKind {
+    Android,
+    Cli,
+    Dashboard,
+    Other,
+}
+
+impl ClientKind {
+    /// Best guess for v1 clients, which only sent a device_id
+    pub fn from_device_id(device_id: &str) -> Self {
+        if device_id.starts_with("ANDROID-") {
+            ClientKind::Android
+        } else if device_id.starts_with("CLI-") {
+            ClientKind::Cli
+        } else if device_id == "DASHBOARD" {
+            ClientKind::Dashboard
+        } else {
+            ClientKind::Other
+        }
+    }
+}
+
+/// How far a client's clock sync has come, as classified by `quality::SyncMonitor`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SyncState {
+    #[default]
+    Unsynced,   // No usable samples, or none recently
+    Converging, // Has an estimate, but it isn't tight enough to play on yet
+    Locked,     // Error bound and offset spread within playback tolerance
+}
+
+/// Why a session ended, as recorded by the server
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisconnectReason {
+    Closed,         // Client sent a Close frame
+    ConnectionLost, // Socket errored or ended without a Close
+    TimedOut,       // Nothing heard (not even a pong) within the idle timeout
+    SendFailed,     // Writing to the client failed or stalled
+    Rejected,       // Never admitted (e.g. wrong PIN)
+}
+
+/// One room member as shown in the roster (`PeerList`/`PeerUpdate`, GET /peers)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PeerInfo {
+    pub session_id: String,
+    pub device_id: String,
+    pub addr: String,
+    pub client_kind: ClientKind,
+    pub role: Role,
+    pub connected_at: u64, // Server time (us)
+    pub last_seen: u64,    // Server time of the last message from this device (us)
+    // Last Telemetry report
+    pub offset: i64,
+    pub rtt: u64,
+    pub drift: i64,
+    pub status: String,
+    pub sync_state: SyncState,
+    pub drift_history: Vec<i64>, // Recent drift reports, oldest first
+    pub online: bool, // False while the server holds the session open for resumption
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerMessage {
-    Welcome { session_id: String },
-    TimeResponse { 
-        t0: u64, 
+    Welcome {
+        session_id: String,
+        protocol_version: u16, // Negotiated version for this session
+        capabilities: u32,     // Intersection of client and server capabilities
+        role: Role,
+        control_token: Option<String>, // Bearer token for REST /control (Host only)
+        resume_token: String, // Present in a later Join to get this session back after a drop
+        resumed: bool,        // True if this connection reattached to an existing session
+        udp_time_port: Option<u16>, // Port answering `udp_time` probes, if the server runs one
+    },
+    TimeResponse {
+        t0: u64,
         t1: u64, // Server receive time
         t2: u64, // Server transmit time
-        seq: u8 
+        seq: u8
     },
     PlayCommand {
         track_url: String, // If playing a new track
         start_at_server_time: u64, // Future timestamp for sync start
         start_at_position_ms: u64, // Where in the track to start (e.g. 0 for new, X for resume)
         server_time_at_broadcast: u64,
+        track: Track, // Metadata for track_url, as far as the server knows it
     },
     PauseCommand {
         server_time: u64, // When the pause happened
     },
-    SyncRequired // Force client to re-sync
+    SyncRequired, // Force client to re-sync
+    Incompatible { // Handshake refused; the server closes the socket after this
+        reason: String,
+        server_version: u16,
+        legacy_version: u16, // The one other version accepted; nothing in between is
+    },
+    Ack { id: RequestId }, // Request was applied
+    Error {
+        id: RequestId, // NO_REQUEST if not caused by a specific request
+        code: ErrorCode,
+        message: String,
+    },
+    RoleChanged { // Broadcast to the room when a member's role changes
+        session_id: String,
+        role: Role,
+    },
+    PeerList { peers: Vec<PeerInfo> }, // Whole roster; broadcast when someone joins or leaves
+    PeerUpdate { peer: PeerInfo },     // One member's telemetry or role changed
+    PeerLeft { session_id: String, reason: DisconnectReason }, // Followed by a fresh PeerList
+    QueueUpdate { // Whole queue; broadcast on every change and sent on joining
+        entries: Vec<QueueEntry>,
+        current_entry: Option<u32>, // Entry playing (or paused), if any
+        repeat: RepeatMode,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ClientMessage {
-    Join { device_id: String },
+    Join {
+        device_id: String,
+        // Defaults let pre-versioning JSON clients (dashboard) through as v1
+        #[serde(default = "v1::version")]
+        protocol_version: u16,
+        #[serde(default = "v1::client_kind")]
+        client_kind: ClientKind,
+        #[serde(default)]
+        capabilities: u32,
+        #[serde(default)]
+        pin: Option<String>, // Required if the room has a PIN; the room's creator sets it
+        #[serde(default)]
+        resume_token: Option<String>, // From a previous Welcome, to reattach to that session
+    },
     TimeRequest { t0: u64, seq: u8 }, // t0 = client send time
-    Telemetry { 
-        rtt: u64,
-        offset: i64,
-        drift: i64,
-        status: String
+    Telemetry {
+        rtt: u64,    // us
+        offset: i64, // Clock offset to the server (us)
+        drift: i64,  // Playback drift (ms)
+        status: String,
+        #[serde(default)]
+        sync_state: SyncState, // Hosts hold playback until every speaker is Locked
     },
     PlayRequest { // Request to play a URL generally
+        id: RequestId,
         track_url: String,
         delay_ms: u64,
     },
     CommandRequest { // Control commands (Play/Pause/Seek)
+        id: RequestId,
         cmd: ControlCommand
-    }
+    },
+    SetRole { // Host only: promote/demote another member
+        id: RequestId,
+        session_id: String,
+        role: Role,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ControlCommand {
-    Play { 
+    Play {
         start_at_ms: u64, // Position in track
-        delay_ms: u64 
+        delay_ms: u64
     },
     Pause,
-    Seek { position_ms: u64 }
+    Seek { position_ms: u64 },
+    // Queue: changes are broadcast as QueueUpdate
+    QueueAdd {
+        track_url: String,
+        duration_ms: Option<u64>, // Needed for the server to move on by itself when it ends
+    },
+    QueueRemove { entry_id: u32 },
+    QueueMove { entry_id: u32, to_index: u32 },
+    Next,
+    Previous,
+    Shuffle, // Reorders everything after the current entry
+    SetRepeat { mode: RepeatMode },
+}
+
+/// One track in a room's play queue
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QueueEntry {
+    pub entry_id: u32, // Stable while the entry is queued, even as it moves
+    pub track_url: String,
+    pub track: Track,
+}
+
+/// What happens when the queue reaches the end of a track
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RepeatMode {
+    #[default]
+    Off, // Stop after the last entry
+    All, // Wrap around to the first
+    One, // Replay the current entry
+}
+
+/// Protocol v1 wire layout (pre-versioning), kept so builds still in the field
+/// can be downgraded instead of silently failing to decode.
+pub mod v1 {
+    use super::ClientKind;
+    use serde::{Deserialize, Serialize};
+
+    pub const VERSION: u16 = 1;
+
+    pub(super) fn version() -> u16 {
+        VERSION
+    }
+
+    pub(super) fn client_kind() -> ClientKind {
+        ClientKind::Other
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub enum ServerMessage {
+        Welcome { session_id: String },
+        TimeResponse { t0: u64, t1: u64, t2: u64, seq: u8 },
+        PlayCommand {
+            track_url: String,
+            start_at_server_time: u64,
+            start_at_position_ms: u64,
+            server_time_at_broadcast: u64,
+        },
+        PauseCommand { server_time: u64 },
+        SyncRequired,
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub enum ClientMessage {
+        Join { device_id: String },
+        TimeRequest { t0: u64, seq: u8 },
+        Telemetry { rtt: u64, offset: i64, drift: i64, status: String },
+        PlayRequest { track_url: String, delay_ms: u64 },
+        CommandRequest { cmd: ControlCommand },
+    }
+
+    #[derive(Serialize, Deserialize, Debug, Clone)]
+    pub enum ControlCommand {
+        Play { start_at_ms: u64, delay_ms: u64 },
+        Pause,
+        Seek { position_ms: u64 },
+    }
+
+    impl From<ControlCommand> for super::ControlCommand {
+        fn from(cmd: ControlCommand) -> Self {
+            match cmd {
+                ControlCommand::Play { start_at_ms, delay_ms } => super::ControlCommand::Play { start_at_ms, delay_ms },
+                ControlCommand::Pause => super::ControlCommand::Pause,
+                ControlCommand::Seek { position_ms } => super::ControlCommand::Seek { position_ms },
+            }
+        }
+    }
+
+    impl From<ClientMessage> for super::ClientMessage {
+        fn from(msg: ClientMessage) -> Self {
+            match msg {
+                ClientMessage::Join { device_id } => super::ClientMessage::Join {
+                    client_kind: ClientKind::from_device_id(&device_id),
+                    device_id,
+                    protocol_version: VERSION,
+                    capabilities: super::capability::HOSTED_STREAM | super::capability::LIVE_STREAM,
+                    pin: None,
+                    resume_token: None,
+                },
+                ClientMessage::TimeRequest { t0, seq } => super::ClientMessage::TimeRequest { t0, seq },
+                ClientMessage::Telemetry { rtt, offset, drift, status } => {
+                    super::ClientMessage::Telemetry { rtt, offset, drift, status, sync_state: super::SyncState::Unsynced }
+                }
+                ClientMessage::PlayRequest { track_url, delay_ms } => {
+                    super::ClientMessage::PlayRequest { id: super::NO_REQUEST, track_url, delay_ms }
+                }
+                ClientMessage::CommandRequest { cmd } => {
+                    super::ClientMessage::CommandRequest { id: super::NO_REQUEST, cmd: cmd.into() }
+                }
+            }
+        }
+    }
+
+    impl super::ServerMessage {
+        /// Downgrade for a v1 client. None if v1 has no equivalent.
+        pub fn to_v1(&self) -> Option<ServerMessage> {
+            match self.clone() {
+                super::ServerMessage::Welcome { session_id, .. } => Some(ServerMessage::Welcome { session_id }),
+                super::ServerMessage::TimeResponse { t0, t1, t2, seq } => {
+                    Some(ServerMessage::TimeResponse { t0, t1, t2, seq })
+                }
+                super::ServerMessage::PlayCommand {
+                    track_url,
+                    start_at_server_time,
+                    start_at_position_ms,
+                    server_time_at_broadcast,
+                    ..
+                } => Some(ServerMessage::PlayCommand {
+                    track_url,
+                    start_at_server_time,
+                    start_at_position_ms,
+                    server_time_at_broadcast,
+                }),
+                super::ServerMessage::PauseCommand { server_time } => Some(ServerMessage::PauseCommand { server_time }),
+                super::ServerMessage::SyncRequired => Some(ServerMessage::SyncRequired),
+                super::ServerMessage::Incompatible { .. }
+                | super::ServerMessage::Ack { .. }
+                | super::ServerMessage::Error { .. }
+                | super::ServerMessage::RoleChanged { .. }
+                | super::ServerMessage::PeerList { .. }
+                | super::ServerMessage::PeerUpdate { .. }
+                | super::ServerMessage::PeerLeft { .. }
+                | super::ServerMessage::QueueUpdate { .. } => None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1_join_decodes_and_upgrades() {
+        let old = v1::ClientMessage::Join { device_id: "ANDROID-1234".into() };
+        let bytes = bincode::serialize(&old).unwrap();
+
+        // Current layout can't read it...
+        assert!(bincode::deserialize::<ClientMessage>(&bytes).is_err());
+        // ...but the v1 layout can, and upgrades cleanly
+        let upgraded: ClientMessage = bincode::deserialize::<v1::ClientMessage>(&bytes).unwrap().into();
+        match upgraded {
+            ClientMessage::Join { protocol_version, client_kind, .. } => {
+                assert_eq!(protocol_version, v1::VERSION);
+                assert_eq!(client_kind, ClientKind::Android);
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_v1_downgrade_keeps_shared_layout() {
+        let msg = ServerMessage::PauseCommand { server_time: 42 };
+        let v1_bytes = bincode::serialize(&msg.to_v1().unwrap()).unwrap();
+        assert_eq!(v1_bytes, bincode::serialize(&msg).unwrap());
+    }
+
+    #[test]
+    fn test_json_join_without_version_is_v1() {
+        let msg: ClientMessage = serde_json::from_str(r#"{"Join":{"device_id":"DASHBOARD"}}"#).unwrap();
+        match msg {
+            ClientMessage::Join { protocol_version, .. } => assert_eq!(protocol_version, v1::VERSION),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
 }
@@ -36,8 +36,7 @@ impl PidController {
         let derivative = if dt > 0.0 { (error - self.last_error) / dt } else { 0.0 };
         self.last_error = error;
         
-        let output = (self.kp * error) + (self.ki * self.integral) + (self.kd * derivative);
-        output
+        (self.kp * error) + (self.ki * self.integral) + (self.kd * derivative)
     }
 }
 
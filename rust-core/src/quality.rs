@@ -0,0 +1,233 @@
+use crate::clock::{trusted_offsets, ClockOffset, OffsetTrend};
+use crate::messages::SyncState;
+use std::collections::VecDeque;
+
+/// When a sample window counts as locked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockCriteria {
+    pub max_error_bound: u64, // us
+    pub min_samples: usize,   // Good samples needed in the window
+    pub max_sample_age: u64,  // us without a good sample before falling back to Unsynced
+}
+
+impl Default for LockCriteria {
+    fn default() -> Self {
+        Self {
+            max_error_bound: 5_000,
+            min_samples: 4,
+            max_sample_age: 60_000_000,
+        }
+    }
+}
+
+/// Quality indicators for a window of clock samples (all times in micros)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncMetrics {
+    pub rtt_min: u64,
+    pub rtt_median: u64,
+    pub rtt_jitter: u64,    // Mean absolute deviation of RTT from the median
+    pub offset_stddev: u64, // Spread of the good samples' offsets around their trend
+    pub error_bound: u64,   // Offset is accurate to +/- this much
+    pub since_good_sample: Option<u64>, // None if the window is empty
+    pub samples: usize,      // In the window
+    pub good_samples: usize, // Survived outlier rejection
+    pub state: SyncState,
+}
+
+/// Keeps the recent samples of a clock sync and rates how trustworthy the result is.
+/// Outliers are rejected the same way `ClockFilter` does, and offsets are detrended
+/// over long windows so clock skew doesn't read as noise.
+#[derive(Debug, Clone)]
+pub struct SyncMonitor {
+    points: VecDeque<(u64, ClockOffset)>, // (local time of sample, sample)
+    window: usize,
+    criteria: LockCriteria,
+}
+
+impl Default for SyncMonitor {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_WINDOW, LockCriteria::default())
+    }
+}
+
+impl SyncMonitor {
+    pub const DEFAULT_WINDOW: usize = 16;
+
+    pub fn new(window: usize, criteria: LockCriteria) -> Self {
+        let window = window.max(1);
+        Self {
+            points: VecDeque::with_capacity(window),
+            window,
+            criteria,
+        }
+    }
+
+    pub fn criteria(&self) -> &LockCriteria {
+        &self.criteria
+    }
+
+    /// Add a sample from NTP timestamps, attributed to the midpoint of the exchange
+    pub fn add_timestamps(&mut self, t0: u64, t1: u64, t2: u64, t3: u64) -> ClockOffset {
+        let sample = ClockOffset::calculate(t0, t1, t2, t3);
+        self.add_sample(t0 / 2 + t3 / 2, sample);
+        sample
+    }
+
+    pub fn add_sample(&mut self, local_time: u64, sample: ClockOffset) {
+        if self.points.len() == self.window {
+            self.points.pop_front();
+        }
+        self.points.push_back((local_time, sample));
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    pub fn reset(&mut self) {
+        self.points.clear();
+    }
+
+    /// Metrics for the current window as of local time `now`
+    pub fn metrics(&self, now: u64) -> SyncMetrics {
+        let mut rtts: Vec<u64> = self.points.iter().map(|(_, s)| s.rtt).collect();
+        rtts.sort_unstable();
+        let Some(&rtt_min) = rtts.first() else {
+            return SyncMetrics::default();
+        };
+        let rtt_median = rtts[rtts.len() / 2];
+        let rtt_jitter = rtts.iter().map(|r| r.abs_diff(rtt_median)).sum::<u64>() / rtts.len() as u64;
+
+        let good = trusted_offsets(&self.points);
+        let last_good = good.iter().map(|(t, _)| *t).max().unwrap_or(0);
+        let since_good_sample = now.saturating_sub(last_good);
+        let offset_stddev = residual_stddev(&good).ceil() as u64;
+        let error_bound = rtt_min / 2 + offset_stddev;
+
+        let state = if since_good_sample > self.criteria.max_sample_age {
+            SyncState::Unsynced
+        } else if good.len() >= self.criteria.min_samples && error_bound <= self.criteria.max_error_bound {
+            SyncState::Locked
+        } else {
+            SyncState::Converging
+        };
+
+        SyncMetrics {
+            rtt_min,
+            rtt_median,
+            rtt_jitter,
+            offset_stddev,
+            error_bound,
+            since_good_sample: Some(since_good_sample),
+            samples: self.points.len(),
+            good_samples: good.len(),
+            state,
+        }
+    }
+}
+
+/// Standard deviation of the offsets around the trend `SkewEstimator` would fit to them
+fn residual_stddev(points: &[(u64, i64)]) -> f64 {
+    let Some(trend) = OffsetTrend::fit(points) else {
+        return 0.0;
+    };
+    let sq_sum: f64 = points
+        .iter()
+        .map(|&(t, o)| {
+            let d = o as f64 - trend.at(t);
+            d * d
+        })
+        .sum();
+    (sq_sum / points.len() as f64).sqrt()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(offset: i64, rtt: u64) -> ClockOffset {
+        ClockOffset { offset, rtt }
+    }
+
+    #[test]
+    fn empty_window_is_unsynced() {
+        let monitor = SyncMonitor::default();
+        let metrics = monitor.metrics(1_000_000);
+        assert_eq!(metrics.state, SyncState::Unsynced);
+        assert_eq!(metrics.since_good_sample, None);
+    }
+
+    #[test]
+    fn locks_once_enough_tight_samples_arrive() {
+        let mut monitor = SyncMonitor::default();
+        monitor.add_sample(1_000_000, sample(500, 3_000));
+        monitor.add_sample(1_050_000, sample(600, 3_200));
+        assert_eq!(monitor.metrics(1_100_000).state, SyncState::Converging);
+
+        monitor.add_sample(1_100_000, sample(400, 2_800));
+        monitor.add_sample(1_150_000, sample(500, 3_000));
+        let metrics = monitor.metrics(1_200_000);
+        assert_eq!(metrics.state, SyncState::Locked);
+        assert_eq!(metrics.rtt_min, 2_800);
+        assert_eq!(metrics.rtt_median, 3_000);
+        assert_eq!(metrics.rtt_jitter, 100);
+        assert_eq!(metrics.offset_stddev, 71);
+        assert_eq!(metrics.error_bound, 1_400 + 71);
+        assert_eq!(metrics.since_good_sample, Some(50_000));
+    }
+
+    #[test]
+    fn outliers_count_toward_jitter_but_not_offset_spread() {
+        let mut monitor = SyncMonitor::default();
+        for i in 0..4 {
+            monitor.add_sample(i * 100_000, sample(1_000, 2_000));
+        }
+        // Congested and asymmetric: wild offset, but rejected
+        monitor.add_sample(400_000, sample(40_000, 90_000));
+        let metrics = monitor.metrics(400_000);
+        assert_eq!(metrics.samples, 5);
+        assert_eq!(metrics.good_samples, 4);
+        assert_eq!(metrics.offset_stddev, 0);
+        assert_eq!(metrics.since_good_sample, Some(100_000));
+        assert!(metrics.rtt_jitter > 0);
+        assert_eq!(metrics.state, SyncState::Locked);
+    }
+
+    #[test]
+    fn noisy_offsets_stay_converging() {
+        let mut monitor = SyncMonitor::default();
+        for (i, offset) in [0, 9_000, -8_000, 7_000, -9_000, 8_000].into_iter().enumerate() {
+            monitor.add_sample(i as u64 * 100_000, sample(offset, 3_000));
+        }
+        let metrics = monitor.metrics(600_000);
+        assert!(metrics.offset_stddev > 5_000, "stddev {}", metrics.offset_stddev);
+        assert_eq!(metrics.state, SyncState::Converging);
+    }
+
+    #[test]
+    fn skew_is_not_mistaken_for_noise() {
+        let mut monitor = SyncMonitor::default();
+        // 50ppm over 16 samples 20s apart: 15ms of trend, no noise
+        for i in 0..16u64 {
+            let t = i * 20_000_000;
+            monitor.add_sample(t, sample((t as f64 * 50e-6) as i64, 2_000));
+        }
+        let metrics = monitor.metrics(300_000_000);
+        assert!(metrics.offset_stddev <= 1, "stddev {}", metrics.offset_stddev);
+        assert_eq!(metrics.state, SyncState::Locked);
+    }
+
+    #[test]
+    fn goes_unsynced_without_fresh_samples() {
+        let mut monitor = SyncMonitor::default();
+        for i in 0..4 {
+            monitor.add_sample(i * 100_000, sample(0, 2_000));
+        }
+        assert_eq!(monitor.metrics(30_000_000).state, SyncState::Locked);
+        assert_eq!(monitor.metrics(61_000_000).state, SyncState::Unsynced);
+    }
+}
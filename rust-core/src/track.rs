@@ -0,0 +1,116 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a track's audio comes from
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceKind {
+    #[default]
+    Url,      // Direct link to an audio file
+    Hosted,   // The room's hosted file, served from /stream
+    Live,     // The host's live capture, served from /stream/live
+    Resolved, // A page URL (e.g. a video site) resolved to its audio stream
+}
+
+/// What the server knows about a track besides where to fetch it
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Track {
+    pub id: String, // Stable for the same source, so it can key caches
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration_ms: Option<u64>,
+    pub mime_type: Option<String>,
+    pub source: SourceKind,
+    pub artwork_url: Option<String>,
+}
+
+impl Track {
+    /// Best guess from the URL alone: title from the file name, MIME type from the extension
+    pub fn from_url(url: &str, source: SourceKind) -> Self {
+        let path = url.split(['?', '#']).next().unwrap_or(url);
+        let file_name = path.rsplit(['/', '\\']).next().filter(|name| !name.is_empty());
+        Self {
+            id: track_id(url),
+            title: file_name.map(|name| percent_decode(name.rsplit_once('.').map_or(name, |(stem, _)| stem))),
+            mime_type: file_name.and_then(mime_type_for),
+            source,
+            ..Self::default()
+        }
+    }
+
+    /// Position clamped to the end of the track, if its length is known
+    pub fn clamp_position(&self, position_ms: u64) -> u64 {
+        self.duration_ms.map_or(position_ms, |d| position_ms.min(d))
+    }
+}
+
+/// Short stable id for a source URL or path (64-bit FNV-1a, hex)
+pub fn track_id(source: &str) -> String {
+    let hash = source.bytes().fold(0xcbf2_9ce4_8422_2325u64, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
+
+/// Undo %XX escapes; anything malformed is kept as it was
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// MIME type for an audio file name, by extension
+pub fn mime_type_for(file_name: &str) -> Option<String> {
+    let (_, ext) = file_name.rsplit_once('.')?;
+    let mime = match ext.to_ascii_lowercase().as_str() {
+        "mp3" => "audio/mpeg",
+        "m4a" | "mp4" | "aac" => "audio/mp4",
+        "ogg" | "oga" => "audio/ogg",
+        "opus" => "audio/opus",
+        "flac" => "audio/flac",
+        "wav" => "audio/wav",
+        "webm" | "weba" => "audio/webm",
+        _ => return None,
+    };
+    Some(mime.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guesses_title_and_type_from_url() {
+        let track = Track::from_url("https://cdn.example.com/music/Song%20One.MP3?sig=abc", SourceKind::Url);
+        assert_eq!(track.title.as_deref(), Some("Song One"));
+        assert_eq!(track.mime_type.as_deref(), Some("audio/mpeg"));
+        assert_eq!(track.duration_ms, None);
+        assert_eq!(track.id, Track::from_url("https://cdn.example.com/music/Song%20One.MP3?sig=abc", SourceKind::Url).id);
+        assert_ne!(track.id, track_id("https://cdn.example.com/music/other.mp3"));
+
+        let page = Track::from_url("https://video.example.com/", SourceKind::Resolved);
+        assert_eq!(page.title, None);
+        assert_eq!(page.mime_type, None);
+    }
+
+    #[test]
+    fn clamps_to_known_duration() {
+        let mut track = Track::from_url("/sdcard/a.flac", SourceKind::Hosted);
+        assert_eq!(track.clamp_position(90_000), 90_000);
+        track.duration_ms = Some(60_000);
+        assert_eq!(track.clamp_position(90_000), 60_000);
+        assert_eq!(track.clamp_position(1_000), 1_000);
+    }
+}
@@ -0,0 +1,105 @@
+//! Clock probes over UDP: the same t0/t1/t2 exchange as `TimeRequest`/`TimeResponse`,
+//! without TCP's head-of-line blocking and delayed ACKs inflating the RTT.
+//!
+//! Both directions use one fixed 32-byte little-endian packet:
+//! magic (4) | version (1) | kind (1) | seq (1) | reserved (1) | t0 (8) | t1 (8) | t2 (8).
+//! Probes carry zero t1/t2 and are as long as replies, so the server never
+//! sends back more than it received.
+
+pub const MAGIC: [u8; 4] = *b"SSTP";
+pub const VERSION: u8 = 1;
+pub const PACKET_LEN: usize = 32;
+
+const KIND_PROBE: u8 = 0;
+const KIND_REPLY: u8 = 1;
+
+/// Client -> server: "what time is it?"
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeProbe {
+    pub t0: u64, // Client send time
+    pub seq: u8,
+}
+
+/// Server -> client, stamped like `ServerMessage::TimeResponse`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeReply {
+    pub t0: u64,
+    pub t1: u64, // Server receive time
+    pub t2: u64, // Server transmit time
+    pub seq: u8,
+}
+
+fn encode(kind: u8, seq: u8, t0: u64, t1: u64, t2: u64) -> [u8; PACKET_LEN] {
+    let mut buf = [0u8; PACKET_LEN];
+    buf[..4].copy_from_slice(&MAGIC);
+    buf[4] = VERSION;
+    buf[5] = kind;
+    buf[6] = seq;
+    buf[8..16].copy_from_slice(&t0.to_le_bytes());
+    buf[16..24].copy_from_slice(&t1.to_le_bytes());
+    buf[24..32].copy_from_slice(&t2.to_le_bytes());
+    buf
+}
+
+/// (seq, t0, t1, t2) if `buf` is a well-formed packet of the given kind
+fn decode(buf: &[u8], kind: u8) -> Option<(u8, u64, u64, u64)> {
+    if buf.len() != PACKET_LEN || buf[..4] != MAGIC || buf[4] != VERSION || buf[5] != kind {
+        return None;
+    }
+    let word = |at: usize| u64::from_le_bytes(buf[at..at + 8].try_into().unwrap());
+    Some((buf[6], word(8), word(16), word(24)))
+}
+
+impl TimeProbe {
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        encode(KIND_PROBE, self.seq, self.t0, 0, 0)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        decode(buf, KIND_PROBE).map(|(seq, t0, _, _)| Self { t0, seq })
+    }
+
+    pub fn reply(&self, t1: u64, t2: u64) -> TimeReply {
+        TimeReply { t0: self.t0, t1, t2, seq: self.seq }
+    }
+}
+
+impl TimeReply {
+    pub fn encode(&self) -> [u8; PACKET_LEN] {
+        encode(KIND_REPLY, self.seq, self.t0, self.t1, self.t2)
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        decode(buf, KIND_REPLY).map(|(seq, t0, t1, t2)| Self { t0, t1, t2, seq })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn probe_and_reply_round_trip() {
+        let probe = TimeProbe { t0: 1_700_000_000_000_001, seq: 200 };
+        let bytes = probe.encode();
+        assert_eq!(TimeProbe::decode(&bytes), Some(probe));
+
+        let reply = probe.reply(1_700_000_000_005_000, 1_700_000_000_005_020);
+        assert_eq!(TimeReply::decode(&reply.encode()), Some(reply));
+        assert_eq!(bytes.len(), reply.encode().len());
+    }
+
+    #[test]
+    fn rejects_foreign_and_mismatched_packets() {
+        let probe = TimeProbe { t0: 7, seq: 1 }.encode();
+        // A probe is not a reply, and vice versa
+        assert_eq!(TimeReply::decode(&probe), None);
+        assert_eq!(TimeProbe::decode(&TimeProbe { t0: 7, seq: 1 }.reply(8, 9).encode()), None);
+        // Short, padded or stray datagrams
+        assert_eq!(TimeProbe::decode(&probe[..16]), None);
+        assert_eq!(TimeProbe::decode(&[probe.as_slice(), &[0]].concat()), None);
+        let mut other = probe;
+        other[0] = b'X';
+        assert_eq!(TimeProbe::decode(&other), None);
+    }
+}
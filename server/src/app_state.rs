@@ -1,55 +1,642 @@
 use dashmap::DashMap;
-use std::sync::{Arc, atomic::AtomicU64, RwLock}; // Added RwLock
-use tokio::sync::broadcast;
-use rust_core::messages::ServerMessage;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU16, AtomicU64, Ordering}, Mutex, RwLock}; // Added RwLock
+use tokio::sync::{broadcast, Notify};
+use rust_core::clock::{Clock, MonotonicClock};
+use crate::media::{MediaConfig, MediaProxy};
+use crate::queue::PlayQueue;
+use crate::resolve::Resolver;
+use rust_core::track::Track;
+use rust_core::messages::{ClientKind, DisconnectReason, PeerInfo, Role, ServerMessage, SyncState};
 
 pub type SharedState = Arc<AppState>;
 
+/// Room used when a client doesn't ask for one. Never cleaned up, so the
+/// embedded Android host and pre-rooms clients keep working.
+pub const DEFAULT_ROOM: &str = "default";
+
+/// Departures kept per room for GET /peers/departed
+pub const DEPARTURE_LOG_LEN: usize = 64;
+
+/// How the server decides a silent client is gone
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration, // How often to ping each client
+    pub timeout: Duration,  // Evict after this long without any frame (pongs count)
+    pub resume_grace: Duration, // How long a dropped session can be resumed (zero disables)
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+            resume_grace: Duration::from_secs(30),
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Defaults, overridden by SONICSYNC_PING_INTERVAL_MS / SONICSYNC_IDLE_TIMEOUT_MS /
+    /// SONICSYNC_RESUME_GRACE_MS
+    pub fn from_env() -> Self {
+        let ms = |name: &str| std::env::var(name).ok()?.parse().ok().map(Duration::from_millis);
+        let default = Self::default();
+        Self {
+            interval: ms("SONICSYNC_PING_INTERVAL_MS").unwrap_or(default.interval),
+            timeout: ms("SONICSYNC_IDLE_TIMEOUT_MS").unwrap_or(default.timeout),
+            resume_grace: ms("SONICSYNC_RESUME_GRACE_MS").unwrap_or(default.resume_grace),
+        }
+    }
+}
+
+/// When a peer's Telemetry means its clock sync has gone bad and it should resync
+#[derive(Debug, Clone, Copy)]
+pub struct SyncTolerance {
+    pub max_drift_ms: i64,       // Playback drift beyond this (either way)
+    pub max_offset_step_us: u64, // Offset moving this far between two reports
+    pub cooldown: Duration,      // Minimum gap between SyncRequired sent to the same peer
+}
+
+impl Default for SyncTolerance {
+    fn default() -> Self {
+        Self {
+            max_drift_ms: 40,
+            max_offset_step_us: 5_000,
+            cooldown: Duration::from_secs(10),
+        }
+    }
+}
+
+impl SyncTolerance {
+    /// Defaults, overridden by SONICSYNC_MAX_DRIFT_MS / SONICSYNC_MAX_OFFSET_STEP_US /
+    /// SONICSYNC_RESYNC_COOLDOWN_MS
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok()?.parse::<u64>().ok();
+        let default = Self::default();
+        Self {
+            max_drift_ms: var("SONICSYNC_MAX_DRIFT_MS").map_or(default.max_drift_ms, |ms| ms as i64),
+            max_offset_step_us: var("SONICSYNC_MAX_OFFSET_STEP_US").unwrap_or(default.max_offset_step_us),
+            cooldown: var("SONICSYNC_RESYNC_COOLDOWN_MS").map_or(default.cooldown, Duration::from_millis),
+        }
+    }
+
+    /// Why a report is out of tolerance, if it is. `previous_offset` is from the
+    /// peer's last report, if it has made one.
+    pub fn violation(&self, previous_offset: Option<i64>, offset: i64, drift_ms: i64) -> Option<String> {
+        if drift_ms.abs() > self.max_drift_ms {
+            return Some(format!("drift {}ms", drift_ms));
+        }
+        let step = previous_offset.map_or(0, |before| before.abs_diff(offset));
+        if step > self.max_offset_step_us {
+            return Some(format!("offset moved {}us", step));
+        }
+        None
+    }
+}
+
+/// A finished session, kept so operators can see why a speaker dropped out
+#[derive(Debug, Clone, Serialize)]
+pub struct Departure {
+    pub session_id: String,
+    pub device_id: String,
+    pub reason: DisconnectReason,
+    pub connected_at: u64, // Server time (us)
+    pub left_at: u64,
+}
+
+/// Drift reports kept per peer (a few minutes at typical report rates)
+pub const DRIFT_HISTORY_LEN: usize = 32;
+
+/// What a peer last told us about its sync, from `ClientMessage::Telemetry`
+#[derive(Debug, Clone, Default)]
+pub struct PeerTelemetry {
+    pub offset: i64, // Clock offset to the server (us)
+    pub rtt: u64,    // us
+    pub drift: i64,  // Playback drift (ms)
+    pub status: String,
+    pub sync_state: SyncState,
+    pub drift_history: VecDeque<i64>, // Oldest first, at most DRIFT_HISTORY_LEN
+    pub reported_at: u64,             // Server time of the last report (us), 0 if none yet
+}
+
+impl PeerTelemetry {
+    /// Offset from the last report, if there has been one
+    pub fn reported_offset(&self) -> Option<i64> {
+        (self.reported_at != 0).then_some(self.offset)
+    }
+}
+
 pub struct Peer {
-    pub addr: std::net::SocketAddr,
-    pub offset: AtomicU64,  // Last calculated offset
-    pub rtt: AtomicU64,     // Last calculated RTT
+    pub addr: RwLock<std::net::SocketAddr>, // Of the current connection
+    pub device_id: String,
+    pub connected_at: u64,  // Server time (us)
+    pub telemetry: RwLock<PeerTelemetry>,
+    pub last_seen: AtomicU64, // Server time of the last frame from this peer (us)
+    pub client_kind: ClientKind,
+    pub protocol_version: u16, // Negotiated in the Join/Welcome handshake
+    pub capabilities: u32,
+    pub role: RwLock<Role>,    // Assigned by Room::admit, changed by the host
+
+    // Session resumption: a reconnecting client presents `resume_token` to
+    // reattach. Each attached connection gets a new epoch; older ones must stop.
+    pub resume_token: String,
+    epoch: AtomicU64,
+    online: AtomicBool,
+    pub replaced: Notify, // Wakes the old connection when a new one attaches
+
+    // Wakes the connection to send SyncRequired; a permit waits out a reconnect
+    pub resync: Notify,
+    last_resync: AtomicU64, // Server time of the last request (us), 0 if never
 }
 
-#[derive(Debug, Clone)]
+impl Peer {
+    pub fn new(
+        addr: std::net::SocketAddr,
+        device_id: String,
+        connected_at: u64,
+        client_kind: ClientKind,
+        protocol_version: u16,
+        capabilities: u32,
+    ) -> Self {
+        Self {
+            addr: RwLock::new(addr),
+            device_id,
+            connected_at,
+            telemetry: RwLock::new(PeerTelemetry::default()),
+            last_seen: AtomicU64::new(connected_at),
+            client_kind,
+            protocol_version,
+            capabilities,
+            role: RwLock::new(Role::Listener),
+            resume_token: uuid::Uuid::new_v4().to_string(),
+            epoch: AtomicU64::new(0),
+            online: AtomicBool::new(false),
+            replaced: Notify::new(),
+            resync: Notify::new(),
+            last_resync: AtomicU64::new(0),
+        }
+    }
+
+    /// Bind a new connection to this peer; returns its epoch
+    pub fn attach(&self, addr: std::net::SocketAddr) -> u64 {
+        *self.addr.write().unwrap() = addr;
+        let epoch = self.epoch.fetch_add(1, Ordering::SeqCst) + 1;
+        self.online.store(true, Ordering::SeqCst);
+        self.replaced.notify_waiters();
+        epoch
+    }
+
+    /// False once a newer connection has attached
+    pub fn is_current(&self, epoch: u64) -> bool {
+        self.epoch.load(Ordering::SeqCst) == epoch
+    }
+
+    /// Connection `epoch` dropped; keep the peer for resumption. False if already replaced.
+    pub fn suspend(&self, epoch: u64) -> bool {
+        if !self.is_current(epoch) {
+            return false;
+        }
+        self.online.store(false, Ordering::SeqCst);
+        true
+    }
+
+    pub fn is_online(&self) -> bool {
+        self.online.load(Ordering::SeqCst)
+    }
+
+    pub fn role(&self) -> Role {
+        *self.role.read().unwrap()
+    }
+
+    /// Note that the peer is alive as of `now`
+    pub fn touch(&self, now: u64) {
+        self.last_seen.fetch_max(now, Ordering::Relaxed);
+    }
+
+    pub fn last_seen(&self) -> u64 {
+        self.last_seen.load(Ordering::Relaxed)
+    }
+
+    /// Have the peer's connection send it SyncRequired
+    pub fn request_resync(&self, now: u64) {
+        self.last_resync.store(now, Ordering::Relaxed);
+        self.resync.notify_one();
+    }
+
+    /// Server time of the last resync request (us), 0 if never
+    pub fn last_resync(&self) -> u64 {
+        self.last_resync.load(Ordering::Relaxed)
+    }
+
+    pub fn record_telemetry(&self, now: u64, rtt: u64, offset: i64, drift: i64, status: String, sync_state: SyncState) {
+        let mut t = self.telemetry.write().unwrap();
+        t.offset = offset;
+        t.rtt = rtt;
+        t.drift = drift;
+        t.status = status;
+        t.sync_state = sync_state;
+        t.reported_at = now;
+        if t.drift_history.len() == DRIFT_HISTORY_LEN {
+            t.drift_history.pop_front();
+        }
+        t.drift_history.push_back(drift);
+    }
+
+    pub fn info(&self, session_id: &str) -> PeerInfo {
+        let t = self.telemetry.read().unwrap();
+        PeerInfo {
+            session_id: session_id.to_string(),
+            device_id: self.device_id.clone(),
+            addr: self.addr.read().unwrap().to_string(),
+            client_kind: self.client_kind,
+            role: self.role(),
+            connected_at: self.connected_at,
+            last_seen: self.last_seen(),
+            offset: t.offset,
+            rtt: t.rtt,
+            drift: t.drift,
+            status: t.status.clone(),
+            sync_state: t.sync_state,
+            online: self.is_online(),
+            drift_history: t.drift_history.iter().copied().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct PlaybackState {
     pub is_playing: bool,
     pub track_url: String,
     pub position_ms: u64,
     pub last_update_time: u64, // Server time when this state was updated
+    pub queue_entry: Option<u32>, // Queue entry the track came from, if any
+    pub track: Track,             // Metadata for track_url
 }
 
-pub struct AppState {
+impl PlaybackState {
+    /// Load a track that isn't from the queue
+    pub fn load(&mut self, track_url: impl Into<String>, track: Track) {
+        self.track_url = track_url.into();
+        self.queue_entry = None;
+        self.track = track;
+    }
+
+    /// Where playback is (or would be, if paused) at server time `now`, never past the end
+    pub fn position_at(&self, now: u64) -> u64 {
+        let elapsed_ms = if self.is_playing { now.saturating_sub(self.last_update_time) / 1000 } else { 0 };
+        self.track.clamp_position(self.position_ms + elapsed_ms)
+    }
+}
+
+/// Who may join a room and who runs it
+#[derive(Debug, Default)]
+struct Access {
+    claimed: bool,        // Someone has been admitted, so the PIN is settled
+    pin: Option<String>,
+    host: Option<String>, // Session id of the Host
+}
+
+/// A file on the host's disk, served at /stream
+#[derive(Debug, Clone)]
+pub struct HostedFile {
+    pub path: String,
+    pub track: Track, // Read from the file once, when it's hosted
+}
+
+impl HostedFile {
+    pub fn new(path: String) -> Self {
+        let track = crate::probe::describe_file(&path);
+        Self { path, track }
+    }
+}
+
+/// One independent party: its own members, playback and channels
+pub struct Room {
+    pub id: String,
     // Map of active peer sessions
     pub peers: DashMap<String, Arc<Peer>>,
-    // Pub/Sub for broadcasting messages to all connected clients
+    // Pub/Sub for broadcasting messages to everyone in the room
     pub tx: broadcast::Sender<ServerMessage>,
-    
-    // Host Mode State
+
     // Host Mode State
-    pub hosted_file_path: Arc<RwLock<Option<String>>>,
+    pub hosted_file: Arc<RwLock<Option<HostedFile>>>,
     pub playback_state: Arc<RwLock<PlaybackState>>,
-    
+    // Lock after playback_state when taking both
+    pub queue: Mutex<PlayQueue>,
+
     // Live Streaming
     pub audio_tx: broadcast::Sender<Vec<u8>>,
+
+    // Shared with AppState so room logic doesn't need the whole state
+    pub clock: Arc<dyn Clock>,
+    pub media: Arc<MediaProxy>,
+
+    // Access control: who opened the room, its PIN and its Host, decided together
+    // under one lock so simultaneous joiners can't both claim the room
+    access: Mutex<Access>,
+    // Lets the host drive REST /control; handed out in the host's Welcome
+    pub control_token: String,
+
+    // Finished sessions, oldest first, at most DEPARTURE_LOG_LEN
+    departures: Mutex<VecDeque<Departure>>,
 }
 
-impl AppState {
-    pub fn new() -> SharedState {
+impl Room {
+    pub fn new(id: &str, clock: Arc<dyn Clock>, media: Arc<MediaProxy>) -> Self {
         let (tx, _) = broadcast::channel(100);
         let (audio_tx, _) = broadcast::channel(1024);
 
-        Arc::new(Self {
+        Self {
+            id: id.to_string(),
             peers: DashMap::new(),
             tx,
-            hosted_file_path: Arc::new(RwLock::new(None)),
-            playback_state: Arc::new(RwLock::new(PlaybackState {
-                is_playing: false,
-                track_url: "".to_string(),
-                position_ms: 0,
-                last_update_time: 0,
-            })),
+            hosted_file: Arc::new(RwLock::new(None)),
+            playback_state: Arc::new(RwLock::new(PlaybackState::default())),
+            queue: Mutex::new(PlayQueue::default()),
             audio_tx,
+            clock,
+            media,
+            access: Mutex::new(Access::default()),
+            control_token: uuid::Uuid::new_v4().to_string(),
+            departures: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Sessions that have left, oldest first
+    pub fn departures(&self) -> Vec<Departure> {
+        self.departures.lock().unwrap().iter().cloned().collect()
+    }
+
+    fn record_departure(&self, departure: Departure) {
+        let mut log = self.departures.lock().unwrap();
+        if log.len() == DEPARTURE_LOG_LEN {
+            log.pop_front();
+        }
+        log.push_back(departure);
+    }
+
+    /// Decide a peer's role once it's in `peers`. The first member admitted to the
+    /// room sets its PIN; if the room has no host the newcomer takes over.
+    /// None if the PIN doesn't match (the caller should drop the peer).
+    pub fn admit(&self, session_id: &str, pin: Option<&str>) -> Option<Role> {
+        let mut access = self.access.lock().unwrap();
+        if !access.claimed {
+            access.claimed = true;
+            access.pin = pin.filter(|p| !p.is_empty()).map(str::to_string);
+        } else if access.pin.is_some() && access.pin.as_deref() != pin {
+            return None;
+        }
+
+        let role = if access.host.is_none() {
+            access.host = Some(session_id.to_string());
+            Role::Host
+        } else {
+            Role::Listener
+        };
+        if let Some(peer) = self.peers.get(session_id) {
+            *peer.role.write().unwrap() = role;
+        }
+        Some(role)
+    }
+
+    /// Find the peer a reconnecting client left behind
+    pub fn find_resumable(&self, token: &str) -> Option<(String, Arc<Peer>)> {
+        self.peers
+            .iter()
+            .find(|p| p.resume_token == token)
+            .map(|p| (p.key().clone(), p.value().clone()))
+    }
+
+    /// Everyone in the room (including peers awaiting resumption), oldest connection first
+    pub fn roster(&self) -> Vec<PeerInfo> {
+        let mut peers: Vec<PeerInfo> = self.peers.iter().map(|p| p.info(p.key())).collect();
+        peers.sort_by_key(|p| p.connected_at);
+        peers
+    }
+
+    pub fn broadcast_roster(&self) {
+        let _ = self.tx.send(ServerMessage::PeerList { peers: self.roster() });
+    }
+
+    pub fn broadcast_peer(&self, session_id: &str) {
+        let Some(peer) = self.peers.get(session_id).map(|p| p.info(session_id)) else {
+            return;
+        };
+        let _ = self.tx.send(ServerMessage::PeerUpdate { peer });
+    }
+
+    pub fn role_of(&self, session_id: &str) -> Option<Role> {
+        self.peers.get(session_id).map(|p| p.role())
+    }
+
+    /// Change a member's role and tell the room. False if it isn't a member.
+    pub fn set_role(&self, session_id: &str, role: Role) -> bool {
+        let Some(peer) = self.peers.get(session_id) else {
+            return false;
+        };
+        *peer.role.write().unwrap() = role;
+        drop(peer);
+        let _ = self.tx.send(ServerMessage::RoleChanged { session_id: session_id.to_string(), role });
+        self.broadcast_peer(session_id);
+        true
+    }
+
+    /// Ask one member, or everyone if `session_id` is None, to resync.
+    /// Returns how many peers were asked.
+    pub fn request_resync(&self, session_id: Option<&str>) -> usize {
+        let now = self.clock.now_micros();
+        let mut asked = 0;
+        for peer in self.peers.iter().filter(|p| session_id.is_none_or(|id| p.key() == id)) {
+            peer.request_resync(now);
+            asked += 1;
+        }
+        asked
+    }
+
+    /// After the host leaves, hand the room to a co-host if there is one;
+    /// otherwise the next member admitted becomes host
+    fn promote_successor(&self, departed: &str) {
+        let mut access = self.access.lock().unwrap();
+        if access.host.as_deref() != Some(departed) {
+            return;
+        }
+        access.host = self.peers.iter().find(|p| p.role() == Role::CoHost).map(|p| p.key().clone());
+        if let Some(session_id) = &access.host {
+            tracing::info!("Room {}: {} promoted to host", self.id, session_id);
+            self.set_role(session_id, Role::Host);
+        }
+    }
+
+    /// Relative URL clients use to fetch this room's hosted file
+    pub fn stream_path(&self) -> String {
+        if self.id == DEFAULT_ROOM {
+            "stream".to_string()
+        } else {
+            format!("stream?room={}", self.id)
+        }
+    }
+}
+
+pub struct AppState {
+    pub rooms: DashMap<String, Arc<Room>>,
+
+    // Time source for every server timestamp
+    pub clock: Arc<dyn Clock>,
+
+    pub heartbeat: HeartbeatConfig,
+
+    pub sync_tolerance: SyncTolerance,
+
+    // Turns page URLs in PlayRequests into playable streams
+    pub resolver: Resolver,
+
+    // Serves web tracks from /media when enabled
+    pub media: Arc<MediaProxy>,
+
+    // Port of the UDP time listener, 0 while none is running
+    udp_time_port: AtomicU16,
+}
+
+/// What an AppState is built from
+pub struct AppStateConfig {
+    pub clock: Arc<dyn Clock>,
+    pub heartbeat: HeartbeatConfig,
+    pub sync_tolerance: SyncTolerance,
+    pub resolver: Resolver,
+    pub media: MediaProxy,
+}
+
+impl Default for AppStateConfig {
+    fn default() -> Self {
+        Self {
+            clock: Arc::new(MonotonicClock::new()),
+            heartbeat: HeartbeatConfig::default(),
+            sync_tolerance: SyncTolerance::default(),
+            resolver: Resolver::default(),
+            media: MediaProxy::default(),
+        }
+    }
+}
+
+impl AppStateConfig {
+    /// Defaults, with each part's SONICSYNC_* overrides
+    pub fn from_env() -> Self {
+        Self {
+            heartbeat: HeartbeatConfig::from_env(),
+            sync_tolerance: SyncTolerance::from_env(),
+            resolver: Resolver::from_env(),
+            media: MediaProxy::new(MediaConfig::from_env()),
+            ..Self::default()
+        }
+    }
+}
+
+impl AppState {
+    pub fn new() -> SharedState {
+        Self::with_config(AppStateConfig::default())
+    }
+
+    pub fn with_config(config: AppStateConfig) -> SharedState {
+        let AppStateConfig { clock, heartbeat, sync_tolerance, resolver, media } = config;
+        let media = Arc::new(media);
+        let rooms = DashMap::new();
+        rooms.insert(DEFAULT_ROOM.to_string(), Arc::new(Room::new(DEFAULT_ROOM, clock.clone(), media.clone())));
+
+        Arc::new(Self {
+            rooms,
+            clock,
+            heartbeat,
+            sync_tolerance,
+            resolver,
+            media,
+            udp_time_port: AtomicU16::new(0),
         })
     }
+
+    /// Port advertised in Welcome for UDP time probes, if the listener is up
+    pub fn udp_time_port(&self) -> Option<u16> {
+        Some(self.udp_time_port.load(Ordering::Relaxed)).filter(|&port| port != 0)
+    }
+
+    pub fn set_udp_time_port(&self, port: Option<u16>) {
+        self.udp_time_port.store(port.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn default_room(&self) -> Arc<Room> {
+        self.get_or_create_room(DEFAULT_ROOM)
+    }
+
+    pub fn room(&self, id: &str) -> Option<Arc<Room>> {
+        self.rooms.get(id).map(|r| r.clone())
+    }
+
+    pub fn get_or_create_room(&self, id: &str) -> Arc<Room> {
+        self.rooms
+            .entry(id.to_string())
+            .or_insert_with(|| {
+                tracing::info!("Room created: {}", id);
+                Arc::new(Room::new(id, self.clock.clone(), self.media.clone()))
+            })
+            .clone()
+    }
+
+    /// Add a peer to a room, creating the room if needed. Retries if the room
+    /// was cleaned up between lookup and insert, so the peer never lands in an orphan.
+    pub fn join_room(&self, id: &str, session_id: &str, peer: Arc<Peer>) -> Arc<Room> {
+        loop {
+            let room = self.get_or_create_room(id);
+            room.peers.insert(session_id.to_string(), peer.clone());
+            match self.rooms.get(id) {
+                Some(current) if Arc::ptr_eq(&current, &room) => return room,
+                _ => {
+                    room.peers.remove(session_id);
+                }
+            }
+        }
+    }
+
+    /// Remove a peer, record why it left, and drop its room if that left it empty
+    pub fn leave_room(&self, room: &Room, session_id: &str, reason: DisconnectReason) {
+        self.remove_peer(room, session_id, reason, |_| true);
+    }
+
+    /// Drop a suspended session whose resume window ran out, unless connection
+    /// `epoch` has been superseded by a resume in the meantime
+    pub fn expire_session(&self, room: &Room, session_id: &str, epoch: u64, reason: DisconnectReason) {
+        self.remove_peer(room, session_id, reason, |p| p.is_current(epoch) && !p.is_online());
+    }
+
+    fn remove_peer(&self, room: &Room, session_id: &str, reason: DisconnectReason, only_if: impl Fn(&Peer) -> bool) {
+        let Some((_, peer)) = room.peers.remove_if(session_id, |_, p| only_if(p)) else {
+            return;
+        };
+        tracing::info!("Session {} left room {}: {:?}", session_id, room.id, reason);
+        room.record_departure(Departure {
+            session_id: session_id.to_string(),
+            device_id: peer.device_id.clone(),
+            reason,
+            connected_at: peer.connected_at,
+            left_at: self.clock.now_micros(),
+        });
+        room.promote_successor(session_id);
+        if reason != DisconnectReason::Rejected {
+            let _ = room.tx.send(ServerMessage::PeerLeft { session_id: session_id.to_string(), reason });
+            room.broadcast_roster();
+        }
+        if room.id != DEFAULT_ROOM
+            && self.rooms.remove_if(&room.id, |_, r| r.peers.is_empty()).is_some()
+        {
+            tracing::info!("Room closed: {}", room.id);
+        }
+    }
+}
+
+/// Room names are used in URLs; keep them short and boring
+pub fn is_valid_room_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= 64
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
 }
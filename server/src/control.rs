@@ -1,89 +1,319 @@
 use axum::{
-    extract::{State, Json},
-    http::StatusCode,
+    extract::{Query, State, Json},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
 };
-use crate::app_state::SharedState;
-use rust_core::messages::{ServerMessage, ControlCommand};
-use std::time::{SystemTime, UNIX_EPOCH};
-
-// Helper to get server time
-fn get_server_micros() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_micros() as u64
-}
-
-// Core logic shared between REST and WebSocket
-pub fn process_control_command(state: &SharedState, cmd: ControlCommand) {
-    let mut pb_guard = state.playback_state.write().unwrap();
-    let now = get_server_micros();
+use crate::app_state::{PlaybackState, Room, SharedState, DEFAULT_ROOM};
+use crate::queue;
+use crate::resolve::{needs_resolution, Resolver};
+use std::collections::HashMap;
+use std::sync::Arc;
+use rust_core::messages::{ServerMessage, ControlCommand, ErrorCode, Role};
+use rust_core::track::{SourceKind, Track};
+use std::fmt;
+
+/// Why a control command was refused
+#[derive(Debug, Clone)]
+pub struct CommandError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl CommandError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.code, self.message)
+    }
+}
+
+/// URL clients should play and what we know about it: the loaded track, else the room's hosted file
+pub(crate) fn current_track(room: &Room, pb: &PlaybackState) -> Option<(String, Track)> {
+    if !pb.track_url.is_empty() {
+        return Some((pb.track_url.clone(), pb.track.clone()));
+    }
+    // If hosted file exists, construct local URL (this part might need IP injection or client handling)
+    // For now, let's assume the client knows where to look if it's hosting
+    let hosted = room.hosted_file.read().unwrap();
+    hosted.as_ref().map(|file| (room.stream_path(), file.track.clone()))
+}
+
+/// Refuse positions past the end of a track of known length
+fn check_position(track: &Track, position_ms: u64) -> Result<(), CommandError> {
+    match track.duration_ms {
+        Some(duration_ms) if position_ms > duration_ms => Err(CommandError::new(
+            ErrorCode::InvalidRequest,
+            format!("position {}ms is past the end of the track ({}ms)", position_ms, duration_ms),
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Longest a start may be scheduled ahead; clients pass a few seconds at most
+pub const MAX_DELAY_MS: u64 = 10 * 60 * 1000;
+
+/// Refuse delays no caller could mean (and that would overflow the start time)
+pub(crate) fn check_delay(delay_ms: u64) -> Result<(), CommandError> {
+    if delay_ms > MAX_DELAY_MS {
+        return Err(CommandError::new(
+            ErrorCode::InvalidRequest,
+            format!("delay {}ms is longer than the {}ms limit", delay_ms, MAX_DELAY_MS),
+        ));
+    }
+    Ok(())
+}
+
+/// Server time `delay_ms` after `now`
+pub(crate) fn start_after(now: u64, delay_ms: u64) -> Result<u64, CommandError> {
+    check_delay(delay_ms)?;
+    Ok(now + delay_ms * 1000)
+}
+
+/// Refuse anyone who isn't allowed to drive playback
+pub fn authorize_control(role: Role) -> Result<(), CommandError> {
+    if role.can_control() {
+        Ok(())
+    } else {
+        Err(CommandError::new(ErrorCode::Forbidden, "only the host or a co-host can control playback"))
+    }
+}
+
+/// Append to the queue and tell the room
+fn enqueue(room: &Arc<Room>, pb: &PlaybackState, queue: &mut queue::PlayQueue, track_url: String, track: Track) {
+    let entry_id = queue.add(track_url, track);
+    tracing::info!("Room {}: queued entry {}", room.id, entry_id);
+    // The timer may already have found nothing to follow the current track
+    queue::schedule_advance(room, pb, queue);
+    queue::broadcast_queue(room, queue);
+}
+
+/// Whether `cmd` queues a page URL, which has to go through the resolver first
+pub fn queues_page_url(cmd: &ControlCommand) -> bool {
+    matches!(cmd, ControlCommand::QueueAdd { track_url, .. } if needs_resolution(track_url))
+}
+
+/// `process_control_command` for callers that can wait: a page URL being queued is
+/// resolved first (without holding the room's locks) and its stream queued instead
+pub async fn process_resolving(resolver: &Resolver, room: &Arc<Room>, role: Role, cmd: ControlCommand) -> Result<(), CommandError> {
+    match cmd {
+        ControlCommand::QueueAdd { track_url, duration_ms } if needs_resolution(&track_url) => {
+            // Don't spend a resolution on someone who can't queue anyway
+            authorize_control(role)?;
+            let resolved = resolver.resolve(&track_url).await?;
+            let track = Track { duration_ms: duration_ms.or(resolved.track.duration_ms), ..resolved.track };
+            let pb_guard = room.playback_state.read().unwrap();
+            let mut queue = room.queue.lock().unwrap();
+            enqueue(room, &pb_guard, &mut queue, resolved.stream_url, track);
+            Ok(())
+        }
+        cmd => process_control_command(room, role, cmd),
+    }
+}
+
+/// How far ahead a skip (Next/Previous) starts, like Seek: time for clients to load
+const SKIP_DELAY_US: u64 = 500_000;
+
+// Core logic shared between REST and WebSocket. `role` is the sender's role in the room.
+pub fn process_control_command(room: &Arc<Room>, role: Role, cmd: ControlCommand) -> Result<(), CommandError> {
+    authorize_control(role)?;
+    let mut pb_guard = room.playback_state.write().unwrap();
+    let mut queue = room.queue.lock().unwrap();
+    let now = room.clock.now_micros();
     
     match cmd {
         ControlCommand::Play { start_at_ms, delay_ms } => {
+            let start_at_server_time = start_after(now, delay_ms)?;
+            // Nothing loaded: start the queue
+            let from_queue = current_track(room, &pb_guard).is_none() && !queue.is_empty();
+            if from_queue {
+                if let Some(entry) = queue.start().cloned() {
+                    pb_guard.track_url = room.media.proxy(&entry.track_url, &entry.track);
+                    pb_guard.queue_entry = Some(entry.entry_id);
+                    pb_guard.track = entry.track;
+                }
+            }
+            let (track_url, track) = current_track(room, &pb_guard)
+                .ok_or_else(|| CommandError::new(ErrorCode::NotFound, "no track loaded and no file hosted"))?;
+            check_position(&track, start_at_ms)?;
+
             pb_guard.is_playing = true;
             pb_guard.position_ms = start_at_ms;
-            pb_guard.last_update_time = now;
-            
-            let start_at_server_time = now + (delay_ms * 1000);
-            
-            // If track_url is empty, check if we have a hosted file
-            let track_url = if pb_guard.track_url.is_empty() {
-                // If hosted file exists, construct local URL (this part might need IP injection or client handling)
-                // For now, let's assume the client knows where to look if it's hosting
-                "stream".to_string() 
-            } else {
-                pb_guard.track_url.clone()
-            };
+            pb_guard.last_update_time = start_at_server_time;
 
             let msg = ServerMessage::PlayCommand {
                 track_url, 
                 start_at_server_time,
                 start_at_position_ms: start_at_ms,
                 server_time_at_broadcast: now,
+                track,
             };
-            let _ = state.tx.send(msg);
+            let _ = room.tx.send(msg);
+            queue::schedule_advance(room, &pb_guard, &mut queue);
+            if from_queue {
+                queue::broadcast_queue(room, &queue);
+            }
         }
         ControlCommand::Pause => {
             // Update position based on how long we played
-            if pb_guard.is_playing {
-                let elapsed_micros = now - pb_guard.last_update_time;
-                pb_guard.position_ms += elapsed_micros / 1000;
-            }
+            pb_guard.position_ms = pb_guard.position_at(now);
             pb_guard.is_playing = false;
             pb_guard.last_update_time = now;
+            queue.cancel_advance();
             
             let msg = ServerMessage::PauseCommand {
                 server_time: now,
             };
-            let _ = state.tx.send(msg);
+            let _ = room.tx.send(msg);
         }
         ControlCommand::Seek { position_ms } => {
+            let (track_url, track) = current_track(room, &pb_guard)
+                .ok_or_else(|| CommandError::new(ErrorCode::InvalidRequest, "nothing loaded to seek in"))?;
+            check_position(&track, position_ms)?;
             pb_guard.position_ms = position_ms;
             pb_guard.last_update_time = now;
             
             // If playing, we need to send a new PlayCommand from this position
             if pb_guard.is_playing {
                  let start_at_server_time = now + 500_000; // 500ms buffer
+                 pb_guard.last_update_time = start_at_server_time;
                  let msg = ServerMessage::PlayCommand {
-                    track_url: pb_guard.track_url.clone(),
+                    track_url,
                     start_at_server_time,
                     start_at_position_ms: position_ms,
                     server_time_at_broadcast: now,
+                    track,
                 };
-                let _ = state.tx.send(msg);
+                let _ = room.tx.send(msg);
+                queue::schedule_advance(room, &pb_guard, &mut queue);
             }
             // If paused, we effectively just updated the "resume from" position
         }
+        ControlCommand::QueueAdd { track_url, duration_ms } => {
+            let track = Track { duration_ms, ..Track::from_url(&track_url, SourceKind::Url) };
+            enqueue(room, &pb_guard, &mut queue, track_url, track);
+        }
+        ControlCommand::QueueRemove { entry_id } => {
+            if !queue.remove(entry_id) {
+                return Err(CommandError::new(ErrorCode::NotFound, "no such queue entry"));
+            }
+            queue::broadcast_queue(room, &queue);
+        }
+        ControlCommand::QueueMove { entry_id, to_index } => {
+            if !queue.move_entry(entry_id, to_index as usize) {
+                return Err(CommandError::new(ErrorCode::NotFound, "no such queue entry"));
+            }
+            queue::broadcast_queue(room, &queue);
+        }
+        ControlCommand::Next => {
+            let entry = queue
+                .advance(false)
+                .cloned()
+                .ok_or_else(|| CommandError::new(ErrorCode::InvalidRequest, "end of the queue"))?;
+            queue::play_entry(room, &mut pb_guard, &mut queue, entry, now + SKIP_DELAY_US);
+            queue::broadcast_queue(room, &queue);
+        }
+        ControlCommand::Previous => {
+            let entry = queue
+                .back()
+                .cloned()
+                .ok_or_else(|| CommandError::new(ErrorCode::InvalidRequest, "the queue is empty"))?;
+            queue::play_entry(room, &mut pb_guard, &mut queue, entry, now + SKIP_DELAY_US);
+            queue::broadcast_queue(room, &queue);
+        }
+        ControlCommand::Shuffle => {
+            queue.shuffle();
+            queue::broadcast_queue(room, &queue);
+        }
+        ControlCommand::SetRepeat { mode } => {
+            queue.set_repeat(mode);
+            queue::schedule_advance(room, &pb_guard, &mut queue);
+            queue::broadcast_queue(room, &queue);
+        }
     }
+    Ok(())
 }
 
-// Handler for processing control commands (from REST)
+/// Host-only: change another member's role. The host's own role can't be handed out this way.
+pub fn assign_role(room: &Room, actor: Role, session_id: &str, role: Role) -> Result<(), CommandError> {
+    if !actor.can_assign_roles() {
+        return Err(CommandError::new(ErrorCode::Forbidden, "only the host can assign roles"));
+    }
+    if role == Role::Host || room.role_of(session_id) == Some(Role::Host) {
+        return Err(CommandError::new(ErrorCode::InvalidRequest, "the host role can't be reassigned"));
+    }
+    if !room.set_role(session_id, role) {
+        return Err(CommandError::new(ErrorCode::NotFound, "no such member in this room"));
+    }
+    Ok(())
+}
+
+pub(crate) fn error_response(e: CommandError) -> axum::response::Response {
+    let status = match e.code {
+        ErrorCode::NotFound => StatusCode::NOT_FOUND,
+        ErrorCode::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        ErrorCode::Unauthorized => StatusCode::UNAUTHORIZED,
+        ErrorCode::Forbidden => StatusCode::FORBIDDEN,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let body = serde_json::json!({ "code": e.code, "message": e.message });
+    (status, Json(body)).into_response()
+}
+
+/// REST callers must present `Authorization: Bearer <control_token>` from the host's Welcome
+pub(crate) fn authorize_rest(room: &Room, headers: &HeaderMap) -> Result<(), CommandError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if token == Some(room.control_token.as_str()) {
+        Ok(())
+    } else {
+        Err(CommandError::new(ErrorCode::Unauthorized, "missing or invalid control token"))
+    }
+}
+
+// Handler for processing control commands (from REST), scoped by ?room=.
 pub async fn handle_control_command(
     State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
     Json(cmd): Json<ControlCommand>,
 ) -> impl IntoResponse {
-    process_control_command(&state, cmd);
-    StatusCode::OK
+    let room_id = query.get("room").map(String::as_str).unwrap_or(DEFAULT_ROOM);
+    let Some(room) = state.room(room_id) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Err(e) = authorize_rest(&room, &headers) {
+        return error_response(e);
+    }
+    match process_resolving(&state.resolver, &room, Role::Host, cmd).await {
+        Ok(()) => StatusCode::OK.into_response(),
+        Err(e) => error_response(e),
+    }
+}
+
+// POST /resync?room=[&session=]: have one member, or the whole room, redo its clock sync
+pub async fn handle_resync(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let room_id = query.get("room").map(String::as_str).unwrap_or(DEFAULT_ROOM);
+    let Some(room) = state.room(room_id) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Err(e) = authorize_rest(&room, &headers) {
+        return error_response(e);
+    }
+    let session_id = query.get("session").map(String::as_str);
+    let asked = room.request_resync(session_id);
+    if asked == 0 && session_id.is_some() {
+        return error_response(CommandError::new(ErrorCode::NotFound, "no such member in this room"));
+    }
+    tracing::info!("Room {}: resync requested for {} peers", room.id, asked);
+    Json(serde_json::json!({ "requested": asked })).into_response()
 }
@@ -1,14 +1,21 @@
-use crate::app_state::{SharedState, Peer};
+use crate::app_state::{is_valid_room_id, Peer, Room, SharedState, DEFAULT_ROOM};
 use axum::{
-    extract::{ws::{Message, WebSocket, WebSocketUpgrade}, ConnectInfo, Query, State},
-    response::IntoResponse,
+    extract::{ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade}, ConnectInfo, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
-use futures::{sink::SinkExt, stream::StreamExt};
+use futures::{sink::SinkExt, stream::{SplitSink, SplitStream, StreamExt}};
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::{Arc, atomic::{Ordering, self}};
-use std::time::{SystemTime, UNIX_EPOCH};
-use rust_core::messages::{ClientMessage, ServerMessage};
+use std::sync::Arc;
+use std::time::Duration;
+use rust_core::clock::Clock;
+use rust_core::messages::{
+    capability, is_supported_version, v1, ClientMessage, DisconnectReason, ErrorCode, Role, ServerMessage,
+    LEGACY_PROTOCOL_VERSION, NO_REQUEST, PROTOCOL_VERSION,
+};
+use rust_core::track::{SourceKind, Track};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
 pub async fn ws_handler(
@@ -16,222 +23,601 @@ pub async fn ws_handler(
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     State(state): State<SharedState>,
     query: Query<HashMap<String, String>>,
-) -> impl IntoResponse {
+) -> Response {
+    if let Some(room) = query.get("room") {
+        if !is_valid_room_id(room) {
+            return (StatusCode::BAD_REQUEST, "Invalid room name").into_response();
+        }
+    }
     tracing::info!("Client connecting: {}", addr);
     ws.on_upgrade(move |socket| handle_socket(socket, addr, state, query))
 }
 
+/// How long a new connection has to send its Join before it is dropped
+const JOIN_TIMEOUT: Duration = Duration::from_secs(10);
+/// A write that can't complete in this long means the client stopped reading
+/// (or its Wi-Fi is gone and the TCP buffer filled up)
+const SEND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Per-connection wire format, fixed by the handshake
+#[derive(Debug, Clone, Copy)]
+pub struct Codec {
+    pub json: bool,            // Dashboard speaks JSON text frames
+    pub protocol_version: u16, // Negotiated version
+}
+
+impl Codec {
+    fn encode(&self, msg: &ServerMessage) -> Option<Message> {
+        if self.protocol_version < PROTOCOL_VERSION {
+            let legacy = msg.to_v1()?;
+            return if self.json {
+                serde_json::to_string(&legacy).ok().map(Message::Text)
+            } else {
+                bincode::serialize(&legacy).ok().map(Message::Binary)
+            };
+        }
+        if self.json {
+            serde_json::to_string(msg).ok().map(Message::Text)
+        } else {
+            bincode::serialize(msg).ok().map(Message::Binary)
+        }
+    }
+
+    fn decode(&self, msg: &Message) -> Result<ClientMessage, String> {
+        let legacy = self.protocol_version < PROTOCOL_VERSION;
+        match msg {
+            Message::Binary(bytes) if legacy => bincode::deserialize::<v1::ClientMessage>(bytes)
+                .map(Into::into)
+                .map_err(|e| e.to_string()),
+            Message::Binary(bytes) => bincode::deserialize::<ClientMessage>(bytes).map_err(|e| e.to_string()),
+            Message::Text(text) => serde_json::from_str::<ClientMessage>(text).map_err(|e| e.to_string()),
+            _ => Err("unsupported frame type".to_string()),
+        }
+    }
+}
+
+/// Pick the session's protocol version and capabilities, or explain why we can't
+pub fn negotiate(client_version: u16, client_capabilities: u32) -> Result<(u16, u32), String> {
+    let version = client_version.min(PROTOCOL_VERSION);
+    if !is_supported_version(version) {
+        return Err(format!(
+            "protocol version {} not supported (server speaks v{} and v{}), please update",
+            client_version, LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION
+        ));
+    }
+    Ok((version, client_capabilities & capability::SERVER))
+}
+
+/// Wait for the first frame and decode it as a Join, trying the v1 layout for old builds
+async fn read_join(receiver: &mut SplitStream<WebSocket>) -> Result<(ClientMessage, bool), String> {
+    while let Some(Ok(msg)) = receiver.next().await {
+        match msg {
+            Message::Binary(bytes) => {
+                let join = bincode::deserialize::<ClientMessage>(&bytes)
+                    .or_else(|_| bincode::deserialize::<v1::ClientMessage>(&bytes).map(Into::into))
+                    .map_err(|e| format!("could not decode Join: {}", e))?;
+                return Ok((join, false));
+            }
+            Message::Text(text) => {
+                let join = serde_json::from_str::<ClientMessage>(&text)
+                    .map_err(|e| format!("could not decode Join: {}", e))?;
+                return Ok((join, true));
+            }
+            Message::Close(_) => break,
+            _ => {}
+        }
+    }
+    Err("connection closed before Join".to_string())
+}
+
+/// Tell the client why it was refused, then close with the reason attached
+/// (the close frame is the only thing a client too old to decode `Incompatible` will see)
+async fn reject(sender: &mut SplitSink<WebSocket, Message>, codec: Codec, reason: String) {
+    tracing::warn!("Rejecting client: {}", reason);
+    let msg = ServerMessage::Incompatible {
+        reason: reason.clone(),
+        server_version: PROTOCOL_VERSION,
+        legacy_version: LEGACY_PROTOCOL_VERSION,
+    };
+    let _ = send_server_message(sender, &msg, codec).await;
+    let mut reason = reason;
+    reason.truncate(120); // Close reasons are capped at 123 bytes
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame { code: close_code::PROTOCOL, reason: reason.into() })))
+        .await;
+}
+
+/// Refuse a client whose handshake was fine but who isn't let into the room
+async fn deny(sender: &mut SplitSink<WebSocket, Message>, codec: Codec, code: ErrorCode, reason: &str) {
+    tracing::warn!("Denying client: {}", reason);
+    let msg = ServerMessage::Error { id: NO_REQUEST, code, message: reason.to_string() };
+    let _ = send_server_message(sender, &msg, codec).await;
+    let _ = sender
+        .send(Message::Close(Some(CloseFrame { code: close_code::POLICY, reason: reason.to_string().into() })))
+        .await;
+}
+
 pub async fn handle_socket(
     socket: WebSocket,
     addr: SocketAddr,
     state: SharedState,
     query: Query<HashMap<String, String>>,
 ) {
-    let session_id = Uuid::new_v4().to_string();
-    let is_dashboard = query.get("type").map(|t| t == "dashboard").unwrap_or(false);
+    let room_id = query.get("room").cloned().unwrap_or_else(|| DEFAULT_ROOM.to_string());
+    let (mut sender, mut receiver) = socket.split();
 
-    // Register peer
-    state.peers.insert(session_id.clone(), Arc::new(Peer {
-        addr,
-        offset: atomic::AtomicU64::new(0),
-        rtt: atomic::AtomicU64::new(0),
-    }));
+    // Handshake: the client's Join fixes the wire format for the rest of the session
+    let fallback = Codec { json: query.get("type").map(|t| t == "dashboard").unwrap_or(false), protocol_version: PROTOCOL_VERSION };
+    let (join, json) = match tokio::time::timeout(JOIN_TIMEOUT, read_join(&mut receiver)).await {
+        Ok(Ok(join)) => join,
+        Ok(Err(reason)) => return reject(&mut sender, fallback, reason).await,
+        Err(_) => return reject(&mut sender, fallback, "timed out waiting for Join".to_string()).await,
+    };
+    let ClientMessage::Join { device_id, protocol_version, client_kind, capabilities, pin, resume_token } = join else {
+        return reject(&mut sender, Codec { json, ..fallback }, "first message must be Join".to_string()).await;
+    };
+    let (protocol_version, capabilities) = match negotiate(protocol_version, capabilities) {
+        Ok(negotiated) => negotiated,
+        Err(reason) => return reject(&mut sender, Codec { json, ..fallback }, reason).await,
+    };
+    let codec = Codec { json, protocol_version };
 
-    let (mut sender, mut receiver) = socket.split();
-    
-    // Send Welcome directly
-    let welcome = ServerMessage::Welcome { session_id: session_id.clone() };
-    if is_dashboard {
-        if let Ok(json) = serde_json::to_string(&welcome) {
-            if sender.send(Message::Text(json)).await.is_err() {
-                return;
+    // Reattach to a dropped session if the client still holds its token,
+    // otherwise register a new peer, check it's allowed in and pick its role
+    let previous = resume_token
+        .as_deref()
+        .and_then(|token| state.room(&room_id).and_then(|room| Some((room.find_resumable(token)?, room))));
+    let (room, session_id, peer, resumed) = match previous {
+        Some(((session_id, peer), room)) => (room, session_id, peer, true),
+        None => {
+            let session_id = Uuid::new_v4().to_string();
+            let peer = Arc::new(Peer::new(addr, device_id.clone(), state.clock.now_micros(), client_kind, protocol_version, capabilities));
+            let room = state.join_room(&room_id, &session_id, peer.clone());
+            if room.admit(&session_id, pin.as_deref()).is_none() {
+                state.leave_room(&room, &session_id, DisconnectReason::Rejected);
+                return deny(&mut sender, codec, ErrorCode::Unauthorized, "wrong room PIN").await;
             }
+            (room, session_id, peer, false)
         }
-    } else {
-        if let Ok(data) = bincode::serialize(&welcome) {
-            if sender.send(Message::Binary(data)).await.is_err() {
-                return;
-            }
-        }
-    }
+    };
+    let epoch = peer.attach(addr);
+    peer.touch(state.clock.now_micros());
+    let role = peer.role();
+    tracing::info!(
+        "Device {}: {} ({}) room={} {:?} {:?} v{} caps={:#x}",
+        if resumed { "resumed" } else { "joined" },
+        device_id, session_id, room.id, client_kind, role, protocol_version, capabilities
+    );
 
-    // State Relay: If server is already playing, send the current track and position to the new client
-    let relay_msg = {
-        let pb = state.playback_state.read().unwrap();
-        if pb.is_playing {
-            let now = get_server_micros();
-            let current_pos = pb.position_ms + ((now - pb.last_update_time) / 1000);
-            Some(ServerMessage::PlayCommand {
-                track_url: pb.track_url.clone(),
-                start_at_server_time: now, // Start immediately
-                start_at_position_ms: current_pos,
-                server_time_at_broadcast: now,
-            })
-        } else {
-            None
+    let reason = 'session: {
+        let control_token = (role == Role::Host).then(|| room.control_token.clone());
+        let welcome = ServerMessage::Welcome {
+            session_id: session_id.clone(),
+            protocol_version,
+            capabilities,
+            role,
+            control_token,
+            resume_token: peer.resume_token.clone(),
+            resumed,
+            udp_time_port: state.udp_time_port(),
+        };
+        if !send_server_message(&mut sender, &welcome, codec).await {
+            break 'session DisconnectReason::SendFailed;
         }
-    };
 
-    if let Some(msg) = relay_msg {
-        if is_dashboard {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if sender.send(Message::Text(json)).await.is_err() {
-                    return;
+        // State Relay: If server is already playing, send the current track and position to the new client.
+        // A resumed client may have missed a pause, so it also hears about that.
+        let relay_msg = {
+            let pb = room.playback_state.read().unwrap();
+            let current = crate::control::current_track(&room, &pb);
+            match current {
+                Some((track_url, track)) if pb.is_playing => {
+                    let now = state.clock.now_micros();
+                    Some(ServerMessage::PlayCommand {
+                        track_url,
+                        // Start immediately, unless everyone else is still waiting to start
+                        start_at_server_time: now.max(pb.last_update_time),
+                        start_at_position_ms: pb.position_at(now),
+                        server_time_at_broadcast: now,
+                        track,
+                    })
                 }
+                Some(_) if resumed => Some(ServerMessage::PauseCommand { server_time: pb.last_update_time }),
+                _ => None,
             }
-        } else {
-            if let Ok(data) = bincode::serialize(&msg) {
-                if sender.send(Message::Binary(data)).await.is_err() {
-                    return;
-                }
+        };
+
+        if let Some(msg) = relay_msg {
+            if !send_server_message(&mut sender, &msg, codec).await {
+                break 'session DisconnectReason::SendFailed;
+            }
+        }
+        let queue_msg = {
+            let queue = room.queue.lock().unwrap();
+            (!queue.is_empty()).then(|| queue.snapshot())
+        };
+        if let Some(msg) = queue_msg {
+            if !send_server_message(&mut sender, &msg, codec).await {
+                break 'session DisconnectReason::SendFailed;
             }
         }
-    }
 
-    // Subscribe to broadcast channel
-    let mut rx = state.tx.subscribe();
+        // Subscribe to the room's broadcast channel
+        let mut rx = room.tx.subscribe();
+        // Direct replies for this session only (including from spawned tasks)
+        let (outbox, mut outbox_rx) = mpsc::unbounded_channel::<ServerMessage>();
+        let session = Session { state: state.clone(), room: room.clone(), id: session_id.clone(), outbox };
+        // Everyone (including the newcomer, now subscribed) gets the updated roster
+        room.broadcast_roster();
 
-    // Loop selection
-    loop {
-        tokio::select! {
-            // 1. Broadcast messages from other parts of the system
-            Ok(msg) = rx.recv() => {
-                if is_dashboard {
-                    if let Ok(json) = serde_json::to_string(&msg) {
-                        if sender.send(Message::Text(json)).await.is_err() {
-                            break;
-                        }
+        // Server-driven pings; any frame from the client (pongs included) counts as alive
+        let heartbeat = state.heartbeat;
+        let mut ping = tokio::time::interval_at(tokio::time::Instant::now() + heartbeat.interval, heartbeat.interval);
+        ping.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+        // Frames are read, stamped and decoded on their own task so t1 is the arrival
+        // time, not whenever this loop got round to the socket
+        let (time_tx, mut time_rx) = mpsc::channel::<TimeProbe>(INBOUND_BUFFER);
+        let (inbound_tx, mut inbound_rx) = mpsc::channel::<Inbound>(INBOUND_BUFFER);
+        let reader = tokio::spawn(read_frames(receiver, codec, state.clock.clone(), peer.clone(), time_tx, inbound_tx));
+
+        // Registered up front: notify_waiters() only wakes a waiter that already exists,
+        // so one created fresh inside select! would miss a takeover between iterations
+        let replaced = peer.replaced.notified();
+        tokio::pin!(replaced);
+        replaced.as_mut().enable();
+
+        // Loop selection. Biased so a time request never waits behind queued
+        // broadcasts: its reply goes out first and t2 is stamped just before the write.
+        let reason = loop {
+            if !peer.is_current(epoch) {
+                break DisconnectReason::ConnectionLost;
+            }
+            tokio::select! {
+                biased;
+
+                // 1. Clock sync fast lane
+                Some(probe) = time_rx.recv() => {
+                    let TimeProbe { t0, seq, t1 } = probe;
+                    let t2 = state.clock.now_micros();
+                    if !send_server_message(&mut sender, &ServerMessage::TimeResponse { t0, t1, t2, seq }, codec).await {
+                        break DisconnectReason::SendFailed;
                     }
-                } else {
-                    if let Ok(data) = bincode::serialize(&msg) {
-                        if sender.send(Message::Binary(data)).await.is_err() {
-                            break;
-                        }
+                }
+
+                // 2. The client reconnected and resumed this session on another socket
+                _ = &mut replaced => {
+                    replaced.set(peer.replaced.notified());
+                    replaced.as_mut().enable();
+                }
+
+                // 3. Everything else from this client, already decoded. A dropped
+                // connection ends the stream without a Close frame, so Lost is a disconnect.
+                inbound = inbound_rx.recv() => match inbound {
+                    Some(Inbound::Message(Ok(client_msg))) => handle_client_message(client_msg, &session).await,
+                    Some(Inbound::Message(Err(e))) => {
+                        tracing::warn!("Undecodable frame from {}: {}", session_id, e);
+                        let _ = session.outbox.send(ServerMessage::Error {
+                            id: NO_REQUEST,
+                            code: ErrorCode::MalformedMessage,
+                            message: format!("could not decode message: {}", e),
+                        });
+                    }
+                    Some(Inbound::Closed) => break DisconnectReason::Closed,
+                    Some(Inbound::Lost) | None => break DisconnectReason::ConnectionLost,
+                },
+
+                // 4. Replies addressed to this client only
+                Some(msg) = outbox_rx.recv() => {
+                    if !send_server_message(&mut sender, &msg, codec).await {
+                        break DisconnectReason::SendFailed;
                     }
                 }
-            }
 
-            // 2. Incoming messages from this client
-            Some(Ok(msg)) = receiver.next() => {
-                match msg {
-                    Message::Binary(bytes) => {
-                         if let Ok(client_msg) = bincode::deserialize::<ClientMessage>(&bytes) {
-                            handle_client_message(client_msg, &mut sender, &state, &session_id, is_dashboard).await;
-                        }
+                // 5. Broadcast messages from other parts of the system
+                Ok(msg) = rx.recv() => {
+                    if !send_server_message(&mut sender, &msg, codec).await {
+                        break DisconnectReason::SendFailed;
                     }
-                    Message::Text(text) => {
-                        if let Ok(client_msg) = serde_json::from_str::<ClientMessage>(&text) {
-                            handle_client_message(client_msg, &mut sender, &state, &session_id, is_dashboard).await;
-                        }
+                }
+
+                // 6. Something decided this client's clock sync can't be trusted
+                _ = peer.resync.notified() => {
+                    if !send_server_message(&mut sender, &ServerMessage::SyncRequired, codec).await {
+                        break DisconnectReason::SendFailed;
+                    }
+                }
+
+                // 7. Liveness: evict if silent too long, otherwise ping
+                _ = ping.tick() => {
+                    if !peer.is_current(epoch) {
+                        break DisconnectReason::ConnectionLost;
+                    }
+                    let idle_us = state.clock.now_micros().saturating_sub(peer.last_seen());
+                    if idle_us > heartbeat.timeout.as_micros() as u64 {
+                        let frame = CloseFrame { code: close_code::AWAY, reason: "idle timeout".into() };
+                        let _ = tokio::time::timeout(SEND_TIMEOUT, sender.send(Message::Close(Some(frame)))).await;
+                        break DisconnectReason::TimedOut;
+                    }
+                    if !matches!(tokio::time::timeout(SEND_TIMEOUT, sender.send(Message::Ping(Vec::new()))).await, Ok(Ok(()))) {
+                        break DisconnectReason::SendFailed;
                     }
-                    Message::Close(_) => break,
-                    _ => {}
                 }
             }
-            
-            else => break,
-        }
+        };
+        // Once the reader has taken the client's Close, writes are refused; that's still a clean close
+        let reason = match reason {
+            DisconnectReason::SendFailed if closed_by_client(&mut inbound_rx).await => DisconnectReason::Closed,
+            other => other,
+        };
+        reader.abort();
+        reason
+    };
+
+    if !peer.is_current(epoch) {
+        // A newer connection owns the session now; just let this socket go
+        tracing::info!("Session {} moved to a new connection", session_id);
+        let frame = CloseFrame { code: close_code::NORMAL, reason: "session resumed elsewhere".into() };
+        let _ = tokio::time::timeout(SEND_TIMEOUT, sender.send(Message::Close(Some(frame)))).await;
+        return;
+    }
+    end_session(state, room, session_id, peer, epoch, reason);
+}
+
+/// A clean close leaves at once; anything else holds the session open for
+/// `resume_grace` so the client can come back with its resume token.
+fn end_session(state: SharedState, room: Arc<Room>, session_id: String, peer: Arc<Peer>, epoch: u64, reason: DisconnectReason) {
+    let grace = state.heartbeat.resume_grace;
+    if reason == DisconnectReason::Closed || grace.is_zero() {
+        state.leave_room(&room, &session_id, reason);
+        return;
+    }
+    if !peer.suspend(epoch) {
+        return; // Resumed on another connection in the meantime
     }
+    tracing::info!("Client dropped: {} (room {}): {:?}, resumable for {:?}", session_id, room.id, reason, grace);
+    room.broadcast_peer(&session_id);
+    tokio::spawn(async move {
+        tokio::time::sleep(grace).await;
+        state.expire_session(&room, &session_id, epoch, reason);
+    });
+}
+
+/// Frames a reader task can have waiting for the session loop
+const INBOUND_BUFFER: usize = 64;
+/// How long a failed send waits to find out whether the client had just closed
+const CLOSE_CHECK: Duration = Duration::from_millis(100);
 
-    state.peers.remove(&session_id);
-    tracing::info!("Client disconnected: {}", session_id);
+/// A TimeRequest, stamped with its arrival time
+struct TimeProbe {
+    t0: u64,
+    seq: u8,
+    t1: u64,
 }
 
-async fn handle_client_message(
-    msg: ClientMessage, 
-    sender: &mut futures::stream::SplitSink<WebSocket, Message>,
-    state: &SharedState, 
-    session_id: &String,
-    is_dashboard: bool
+/// What the reader task hands the session loop besides time requests
+enum Inbound {
+    Message(Result<ClientMessage, String>),
+    Closed, // Clean Close frame
+    Lost,   // Stream ended or errored without one
+}
+
+/// Whether the reader saw a Close frame (or does within a moment) before the connection ended
+async fn closed_by_client(inbound_rx: &mut mpsc::Receiver<Inbound>) -> bool {
+    let seen = tokio::time::timeout(CLOSE_CHECK, async {
+        while let Some(inbound) = inbound_rx.recv().await {
+            match inbound {
+                Inbound::Closed => return true,
+                Inbound::Lost => return false,
+                Inbound::Message(_) => continue,
+            }
+        }
+        false
+    });
+    seen.await.unwrap_or(false)
+}
+
+/// Read frames as they arrive: stamp each on receipt (before decoding), mark the
+/// peer alive, and send TimeRequests down the fast lane. Ends with the connection.
+async fn read_frames(
+    mut receiver: SplitStream<WebSocket>,
+    codec: Codec,
+    clock: Arc<dyn Clock>,
+    peer: Arc<Peer>,
+    time_tx: mpsc::Sender<TimeProbe>,
+    inbound_tx: mpsc::Sender<Inbound>,
 ) {
+    loop {
+        let incoming = receiver.next().await;
+        let received_at = clock.now_micros();
+        let msg = match incoming {
+            Some(Ok(msg)) => msg,
+            _ => {
+                let _ = inbound_tx.send(Inbound::Lost).await;
+                return;
+            }
+        };
+        peer.touch(received_at);
+        match msg {
+            Message::Binary(_) | Message::Text(_) => {}
+            Message::Close(_) => {
+                let _ = inbound_tx.send(Inbound::Closed).await;
+                return;
+            }
+            _ => continue,
+        }
+        let sent = match codec.decode(&msg) {
+            Ok(ClientMessage::TimeRequest { t0, seq }) => time_tx.send(TimeProbe { t0, seq, t1: received_at }).await.is_ok(),
+            decoded => inbound_tx.send(Inbound::Message(decoded)).await.is_ok(),
+        };
+        if !sent {
+            return;
+        }
+    }
+}
+
+/// Encode with the session's codec and send. Messages the client's protocol
+/// version can't represent are skipped. Returns false if the socket is gone.
+async fn send_server_message(
+    sender: &mut SplitSink<WebSocket, Message>,
+    msg: &ServerMessage,
+    codec: Codec,
+) -> bool {
+    match codec.encode(msg) {
+        Some(frame) => matches!(tokio::time::timeout(SEND_TIMEOUT, sender.send(frame)).await, Ok(Ok(()))),
+        None => true,
+    }
+}
+
+/// Direct-reply channel for one session
+pub type Outbox = mpsc::UnboundedSender<ServerMessage>;
+
+/// Everything a message handler needs to know about the connection it came from
+pub struct Session {
+    pub state: SharedState,
+    pub room: Arc<Room>,
+    pub id: String,
+    pub outbox: Outbox,
+}
+
+impl Session {
+    /// Current role in the room (roles can change mid-session)
+    pub fn role(&self) -> Role {
+        self.room.role_of(&self.id).unwrap_or(Role::Listener)
+    }
+
+    fn reply_error(&self, id: rust_core::messages::RequestId, e: crate::control::CommandError) {
+        tracing::warn!("Request from {} failed: {}", self.id, e);
+        let _ = self.outbox.send(ServerMessage::Error { id, code: e.code, message: e.message });
+    }
+}
+
+/// Apply a message from a client. Replies for that client go to its outbox;
+/// anything meant for the whole room goes out on `room.tx`.
+pub async fn handle_client_message(msg: ClientMessage, session: &Session) {
+    let room = &session.room;
     match msg {
-        ClientMessage::Join { device_id } => {
-            tracing::info!("Device joined: {} ({})", device_id, session_id);
+        ClientMessage::Join { device_id, .. } => {
+            // Handled by the handshake in handle_socket
+            tracing::debug!("Ignoring repeated Join from {} ({})", device_id, session.id);
         }
         ClientMessage::TimeRequest { t0, seq } => {
-            let t1 = get_server_micros();
-            let t2 = get_server_micros();
-            
-            let resp = ServerMessage::TimeResponse { t0, t1, t2, seq };
-            
-            if is_dashboard {
-                if let Ok(json) = serde_json::to_string(&resp) {
-                    let _ = sender.send(Message::Text(json)).await;
-                }
-            } else {
-                if let Ok(data) = bincode::serialize(&resp) {
-                    let _ = sender.send(Message::Binary(data)).await;
+            // Live sockets answer these in handle_socket's fast lane with t1 taken on
+            // arrival; this path only serves callers that drive sessions directly
+            let t1 = room.clock.now_micros();
+            let t2 = room.clock.now_micros();
+            let _ = session.outbox.send(ServerMessage::TimeResponse { t0, t1, t2, seq });
+        }
+        ClientMessage::Telemetry { rtt, offset, drift, status, sync_state } => {
+            let Some(peer) = room.peers.get(&session.id).map(|p| p.clone()) else {
+                return;
+            };
+            let now = room.clock.now_micros();
+            let previous_offset = peer.telemetry.read().unwrap().reported_offset();
+            peer.record_telemetry(now, rtt, offset, drift, status, sync_state);
+            room.broadcast_peer(&session.id);
+
+            let tolerance = session.state.sync_tolerance;
+            if let Some(why) = tolerance.violation(previous_offset, offset, drift) {
+                let cooldown = tolerance.cooldown.as_micros() as u64;
+                if peer.last_resync() == 0 || now.saturating_sub(peer.last_resync()) >= cooldown {
+                    tracing::info!("Session {} out of sync tolerance ({}), requesting resync", session.id, why);
+                    peer.request_resync(now);
                 }
             }
         }
-        ClientMessage::Telemetry { rtt, offset, .. } => {
-             if let Some(peer) = state.peers.get(session_id) {
-                peer.rtt.store(rtt, Ordering::Relaxed);
-                peer.offset.store(offset as u64, Ordering::Relaxed);
-             }
-        }
-        ClientMessage::PlayRequest { track_url, delay_ms } => {
-            // ... (keep existing logic for external URLs if needed, or deprecate)
-             let state = state.clone();
-            let session_id = session_id.clone();
-            
+        ClientMessage::PlayRequest { id, track_url, delay_ms } => {
+            let allowed = crate::control::authorize_control(session.role()).and_then(|()| crate::control::check_delay(delay_ms));
+            if let Err(e) = allowed {
+                return session.reply_error(id, e);
+            }
+            let state = session.state.clone();
+            let room = room.clone();
+            let outbox = session.outbox.clone();
+            let session_id = session.id.clone();
+
             // Spawn resolution in a separate task so we don't block the heartbeats
             tokio::spawn(async move {
-                 // ... (keep existing URL resolution logic)
-                 // For now, just broadcasting PlayCommand as before but mapping to new fields
-                 let now = get_server_micros();
-                 let start_time = now + (delay_ms * 1000); 
-                 
-                 let cmd = ServerMessage::PlayCommand {
-                    track_url,
-                    start_at_server_time: start_time,
-                    start_at_position_ms: 0, 
-                    server_time_at_broadcast: now,
+                let (track_url, track) = if crate::resolve::needs_resolution(&track_url) {
+                    match state.resolver.resolve(&track_url).await {
+                        Ok(resolved) => (resolved.stream_url, resolved.track),
+                        Err(e) => {
+                            tracing::warn!("Request from {} failed: {}", session_id, e);
+                            let e = crate::control::CommandError::from(e);
+                            let _ = outbox.send(ServerMessage::Error { id, code: e.code, message: e.message });
+                            return;
+                        }
+                    }
+                } else {
+                    let track = Track::from_url(&track_url, SourceKind::Url);
+                    (track_url, track)
                 };
-                let _ = state.tx.send(cmd);
+                let track_url = room.media.proxy(&track_url, &track);
+                // The delay counts from when the track is ready, not from the request
+                let now = room.clock.now_micros();
+                let start_time = now + delay_ms * 1000; // Bounded by check_delay
+                {
+                    // An ad hoc track takes over from the queue
+                    let mut pb = room.playback_state.write().unwrap();
+                    pb.load(track_url.clone(), track.clone());
+                    pb.is_playing = true;
+                    pb.position_ms = 0;
+                    pb.last_update_time = start_time;
+                    let mut queue = room.queue.lock().unwrap();
+                    crate::queue::schedule_advance(&room, &pb, &mut queue);
+
+                    let cmd = ServerMessage::PlayCommand {
+                        track_url,
+                        start_at_server_time: start_time,
+                        start_at_position_ms: 0,
+                        server_time_at_broadcast: now,
+                        track,
+                    };
+                    let _ = room.tx.send(cmd);
+                    crate::queue::detach_queue(&room, &mut queue);
+                }
+                let _ = outbox.send(ServerMessage::Ack { id });
             });
         }
-        ClientMessage::CommandRequest { cmd } => {
-            crate::control::process_control_command(state, cmd);
-        }
-    }
-}
+        ClientMessage::CommandRequest { id, cmd } if crate::control::queues_page_url(&cmd) => {
+            if let Err(e) = crate::control::authorize_control(session.role()) {
+                return session.reply_error(id, e);
+            }
+            let state = session.state.clone();
+            let room = room.clone();
+            let outbox = session.outbox.clone();
+            let session_id = session.id.clone();
+            let role = session.role();
 
-async fn resolve_audio_url(url: &str) -> anyhow::Result<String> {
-    use tokio::process::Command;
-    use std::process::Stdio;
-
-    let output = Command::new("yt-dlp")
-        .arg("-g")
-        .arg("--format")
-        .arg("bestaudio")
-        .arg(url)
-        .stdout(Stdio::piped())
-        .stderr(Stdio::piped())
-        .spawn()?
-        .wait_with_output()
-        .await?;
-
-    if output.status.success() {
-        let stdout = String::from_utf8(output.stdout)?;
-        // Take ONLY the first line (first URL) in case yt-dlp returns multiple
-        let first_url = stdout.lines().next().unwrap_or("").trim().to_string();
-        if first_url.is_empty() {
-             anyhow::bail!("yt-dlp returned empty URL")
-        }
-        Ok(first_url)
-    } else {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("yt-dlp failed: {}", stderr)
+            // Resolving can take seconds; like PlayRequest, don't hold up the heartbeats
+            tokio::spawn(async move {
+                match crate::control::process_resolving(&state.resolver, &room, role, cmd).await {
+                    Ok(()) => {
+                        let _ = outbox.send(ServerMessage::Ack { id });
+                    }
+                    Err(e) => {
+                        tracing::warn!("Request from {} failed: {}", session_id, e);
+                        let _ = outbox.send(ServerMessage::Error { id, code: e.code, message: e.message });
+                    }
+                }
+            });
+        }
+        ClientMessage::CommandRequest { id, cmd } => {
+            match crate::control::process_control_command(room, session.role(), cmd) {
+                Ok(()) => {
+                    let _ = session.outbox.send(ServerMessage::Ack { id });
+                }
+                Err(e) => session.reply_error(id, e),
+            }
+        }
+        ClientMessage::SetRole { id, session_id, role } => {
+            match crate::control::assign_role(room, session.role(), &session_id, role) {
+                Ok(()) => {
+                    tracing::info!("{} set {} to {:?} in room {}", session.id, session_id, role, room.id);
+                    let _ = session.outbox.send(ServerMessage::Ack { id });
+                }
+                Err(e) => session.reply_error(id, e),
+            }
+        }
     }
 }
-
-// Helper: Get monotonic-like time in micros
-fn get_server_micros() -> u64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_micros() as u64
-}
-
-
@@ -1,11 +1,16 @@
 pub mod app_state;
 pub mod handlers;
+pub mod media;
 pub mod stream;
 pub mod control;
+pub mod peers;
+pub mod probe;
+pub mod queue;
+pub mod resolve;
 pub mod routes;
+pub mod udp_time;
 
 use std::net::SocketAddr;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 pub use app_state::AppState; // Re-export for convenience
 
@@ -13,9 +18,14 @@ pub async fn run(port: u16, app_state: app_state::SharedState) {
     // Initialize tracing if not already initialized
     // ...
     
-    let app = routes::create_router(app_state);
-
     let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    // Same port number over UDP; SONICSYNC_UDP_TIME=0 turns it off
+    if std::env::var("SONICSYNC_UDP_TIME").as_deref() != Ok("0") {
+        if let Err(e) = udp_time::start(app_state.clone(), addr).await {
+            tracing::warn!("UDP time sync unavailable, clients will sync over WebSocket: {}", e);
+        }
+    }
+    let app = routes::create_router(app_state);
     tracing::info!("Server listening on {}", addr);
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
@@ -10,6 +10,6 @@ async fn main() {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let state = server::app_state::AppState::new();
+    let state = server::app_state::AppState::with_config(server::app_state::AppStateConfig::from_env());
     server::run(3000, state).await;
 }
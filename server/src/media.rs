@@ -0,0 +1,412 @@
+use crate::app_state::SharedState;
+use axum::{
+    body::{Body, Bytes},
+    extract::{Path, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use rust_core::track::Track;
+use std::collections::VecDeque;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Where URLs are rewritten to when the proxy serves a track (relative, like "stream")
+pub const MEDIA_PATH: &str = "media";
+
+/// Optional proxy: the server downloads each track once and serves it to every client
+#[derive(Debug, Clone)]
+pub struct MediaConfig {
+    pub enabled: bool,
+    pub cache_dir: PathBuf,
+    pub max_entries: usize,   // Tracks kept on disk; the least recently used are dropped first
+    pub max_bytes: u64,       // Larger downloads are abandoned (e.g. endless radio streams)
+    pub fetch_timeout: Duration,
+    pub allow_private: bool,  // Also fetch from loopback, link-local and private addresses
+}
+
+impl Default for MediaConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cache_dir: std::env::temp_dir().join("sonicsync-media"),
+            max_entries: 32,
+            max_bytes: 200 * 1024 * 1024,
+            fetch_timeout: Duration::from_secs(120),
+            allow_private: false,
+        }
+    }
+}
+
+impl MediaConfig {
+    /// Off unless SONICSYNC_MEDIA_PROXY=1; limits overridden by SONICSYNC_MEDIA_CACHE_DIR /
+    /// SONICSYNC_MEDIA_CACHE_ENTRIES / SONICSYNC_MEDIA_MAX_MB / SONICSYNC_MEDIA_FETCH_TIMEOUT_MS.
+    /// Only public addresses are fetched from unless SONICSYNC_MEDIA_ALLOW_PRIVATE=1.
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok()?.parse::<u64>().ok();
+        let default = Self::default();
+        Self {
+            enabled: std::env::var("SONICSYNC_MEDIA_PROXY").as_deref() == Ok("1"),
+            cache_dir: std::env::var_os("SONICSYNC_MEDIA_CACHE_DIR").map_or(default.cache_dir, PathBuf::from),
+            max_entries: var("SONICSYNC_MEDIA_CACHE_ENTRIES").map_or(default.max_entries, |n| n as usize),
+            max_bytes: var("SONICSYNC_MEDIA_MAX_MB").map_or(default.max_bytes, |mb| mb * 1024 * 1024),
+            fetch_timeout: var("SONICSYNC_MEDIA_FETCH_TIMEOUT_MS").map_or(default.fetch_timeout, Duration::from_millis),
+            allow_private: std::env::var("SONICSYNC_MEDIA_ALLOW_PRIVATE").as_deref() == Ok("1"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Fetch {
+    Pending,
+    Receiving { content_type: Option<String>, bytes: u64 }, // Written to the part file so far
+    Ready { content_type: Option<String> },                 // Upstream's, if it sent one
+    Failed(String),
+}
+
+#[derive(Debug)]
+struct Entry {
+    id: String,
+    generation: u64,           // Tells this fetch of `id` apart from earlier, abandoned ones
+    mime_type: Option<String>, // From the track, preferred over upstream's guess
+    status: watch::Receiver<Fetch>,
+    task: JoinHandle<()>,
+}
+
+type Entries = Arc<Mutex<VecDeque<Entry>>>; // Least recently used first
+
+/// Downloads upstream audio once per track and serves it from /media/{track_id}
+pub struct MediaProxy {
+    config: MediaConfig,
+    client: reqwest::Client,
+    entries: Entries,
+    next_generation: AtomicU64,
+}
+
+impl Default for MediaProxy {
+    fn default() -> Self {
+        Self::new(MediaConfig::default())
+    }
+}
+
+impl MediaProxy {
+    pub fn new(config: MediaConfig) -> Self {
+        let client = if config.allow_private {
+            reqwest::Client::new()
+        } else {
+            // /media is open to anyone, so it mustn't become a way into the server's own network
+            reqwest::Client::builder()
+                .dns_resolver(Arc::new(PublicOnly))
+                .redirect(reqwest::redirect::Policy::custom(|attempt| {
+                    if attempt.previous().len() >= 10 {
+                        attempt.error("too many redirects")
+                    } else if names_private_address(attempt.url()) {
+                        attempt.error("redirected to a non-public address")
+                    } else {
+                        attempt.follow()
+                    }
+                }))
+                .build()
+                .expect("HTTP client")
+        };
+        Self {
+            config,
+            client,
+            entries: Arc::new(Mutex::new(VecDeque::new())),
+            next_generation: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &MediaConfig {
+        &self.config
+    }
+
+    /// URL clients should fetch `track_url` from: "media/{id}" for web URLs while the
+    /// proxy is on (starting the download if it isn't cached), else `track_url` itself
+    pub fn proxy(&self, track_url: &str, track: &Track) -> String {
+        let is_web = track_url.starts_with("http://") || track_url.starts_with("https://");
+        // Ids become file names, so only take the ones we generate
+        let safe_id = !track.id.is_empty() && track.id.bytes().all(|b| b.is_ascii_alphanumeric());
+        if !self.config.enabled || !is_web || !safe_id {
+            return track_url.to_string();
+        }
+        // Clients can reach a LAN address themselves; the server won't fetch it for them
+        if !self.config.allow_private && reqwest::Url::parse(track_url).is_ok_and(|url| names_private_address(&url)) {
+            return track_url.to_string();
+        }
+        // Controls can run on threads outside the runtime (the Android bridge)
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::warn!("No runtime to fetch {} on, clients will fetch it themselves", track.id);
+            return track_url.to_string();
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        // Cached or on its way; a failed fetch is retried (e.g. with a freshly resolved URL)
+        if let Some(entry) = touch(&mut entries, &track.id) {
+            if !matches!(*entry.status.borrow(), Fetch::Failed(_)) {
+                return self.path_for(&track.id);
+            }
+            if let Some(old) = entries.pop_back() {
+                self.discard(old);
+            }
+        }
+        while entries.len() >= self.config.max_entries.max(1) {
+            let Some(old) = entries.pop_front() else { break };
+            self.discard(old);
+        }
+
+        let generation = self.next_generation.fetch_add(1, Ordering::Relaxed);
+        let job = Job {
+            id: track.id.clone(),
+            generation,
+            upstream: track_url.to_string(),
+            part: self.part_for(&track.id, generation),
+            file: self.file_for(&track.id),
+            entries: self.entries.clone(),
+        };
+        let (tx, status) = watch::channel(Fetch::Pending);
+        tracing::info!("Media proxy fetching {} from {}", track.id, track_url);
+        // The task can't finish before the entry is in place: it needs this lock to install the file
+        let task = runtime.spawn(fetch(self.client.clone(), self.config.clone(), job, tx));
+        entries.push_back(Entry { id: track.id.clone(), generation, mime_type: track.mime_type.clone(), status, task });
+        self.path_for(&track.id)
+    }
+
+    /// Stop an entry's download and delete whatever it left on disk.
+    /// Called with the entries locked, so its fetch can't be installing the file meanwhile.
+    fn discard(&self, old: Entry) {
+        old.task.abort();
+        let _ = std::fs::remove_file(self.file_for(&old.id));
+        let _ = std::fs::remove_file(self.part_for(&old.id, old.generation));
+    }
+
+    fn path_for(&self, id: &str) -> String {
+        format!("{}/{}", MEDIA_PATH, id)
+    }
+
+    fn file_for(&self, id: &str) -> PathBuf {
+        self.config.cache_dir.join(id)
+    }
+
+    /// Each fetch downloads to its own file, so a retry never writes into an abandoned one's
+    fn part_for(&self, id: &str, generation: u64) -> PathBuf {
+        self.config.cache_dir.join(format!("{}.{}.part", id, generation))
+    }
+
+    /// An entry's MIME type, part file and status
+    fn lookup(&self, id: &str) -> Option<(Option<String>, PathBuf, watch::Receiver<Fetch>)> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = touch(&mut entries, id)?;
+        Some((entry.mime_type.clone(), self.part_for(id, entry.generation), entry.status.clone()))
+    }
+}
+
+/// Move `id`'s entry to the back, so the tracks being played are the last to be evicted
+fn touch<'a>(entries: &'a mut VecDeque<Entry>, id: &str) -> Option<&'a Entry> {
+    let i = entries.iter().position(|e| e.id == id)?;
+    let entry = entries.remove(i)?;
+    entries.push_back(entry);
+    entries.back()
+}
+
+/// Anything but a unicast address on the public internet
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                || (a == 100 && b & 0xc0 == 64) // Carrier-grade NAT
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_private(ip.into()),
+            None => ip.is_loopback() || ip.is_unspecified() || ip.is_multicast() || ip.is_unique_local() || ip.is_unicast_link_local(),
+        },
+    }
+}
+
+/// Whether the URL's host is a literal non-public address. Names are checked as they resolve.
+fn names_private_address(url: &reqwest::Url) -> bool {
+    let host = url.host_str().unwrap_or_default();
+    host.trim_start_matches('[').trim_end_matches(']').parse().is_ok_and(is_private)
+}
+
+/// Resolves names like the system does, minus any non-public addresses
+struct PublicOnly;
+
+impl reqwest::dns::Resolve for PublicOnly {
+    fn resolve(&self, name: reqwest::dns::Name) -> reqwest::dns::Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.filter(|a| !is_private(a.ip())).collect();
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", host).into());
+            }
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+/// One fetch of a track: where it downloads to, and how to tell it's still wanted
+struct Job {
+    id: String,
+    generation: u64,
+    upstream: String,
+    part: PathBuf,
+    file: PathBuf,
+    entries: Entries,
+}
+
+impl Job {
+    /// Move the finished download into place, unless its entry was evicted or retried meanwhile
+    fn install(&self) -> anyhow::Result<()> {
+        // Eviction happens under this lock too, so it can't slip between the check and the rename
+        let entries = self.entries.lock().unwrap();
+        if !entries.iter().any(|e| e.id == self.id && e.generation == self.generation) {
+            anyhow::bail!("dropped from the cache before it finished");
+        }
+        std::fs::rename(&self.part, &self.file)?;
+        Ok(())
+    }
+}
+
+/// Deletes a part file however its fetch ends, including by being aborted
+struct PartFile<'a>(&'a std::path::Path);
+
+impl Drop for PartFile<'_> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.0);
+    }
+}
+
+async fn fetch(client: reqwest::Client, config: MediaConfig, job: Job, tx: watch::Sender<Fetch>) {
+    let _part = PartFile(&job.part);
+    let download = download(&client, &job.upstream, &job.part, config.max_bytes, &tx);
+    let outcome = match tokio::time::timeout(config.fetch_timeout, download).await {
+        Ok(Ok(content_type)) => match job.install() {
+            Ok(()) => Fetch::Ready { content_type },
+            Err(e) => Fetch::Failed(e.to_string()),
+        },
+        Ok(Err(e)) => Fetch::Failed(e.to_string()),
+        Err(_) => Fetch::Failed(format!("timed out after {}ms", config.fetch_timeout.as_millis())),
+    };
+    if let Fetch::Failed(why) = &outcome {
+        tracing::warn!("Media proxy couldn't fetch {}: {}", job.upstream, why);
+    }
+    let _ = tx.send(outcome);
+}
+
+/// Download to `part`, reporting progress on `tx`; returns upstream's Content-Type
+async fn download(
+    client: &reqwest::Client,
+    upstream: &str,
+    part: &std::path::Path,
+    max_bytes: u64,
+    tx: &watch::Sender<Fetch>,
+) -> anyhow::Result<Option<String>> {
+    let mut response = client.get(upstream).send().await?.error_for_status()?;
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    if let Some(dir) = part.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let mut out = tokio::fs::File::create(part).await?;
+    let mut total = 0u64;
+    tx.send_replace(Fetch::Receiving { content_type: content_type.clone(), bytes: 0 });
+    while let Some(chunk) = response.chunk().await? {
+        total += chunk.len() as u64;
+        if total > max_bytes {
+            anyhow::bail!("larger than {} bytes", max_bytes);
+        }
+        out.write_all(&chunk).await?;
+        // Readers following the part file must find the chunk there once they hear of it
+        out.flush().await?;
+        tx.send_replace(Fetch::Receiving { content_type: content_type.clone(), bytes: total });
+    }
+    Ok(content_type)
+}
+
+// GET /media/{track_id}: a proxied track, with Range and conditional request support.
+// While it's still downloading, a request for the whole track gets what has arrived
+// and then the rest as it lands; anything else waits for the download to finish.
+pub async fn serve_media(State(state): State<SharedState>, Path(id): Path<String>, req: Request) -> Response {
+    let Some((mime_type, part, mut status)) = state.media.lookup(&id) else {
+        return (StatusCode::NOT_FOUND, "No such track").into_response();
+    };
+    let mut outcome = status.wait_for(|s| *s != Fetch::Pending).await.map(|s| s.clone());
+    if let Ok(Fetch::Receiving { content_type, .. }) = &outcome {
+        if !req.headers().contains_key(header::RANGE) {
+            // Fails if the download finished and was moved into place meanwhile
+            if let Ok(file) = tokio::fs::File::open(&part).await {
+                return follow_download(file, status, mime_type.or(content_type.clone()));
+            }
+        }
+        outcome = status
+            .wait_for(|s| !matches!(s, Fetch::Pending | Fetch::Receiving { .. }))
+            .await
+            .map(|s| s.clone());
+    }
+    let content_type = match outcome {
+        Ok(Fetch::Ready { content_type }) => mime_type.or(content_type),
+        Ok(Fetch::Failed(why)) => return (StatusCode::BAD_GATEWAY, format!("Upstream fetch failed: {}", why)).into_response(),
+        // The entry was dropped while we waited
+        Ok(Fetch::Pending | Fetch::Receiving { .. }) | Err(_) => return (StatusCode::NOT_FOUND, "No such track").into_response(),
+    };
+
+    // Detecting the type from a cached file's bytes is the last resort
+    crate::stream::serve_file(&state.media.file_for(&id), req, content_type).await
+}
+
+/// Stream a part file as its download writes it, until the download ends. Its length isn't
+/// known yet, so the body is chunked; a failed or evicted download cuts it short.
+fn follow_download(mut file: tokio::fs::File, mut status: watch::Receiver<Fetch>, content_type: Option<String>) -> Response {
+    let body = async_stream::stream! {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            // Seen before reading, so a chunk written after the read still wakes us below
+            let seen = status.borrow_and_update().clone();
+            let n = match file.read(&mut buf).await {
+                Ok(n) => n,
+                Err(e) => {
+                    yield Err(e);
+                    break;
+                }
+            };
+            if n > 0 {
+                yield Ok::<_, std::io::Error>(Bytes::copy_from_slice(&buf[..n]));
+                continue;
+            }
+            match seen {
+                Fetch::Ready { .. } => break,
+                Fetch::Failed(why) => {
+                    yield Err(std::io::Error::other(why));
+                    break;
+                }
+                Fetch::Pending | Fetch::Receiving { .. } => {}
+            }
+            if status.changed().await.is_err() {
+                yield Err(std::io::Error::other("dropped from the cache"));
+                break;
+            }
+        }
+    };
+    let mut response = Body::from_stream(body).into_response();
+    if let Some(value) = content_type.and_then(|t| HeaderValue::from_str(&t).ok()) {
+        response.headers_mut().insert(header::CONTENT_TYPE, value);
+    }
+    response
+}
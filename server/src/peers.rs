@@ -0,0 +1,41 @@
+use axum::{
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::IntoResponse,
+    Json,
+};
+use crate::app_state::{SharedState, DEFAULT_ROOM};
+use crate::control::{authorize_rest, error_response};
+use std::collections::HashMap;
+
+// GET /peers?room=: the room's roster, for operators. Same bearer token as /control.
+pub async fn list_peers(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let room_id = query.get("room").map(String::as_str).unwrap_or(DEFAULT_ROOM);
+    let Some(room) = state.room(room_id) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Err(e) = authorize_rest(&room, &headers) {
+        return error_response(e);
+    }
+    Json(room.roster()).into_response()
+}
+
+// GET /peers/departed?room=: recent sessions that ended, with the reason
+pub async fn list_departures(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let room_id = query.get("room").map(String::as_str).unwrap_or(DEFAULT_ROOM);
+    let Some(room) = state.room(room_id) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    if let Err(e) = authorize_rest(&room, &headers) {
+        return error_response(e);
+    }
+    Json(room.departures()).into_response()
+}
@@ -0,0 +1,215 @@
+use rust_core::track::{SourceKind, Track};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+/// How much of a file's start and end is read to find its headers
+const HEAD_LEN: u64 = 64 * 1024;
+const TAIL_LEN: u64 = 72 * 1024; // At least one whole Ogg page
+
+/// Layer III bitrates in kbps by header index; 0 is "free format", which we can't time
+const MPEG1_KBPS: [u64; 15] = [0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320];
+const MPEG2_KBPS: [u64; 15] = [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+
+/// A hosted file's track: title and MIME type from its name (or its first bytes),
+/// duration from its container headers. Nothing is decoded, so this is cheap.
+pub fn describe_file(path: &str) -> Track {
+    let mut track = Track::from_url(path, SourceKind::Hosted);
+    let Ok(mut file) = File::open(path) else {
+        return track;
+    };
+    let Some(head) = read_at(&mut file, 0, HEAD_LEN) else {
+        return track;
+    };
+    if track.mime_type.is_none() {
+        track.mime_type = sniff_audio(&head).map(String::from);
+    }
+    track.duration_ms = duration_ms(&mut file, &head);
+    track
+}
+
+/// Recognize common audio containers by their magic bytes
+pub(crate) fn sniff_audio(head: &[u8]) -> Option<&'static str> {
+    let mime = match head {
+        [b'I', b'D', b'3', ..] => "audio/mpeg",
+        [0xFF, b, ..] if b & 0xE0 == 0xE0 && b & 0x06 != 0 => "audio/mpeg", // MPEG frame sync, layer set
+        [0xFF, b, ..] if b & 0xF6 == 0xF0 => "audio/aac",                     // ADTS
+        [b'f', b'L', b'a', b'C', ..] => "audio/flac",
+        [b'O', b'g', b'g', b'S', ..] => "audio/ogg",
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "audio/wav",
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => "audio/mp4",
+        [0x1A, 0x45, 0xDF, 0xA3, ..] => "audio/webm",
+        _ => return None,
+    };
+    Some(mime)
+}
+
+fn duration_ms(file: &mut File, head: &[u8]) -> Option<u64> {
+    match head {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => wav(head),
+        [b'f', b'L', b'a', b'C', ..] => flac(head),
+        [b'O', b'g', b'g', b'S', ..] => ogg(file, head),
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => mp4(file),
+        _ => mp3(file, head),
+    }
+}
+
+/// Data chunk size over the byte rate in the fmt chunk
+fn wav(head: &[u8]) -> Option<u64> {
+    let mut at = 12;
+    let mut byte_rate = None;
+    while let (Some(id), Some(size)) = (head.get(at..at + 4), le32(head, at + 4)) {
+        match id {
+            b"fmt " => byte_rate = le32(head, at + 16),
+            b"data" => return millis(size as u64, byte_rate? as u64),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        at += 8 + size as usize + (size as usize & 1);
+    }
+    None
+}
+
+/// Total samples over the sample rate, both in STREAMINFO (always the first metadata block)
+fn flac(head: &[u8]) -> Option<u64> {
+    if head.get(4)? & 0x7F != 0 {
+        return None;
+    }
+    let info = head.get(8..42)?;
+    let rate = (info[10] as u64) << 12 | (info[11] as u64) << 4 | (info[12] as u64) >> 4;
+    let samples = ((info[13] & 0x0F) as u64) << 32 | be32(info, 14)? as u64;
+    millis(samples, rate)
+}
+
+/// The last page's granule position over the rate in the first page's codec header
+fn ogg(file: &mut File, head: &[u8]) -> Option<u64> {
+    let packet = head.get(27 + *head.get(26)? as usize..)?;
+    let (rate, pre_skip) = if packet.starts_with(b"\x01vorbis") {
+        (le32(packet, 12)? as u64, 0)
+    } else if packet.starts_with(b"OpusHead") {
+        // Opus always counts at 48kHz, including the samples the decoder drops at the start
+        (48_000, le16(packet, 10)? as u64)
+    } else {
+        return None;
+    };
+    let len = file.metadata().ok()?.len();
+    let tail = read_at(file, len.saturating_sub(TAIL_LEN), TAIL_LEN)?;
+    let last_page = tail.windows(4).rposition(|w| w == b"OggS")?;
+    millis(le64(&tail, last_page + 6)?.checked_sub(pre_skip)?, rate)
+}
+
+/// Duration over the timescale in moov/mvhd
+fn mp4(file: &mut File) -> Option<u64> {
+    let len = file.metadata().ok()?.len();
+    let (moov, moov_end) = find_box(file, 0, len, b"moov")?;
+    let (mvhd, _) = find_box(file, moov, moov_end, b"mvhd")?;
+    let header = read_at(file, mvhd, 32)?;
+    let (timescale, duration) = match header.first()? {
+        0 => (be32(&header, 12)?, be32(&header, 16)? as u64),
+        1 => (be32(&header, 20)?, be64(&header, 24)?),
+        _ => return None,
+    };
+    millis(duration, timescale as u64)
+}
+
+/// Where the contents of the first `kind` box between `start` and `end` start and end
+fn find_box(file: &mut File, start: u64, end: u64, kind: &[u8; 4]) -> Option<(u64, u64)> {
+    let mut at = start;
+    while at.checked_add(8)? <= end {
+        let header = read_at(file, at, 16)?;
+        let (size, header_len) = match be32(&header, 0)? {
+            0 => (end - at, 8), // Runs to the end
+            1 => (be64(&header, 8)?, 16),
+            size => (size as u64, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+        if header.get(4..8)? == kind {
+            return Some((at + header_len, at + size));
+        }
+        at = at.checked_add(size)?;
+    }
+    None
+}
+
+/// Frame count from a Xing/Info or VBRI header if the first frame has one,
+/// else the first frame's bitrate taken as constant
+fn mp3(file: &mut File, head: &[u8]) -> Option<u64> {
+    // Audio starts after any ID3v2 tag, which can be far longer than `head` when it has cover art
+    let tag_len = match head {
+        [b'I', b'D', b'3', _, _, flags, size @ ..] => {
+            let size = size.get(..4)?.iter().fold(0u64, |n, &b| n << 7 | (b & 0x7F) as u64);
+            10 + size + if flags & 0x10 != 0 { 10 } else { 0 }
+        }
+        _ => 0,
+    };
+    let after_tag = read_at(file, tag_len, 4096)?;
+    let sync = after_tag.windows(2).position(|w| w[0] == 0xFF && w[1] & 0xE0 == 0xE0)?;
+    let frame = &after_tag[sync..];
+    let [0xFF, b1, b2, b3, ..] = *frame else {
+        return None;
+    };
+    let version = (b1 >> 3) & 0x03; // 3: MPEG-1, 2: MPEG-2, 0: MPEG-2.5
+    if version == 1 || b1 & 0x06 != 0x02 {
+        return None; // Reserved, or not Layer III
+    }
+    let mpeg1 = version == 3;
+    let kbps_by_index = if mpeg1 { MPEG1_KBPS } else { MPEG2_KBPS };
+    let kbps = kbps_by_index.get((b2 >> 4) as usize).copied().filter(|&k| k != 0)?;
+    // MPEG-2 halves MPEG-1's rates and MPEG-2.5 quarters them
+    let rate = [44_100, 48_000, 32_000].get(((b2 >> 2) & 0x03) as usize)? >> match version {
+        3 => 0,
+        2 => 1,
+        _ => 2,
+    };
+    let samples_per_frame = if mpeg1 { 1152 } else { 576 };
+
+    let mono = b3 >> 6 == 3;
+    let xing = 4 + match (mpeg1, mono) {
+        (true, false) => 32,
+        (true, true) | (false, false) => 17,
+        (false, true) => 9,
+    };
+    if matches!(frame.get(xing..xing + 4), Some(b"Xing" | b"Info")) && be32(frame, xing + 4)? & 1 != 0 {
+        return millis(be32(frame, xing + 8)? as u64 * samples_per_frame, rate);
+    }
+    if frame.get(36..40) == Some(&b"VBRI"[..]) {
+        return millis(be32(frame, 50)? as u64 * samples_per_frame, rate);
+    }
+    let audio_len = file.metadata().ok()?.len().checked_sub(tag_len + sync as u64)?;
+    // kbps is bits per millisecond
+    (audio_len * 8).checked_div(kbps).filter(|&ms| ms > 0)
+}
+
+/// `units` at `per_second` in milliseconds; None if that's nothing or can't be worked out
+fn millis(units: u64, per_second: u64) -> Option<u64> {
+    units.checked_mul(1000)?.checked_div(per_second).filter(|&ms| ms > 0)
+}
+
+/// Up to `len` bytes from `offset`
+fn read_at(file: &mut File, offset: u64, len: u64) -> Option<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset)).ok()?;
+    let mut bytes = Vec::new();
+    file.by_ref().take(len).read_to_end(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn le64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn be32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be64(bytes: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(bytes.get(at..at + 8)?.try_into().ok()?))
+}
@@ -0,0 +1,290 @@
+use crate::app_state::{PlaybackState, Room};
+use rand::seq::SliceRandom;
+use rust_core::messages::{QueueEntry, RepeatMode, ServerMessage};
+use rust_core::track::Track;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// The next track's PlayCommand goes out this long before the current one ends,
+/// so clients can buffer it and start right on the boundary
+pub const ADVANCE_LEAD: Duration = Duration::from_secs(2);
+
+/// A room's play queue, plus the timer that moves it along when a track ends
+#[derive(Debug, Default)]
+pub struct PlayQueue {
+    entries: Vec<QueueEntry>,
+    current: Option<usize>,
+    // Nothing from the queue is playing: the current entry was removed, or an ad hoc
+    // track replaced it. `current` points at the entry Next and auto-advance follow on
+    // from (None to start from the top).
+    detached: bool,
+    repeat: RepeatMode,
+    next_id: u32,
+    // Bumped whenever the timer is replaced, so a timer that already woke can tell it's stale
+    generation: u64,
+    timer: Option<JoinHandle<()>>,
+}
+
+impl PlayQueue {
+    pub fn entries(&self) -> &[QueueEntry] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entry that's playing or paused, if any
+    pub fn current(&self) -> Option<&QueueEntry> {
+        if self.detached {
+            return None;
+        }
+        self.current.and_then(|i| self.entries.get(i))
+    }
+
+    pub fn get(&self, entry_id: u32) -> Option<&QueueEntry> {
+        self.entries.iter().find(|e| e.entry_id == entry_id)
+    }
+
+    pub fn repeat(&self) -> RepeatMode {
+        self.repeat
+    }
+
+    pub fn set_repeat(&mut self, mode: RepeatMode) {
+        self.repeat = mode;
+    }
+
+    /// Append a track; returns its entry id
+    pub fn add(&mut self, track_url: String, track: Track) -> u32 {
+        self.next_id += 1;
+        let entry_id = self.next_id;
+        self.entries.push(QueueEntry { entry_id, track_url, track });
+        entry_id
+    }
+
+    /// False if there's no such entry. Removing the current entry doesn't stop it;
+    /// the queue just moves on to whatever followed it.
+    pub fn remove(&mut self, entry_id: u32) -> bool {
+        let Some(index) = self.index_of(entry_id) else {
+            return false;
+        };
+        self.entries.remove(index);
+        if let Some(current) = self.current {
+            if index < current {
+                self.current = Some(current - 1);
+            } else if index == current && !self.detached {
+                self.current = current.checked_sub(1);
+                self.detached = true;
+            } else if index == current {
+                // Detached cursor sat on this entry (the one before the removed current)
+                self.current = current.checked_sub(1);
+            }
+        }
+        true
+    }
+
+    /// Move an entry to `to_index` (clamped to the end). False if there's no such entry.
+    pub fn move_entry(&mut self, entry_id: u32, to_index: usize) -> bool {
+        let Some(from) = self.index_of(entry_id) else {
+            return false;
+        };
+        let current_id = self.current.map(|i| self.entries[i].entry_id);
+        let entry = self.entries.remove(from);
+        self.entries.insert(to_index.min(self.entries.len()), entry);
+        self.current = current_id.and_then(|id| self.index_of(id));
+        true
+    }
+
+    /// Shuffle the entries after the current one (all of them if nothing has played)
+    pub fn shuffle(&mut self) {
+        let start = self.current.map_or(0, |i| i + 1);
+        if let Some(upcoming) = self.entries.get_mut(start..) {
+            upcoming.shuffle(&mut rand::thread_rng());
+        }
+    }
+
+    /// Make the next entry current. `auto` is the end of a track, where RepeatMode::One
+    /// replays it; an explicit Next always moves on. None at the end of the queue.
+    pub fn advance(&mut self, auto: bool) -> Option<&QueueEntry> {
+        let next = self.next_index(auto)?;
+        self.select(next)
+    }
+
+    /// Entry the queue will move on to when the current one ends, without moving
+    pub fn upcoming(&self) -> Option<&QueueEntry> {
+        self.next_index(true).and_then(|i| self.entries.get(i))
+    }
+
+    /// Make the previous entry current, wrapping unless RepeatMode::Off
+    pub fn back(&mut self) -> Option<&QueueEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let previous = match self.current {
+            None => 0,
+            Some(i) if self.detached => i,
+            Some(0) if self.repeat != RepeatMode::Off => self.entries.len() - 1,
+            Some(i) => i.saturating_sub(1),
+        };
+        self.select(previous)
+    }
+
+    /// The current entry, or the first one if nothing has played yet
+    pub fn start(&mut self) -> Option<&QueueEntry> {
+        match self.current {
+            Some(i) if !self.detached => self.select(i),
+            _ => self.advance(false),
+        }
+    }
+
+    pub fn snapshot(&self) -> ServerMessage {
+        ServerMessage::QueueUpdate {
+            entries: self.entries.clone(),
+            current_entry: self.current().map(|e| e.entry_id),
+            repeat: self.repeat,
+        }
+    }
+
+    /// Something from outside the queue is playing instead: nothing is current any more,
+    /// but Next still moves on from the entry that was. False if nothing was current.
+    pub fn detach(&mut self) -> bool {
+        if self.current().is_none() {
+            return false;
+        }
+        self.detached = true;
+        true
+    }
+
+    /// Stop the auto-advance timer, if one is running
+    pub fn cancel_advance(&mut self) {
+        self.generation += 1;
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+    }
+
+    fn select(&mut self, index: usize) -> Option<&QueueEntry> {
+        self.current = Some(index);
+        self.detached = false;
+        self.entries.get(index)
+    }
+
+    fn next_index(&self, auto: bool) -> Option<usize> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        let next = match self.current {
+            None => 0,
+            Some(i) if auto && self.repeat == RepeatMode::One && !self.detached => i,
+            Some(i) => i + 1,
+        };
+        if next < self.entries.len() {
+            Some(next)
+        } else if self.repeat != RepeatMode::Off {
+            Some(0)
+        } else {
+            None
+        }
+    }
+
+    fn index_of(&self, entry_id: u32) -> Option<usize> {
+        self.entries.iter().position(|e| e.entry_id == entry_id)
+    }
+}
+
+pub fn broadcast_queue(room: &Room, queue: &PlayQueue) {
+    let _ = room.tx.send(queue.snapshot());
+}
+
+/// A track from outside the queue took over: tell everyone nothing in it is playing now
+pub fn detach_queue(room: &Room, queue: &mut PlayQueue) {
+    if queue.detach() {
+        broadcast_queue(room, queue);
+    }
+}
+
+/// Load `entry` as the room's track, have everyone start it at `start_at` and
+/// arm the timer for whatever follows it
+pub(crate) fn play_entry(room: &Arc<Room>, pb: &mut PlaybackState, queue: &mut PlayQueue, entry: QueueEntry, start_at: u64) {
+    let now = room.clock.now_micros();
+    let track_url = room.media.proxy(&entry.track_url, &entry.track);
+    pb.track_url = track_url.clone();
+    pb.queue_entry = Some(entry.entry_id);
+    pb.track = entry.track.clone();
+    pb.is_playing = true;
+    pb.position_ms = 0;
+    pb.last_update_time = start_at;
+    let _ = room.tx.send(ServerMessage::PlayCommand {
+        track_url,
+        start_at_server_time: start_at,
+        start_at_position_ms: 0,
+        server_time_at_broadcast: now,
+        track: entry.track,
+    });
+    schedule_advance(room, pb, queue);
+}
+
+/// (Re)arm the timer for the end of the playing track: queue entries move on to the
+/// next one, and any track stops there. Does nothing (beyond cancelling the old timer)
+/// unless a track of known length is playing.
+pub(crate) fn schedule_advance(room: &Arc<Room>, pb: &PlaybackState, queue: &mut PlayQueue) {
+    queue.cancel_advance();
+    if pb.is_playing && pb.queue_entry.is_some() {
+        // Have the proxy (if on) fetch what's next while this one plays
+        if let Some(next) = queue.upcoming() {
+            room.media.proxy(&next.track_url, &next.track);
+        }
+    }
+    let Some(duration_ms) = pb.track.duration_ms.filter(|_| pb.is_playing) else {
+        return;
+    };
+    // Controls can run on threads outside the runtime (the Android bridge)
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        tracing::warn!("Room {}: no runtime to advance the queue on", room.id);
+        return;
+    };
+    let ends_at = pb.last_update_time + duration_ms.saturating_sub(pb.position_ms) * 1000;
+    // Never more than one track ahead: wait for this one to start before announcing the next
+    let announce_at = ends_at.saturating_sub(ADVANCE_LEAD.as_micros() as u64).max(pb.last_update_time);
+    let generation = queue.generation;
+    queue.timer = Some(runtime.spawn(advance_at_end(room.clone(), generation, announce_at, ends_at, duration_ms)));
+}
+
+async fn sleep_until(room: &Room, server_time: u64) {
+    let wait = server_time.saturating_sub(room.clock.now_micros());
+    tokio::time::sleep(Duration::from_micros(wait)).await;
+}
+
+/// At `announce_at`, have everyone start the next entry exactly at `ends_at`.
+/// With nothing to follow (or outside the queue), mark playback finished once the track ends.
+async fn advance_at_end(room: Arc<Room>, generation: u64, announce_at: u64, ends_at: u64, duration_ms: u64) {
+    sleep_until(&room, announce_at).await;
+    {
+        let mut pb = room.playback_state.write().unwrap();
+        let mut queue = room.queue.lock().unwrap();
+        if queue.generation != generation {
+            return;
+        }
+        // Tracks loaded outside the queue just stop at the end
+        let next = if pb.queue_entry.is_some() { queue.advance(true).cloned() } else { None };
+        if let Some(next) = next {
+            // Our own handle: let go of it rather than abort ourselves
+            queue.timer.take();
+            tracing::info!("Room {}: queue moving on to entry {}", room.id, next.entry_id);
+            play_entry(&room, &mut pb, &mut queue, next, ends_at);
+            broadcast_queue(&room, &queue);
+            return;
+        }
+    }
+
+    sleep_until(&room, ends_at).await;
+    let mut pb = room.playback_state.write().unwrap();
+    if room.queue.lock().unwrap().generation != generation {
+        return;
+    }
+    tracing::info!("Room {}: track finished", room.id);
+    pb.is_playing = false;
+    pb.position_ms = duration_ms;
+    pb.last_update_time = ends_at;
+}
@@ -0,0 +1,220 @@
+use crate::control::CommandError;
+use futures::future::BoxFuture;
+use rust_core::messages::ErrorCode;
+use rust_core::track::{mime_type_for, track_id, SourceKind, Track};
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+/// The audio stream behind a page URL, and what the resolver learned about the track
+#[derive(Debug, Clone)]
+pub struct Resolved {
+    pub stream_url: String,
+    pub track: Track,
+}
+
+#[derive(Debug, Clone)]
+pub enum ResolveError {
+    Unavailable(String), // The resolver couldn't run at all
+    Failed(String),      // It ran, but found no audio at that URL
+    TimedOut(Duration),
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unavailable(why) => write!(f, "resolver unavailable: {}", why),
+            Self::Failed(why) => write!(f, "couldn't resolve URL: {}", why),
+            Self::TimedOut(after) => write!(f, "resolving URL timed out after {}ms", after.as_millis()),
+        }
+    }
+}
+
+impl From<ResolveError> for CommandError {
+    fn from(e: ResolveError) -> Self {
+        let code = match e {
+            ResolveError::Failed(_) => ErrorCode::NotFound,
+            ResolveError::Unavailable(_) | ResolveError::TimedOut(_) => ErrorCode::Internal,
+        };
+        CommandError::new(code, e.to_string())
+    }
+}
+
+/// Turns a page URL (e.g. a video site) into a stream clients can play
+pub trait UrlResolver: Send + Sync {
+    fn resolve<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Resolved, ResolveError>>;
+}
+
+/// Page URLs go to the resolver; direct links to audio files and the server's own
+/// paths ("live", "stream?room=...") are played as they are
+pub fn needs_resolution(url: &str) -> bool {
+    let is_web = url.starts_with("http://") || url.starts_with("https://");
+    is_web && Track::from_url(url, SourceKind::Url).mime_type.is_none()
+}
+
+/// Runs `yt-dlp -j` and reads the best audio format's URL and metadata from its JSON
+#[derive(Debug, Clone)]
+pub struct YtDlp {
+    program: PathBuf, // Looked up on PATH unless absolute
+}
+
+impl Default for YtDlp {
+    fn default() -> Self {
+        Self { program: "yt-dlp".into() }
+    }
+}
+
+impl YtDlp {
+    /// Run `program` instead of whatever `yt-dlp` is on PATH
+    pub fn with_program(program: impl Into<PathBuf>) -> Self {
+        Self { program: program.into() }
+    }
+
+    async fn run(&self, url: &str) -> Result<Resolved, ResolveError> {
+        let output = tokio::process::Command::new(&self.program)
+            .args(["--no-playlist", "--format", "bestaudio", "--dump-json", url])
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            // A timed-out resolution drops this future; don't leave yt-dlp running
+            .kill_on_drop(true)
+            .output()
+            .await
+            .map_err(|e| ResolveError::Unavailable(format!("{}: {}", self.program.display(), e)))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let why = stderr.lines().rev().find(|l| !l.trim().is_empty()).unwrap_or("no output");
+            return Err(ResolveError::Failed(why.trim().to_string()));
+        }
+        // One JSON object per line; a playlist slipping through still gives us its first entry
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let info: serde_json::Value = stdout
+            .lines()
+            .next()
+            .and_then(|line| serde_json::from_str(line).ok())
+            .ok_or_else(|| ResolveError::Failed("yt-dlp printed no track info".into()))?;
+        parse_info(url, &info)
+    }
+}
+
+impl UrlResolver for YtDlp {
+    fn resolve<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Resolved, ResolveError>> {
+        Box::pin(self.run(url))
+    }
+}
+
+fn parse_info(page_url: &str, info: &serde_json::Value) -> Result<Resolved, ResolveError> {
+    let text = |key: &str| info.get(key).and_then(|v| v.as_str()).filter(|s| !s.is_empty()).map(String::from);
+    let stream_url = text("url").ok_or_else(|| ResolveError::Failed("no audio stream found".into()))?;
+    let track = Track {
+        // Keyed by the page, since stream URLs are signed and change every time
+        id: track_id(page_url),
+        title: text("title"),
+        artist: text("artist").or_else(|| text("uploader")),
+        duration_ms: info.get("duration").and_then(|v| v.as_f64()).map(|secs| (secs * 1000.0).round() as u64),
+        mime_type: text("ext").and_then(|ext| mime_type_for(&format!("audio.{}", ext))),
+        source: SourceKind::Resolved,
+        artwork_url: text("thumbnail"),
+    };
+    Ok(Resolved { stream_url, track })
+}
+
+/// Limits on URL resolution
+#[derive(Debug, Clone, Copy)]
+pub struct ResolverConfig {
+    pub timeout: Duration,     // Give up on a single resolution after this long
+    pub cache_ttl: Duration,   // Reuse a resolved stream this long (they're signed and expire)
+    pub max_concurrent: usize, // Resolutions running at once; the rest wait their turn
+}
+
+impl Default for ResolverConfig {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(20),
+            cache_ttl: Duration::from_secs(30 * 60),
+            max_concurrent: 2,
+        }
+    }
+}
+
+impl ResolverConfig {
+    /// Defaults, overridden by SONICSYNC_RESOLVE_TIMEOUT_MS / SONICSYNC_RESOLVE_CACHE_TTL_MS /
+    /// SONICSYNC_RESOLVE_CONCURRENCY
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var(name).ok()?.parse::<u64>().ok();
+        let default = Self::default();
+        Self {
+            timeout: var("SONICSYNC_RESOLVE_TIMEOUT_MS").map_or(default.timeout, Duration::from_millis),
+            cache_ttl: var("SONICSYNC_RESOLVE_CACHE_TTL_MS").map_or(default.cache_ttl, Duration::from_millis),
+            max_concurrent: var("SONICSYNC_RESOLVE_CONCURRENCY").map_or(default.max_concurrent, |n| n as usize),
+        }
+    }
+}
+
+/// A `UrlResolver` with a timeout, a cache of recent results and a cap on how many run at once
+pub struct Resolver {
+    backend: Arc<dyn UrlResolver>,
+    config: ResolverConfig,
+    cache: Mutex<HashMap<String, (Instant, Resolved)>>, // page URL -> (resolved at, result)
+    permits: Semaphore,
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Self::new(Arc::new(YtDlp::default()), ResolverConfig::default())
+    }
+}
+
+impl Resolver {
+    pub fn new(backend: Arc<dyn UrlResolver>, config: ResolverConfig) -> Self {
+        Self {
+            backend,
+            config,
+            cache: Mutex::new(HashMap::new()),
+            permits: Semaphore::new(config.max_concurrent.max(1)),
+        }
+    }
+
+    /// yt-dlp (SONICSYNC_YTDLP overrides where it is) with limits from `ResolverConfig::from_env`
+    pub fn from_env() -> Self {
+        let backend = std::env::var_os("SONICSYNC_YTDLP").map_or_else(YtDlp::default, YtDlp::with_program);
+        Self::new(Arc::new(backend), ResolverConfig::from_env())
+    }
+
+    pub fn config(&self) -> &ResolverConfig {
+        &self.config
+    }
+
+    pub async fn resolve(&self, url: &str) -> Result<Resolved, ResolveError> {
+        if let Some(hit) = self.cached(url) {
+            return Ok(hit);
+        }
+        let _permit = self.permits.acquire().await.expect("resolver semaphore is never closed");
+        // Someone may have resolved the same URL while we waited
+        if let Some(hit) = self.cached(url) {
+            return Ok(hit);
+        }
+        let resolved = tokio::time::timeout(self.config.timeout, self.backend.resolve(url))
+            .await
+            .map_err(|_| ResolveError::TimedOut(self.config.timeout))??;
+
+        let mut cache = self.cache.lock().unwrap();
+        let ttl = self.config.cache_ttl;
+        cache.retain(|_, (at, _)| at.elapsed() < ttl);
+        cache.insert(url.to_string(), (Instant::now(), resolved.clone()));
+        Ok(resolved)
+    }
+
+    fn cached(&self, url: &str) -> Option<Resolved> {
+        let cache = self.cache.lock().unwrap();
+        cache
+            .get(url)
+            .filter(|(at, _)| at.elapsed() < self.config.cache_ttl)
+            .map(|(_, resolved)| resolved.clone())
+    }
+}
@@ -3,13 +3,17 @@ use axum::{
     Router,
 };
 use crate::app_state::SharedState;
-use crate::{handlers, stream, control};
+use crate::{handlers, stream, control, media, peers};
 
 pub fn create_router(state: SharedState) -> Router {
     Router::new()
         .route("/ws", get(handlers::ws_handler))
         .route("/stream", get(stream::stream_audio))
         .route("/stream/live", get(stream::live_stream))
+        .route("/media/:track_id", get(media::serve_media))
         .route("/control", post(control::handle_control_command))
+        .route("/resync", post(control::handle_resync))
+        .route("/peers", get(peers::list_peers))
+        .route("/peers/departed", get(peers::list_departures))
         .with_state(state)
 }
@@ -1,40 +1,147 @@
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
     response::{IntoResponse, Response},
     body::Body,
 };
-use crate::app_state::SharedState;
+use crate::app_state::{Room, SharedState, DEFAULT_ROOM};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncReadExt;
 use tower_http::services::ServeFile;
 use tower::ServiceExt; // for Request
 
-pub async fn stream_audio(State(state): State<SharedState>) -> Response {
-    let file_path = {
-        let guard = state.hosted_file_path.read().unwrap();
-        guard.clone()
+/// Room named by ?room=, or the default room
+fn requested_room(state: &SharedState, query: &HashMap<String, String>) -> Option<Arc<Room>> {
+    state.room(query.get("room").map(String::as_str).unwrap_or(DEFAULT_ROOM))
+}
+
+pub async fn stream_audio(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+    req: Request,
+) -> Response {
+    let Some(room) = requested_room(&state, &query) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    let hosted = room.hosted_file.read().unwrap().clone();
+
+    match hosted {
+        Some(file) => serve_file(Path::new(&file.path), req, file.track.mime_type).await,
+        None => (StatusCode::NOT_FOUND, "No file hosted").into_response(),
+    }
+}
+
+/// Serve a file with the client's own headers, so Range (206/416) and conditional
+/// requests work: If-Modified-Since/If-Unmodified-Since are left to ServeFile, the
+/// ETag ones (If-None-Match, If-Match, If-Range) are handled here.
+/// `content_type` wins over what we'd detect from the file.
+pub(crate) async fn serve_file(path: &Path, mut req: Request, content_type: Option<String>) -> Response {
+    let meta = match tokio::fs::metadata(path).await {
+        Ok(meta) if meta.is_file() => meta,
+        _ => return (StatusCode::NOT_FOUND, "File not found").into_response(),
     };
+    let modified = meta.modified().ok();
+    let etag = entity_tag(meta.len(), modified);
+    let headers = req.headers_mut();
 
-    match file_path {
-        Some(path) => {
-            let req = axum::http::Request::builder()
-                .body(Body::empty())
-                .unwrap();
-                
-            // Use tower_http to serve the file with range support
-            match ServeFile::new(path).oneshot(req).await {
-                Ok(res) => res.into_response(),
-                Err(err) => {
-                    tracing::error!("Failed to serve file: {}", err);
-                    (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serve file").into_response()
+    if let Some(wanted) = headers.get(header::IF_MATCH) {
+        if !etag_matches(wanted, &etag, false) {
+            return (StatusCode::PRECONDITION_FAILED, [(header::ETAG, etag)]).into_response();
+        }
+    }
+    if let Some(cached) = headers.get(header::IF_NONE_MATCH) {
+        if etag_matches(cached, &etag, true) {
+            return (StatusCode::NOT_MODIFIED, [(header::ETAG, etag)]).into_response();
+        }
+        // If-None-Match takes precedence: a changed ETag means a full response
+        headers.remove(header::IF_MODIFIED_SINCE);
+    }
+    // A range of a file that changed since the client's copy would corrupt it: send it all
+    if let Some(validator) = headers.get(header::IF_RANGE) {
+        if !if_range_holds(validator, &etag, modified) {
+            headers.remove(header::RANGE);
+        }
+    }
+
+    let content_type = match content_type {
+        Some(content_type) => Some(content_type),
+        None => detect_content_type(path).await,
+    };
+    match ServeFile::new(path).oneshot(req).await {
+        Ok(res) => {
+            let mut res = res.map(Body::new);
+            let status = res.status();
+            if status.is_success() || status == StatusCode::NOT_MODIFIED {
+                res.headers_mut().insert(header::ETAG, etag);
+            }
+            if status.is_success() {
+                if let Some(value) = content_type.and_then(|t| HeaderValue::from_str(&t).ok()) {
+                    res.headers_mut().insert(header::CONTENT_TYPE, value);
                 }
             }
+            res
         }
-        None => (StatusCode::NOT_FOUND, "No file hosted").into_response(),
+        Err(err) => {
+            tracing::error!("Failed to serve file: {}", err);
+            (StatusCode::INTERNAL_SERVER_ERROR, "Failed to serve file").into_response()
+        }
+    }
+}
+
+/// Strong validator from size and modification time
+fn entity_tag(len: u64, modified: Option<SystemTime>) -> HeaderValue {
+    let nanos = modified.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map_or(0, |d| d.as_nanos());
+    HeaderValue::from_str(&format!("\"{:x}-{:x}\"", len, nanos)).expect("hex digits are a valid header")
+}
+
+/// Whether an If-Match/If-None-Match list names `etag`. Weak comparison ignores W/ prefixes.
+fn etag_matches(list: &HeaderValue, etag: &HeaderValue, weak: bool) -> bool {
+    let Ok(list) = list.to_str() else {
+        return false;
+    };
+    let ours = etag.to_str().unwrap_or_default();
+    list.split(',').map(str::trim).any(|tag| {
+        tag == "*" || tag == ours || (weak && tag.strip_prefix("W/") == Some(ours))
+    })
+}
+
+/// If-Range holds if its ETag matches exactly, or its date is exactly our Last-Modified
+fn if_range_holds(validator: &HeaderValue, etag: &HeaderValue, modified: Option<SystemTime>) -> bool {
+    let Ok(validator) = validator.to_str() else {
+        return false;
+    };
+    if validator.starts_with('"') || validator.starts_with("W/") {
+        return validator == etag.to_str().unwrap_or_default();
     }
+    // Last-Modified has whole seconds
+    let modified = modified.and_then(|t| t.duration_since(UNIX_EPOCH).ok()).map(|d| d.as_secs());
+    let date = httpdate::parse_http_date(validator).ok().and_then(|t| t.duration_since(UNIX_EPOCH).ok());
+    matches!((date, modified), (Some(date), Some(modified)) if date.as_secs() == modified)
 }
 
-pub async fn live_stream(State(state): State<SharedState>) -> Response {
-    let mut rx = state.audio_tx.subscribe();
+/// Audio type from the file name, else from the first bytes; None leaves it to ServeFile
+async fn detect_content_type(path: &Path) -> Option<String> {
+    let by_name = path.file_name().and_then(|n| n.to_str()).and_then(rust_core::track::mime_type_for);
+    if by_name.is_some() {
+        return by_name;
+    }
+    let mut head = [0u8; 12];
+    let mut file = tokio::fs::File::open(path).await.ok()?;
+    let n = file.read(&mut head).await.ok()?;
+    crate::probe::sniff_audio(&head[..n]).map(String::from)
+}
+
+pub async fn live_stream(
+    State(state): State<SharedState>,
+    Query(query): Query<HashMap<String, String>>,
+) -> Response {
+    let Some(room) = requested_room(&state, &query) else {
+        return (StatusCode::NOT_FOUND, "No such room").into_response();
+    };
+    let mut rx = room.audio_tx.subscribe();
     
     let stream = async_stream::stream! {
         loop {
@@ -0,0 +1,46 @@
+use crate::app_state::SharedState;
+use rust_core::clock::Clock;
+use rust_core::udp_time::{TimeProbe, PACKET_LEN};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::net::UdpSocket;
+
+/// Bind the UDP time listener, advertise its port in Welcome and answer probes
+/// in the background. Clients fall back to WebSocket sync if this fails.
+pub async fn start(state: SharedState, addr: SocketAddr) -> std::io::Result<SocketAddr> {
+    let socket = UdpSocket::bind(addr).await?;
+    let local = socket.local_addr()?;
+    state.set_udp_time_port(Some(local.port()));
+    tracing::info!("UDP time sync listening on {}", local);
+    tokio::spawn(async move {
+        if let Err(e) = serve(socket, state.clock.clone()).await {
+            tracing::error!("UDP time listener stopped: {}", e);
+        }
+        state.set_udp_time_port(None);
+    });
+    Ok(local)
+}
+
+/// Answer probes until the socket fails. t1 is taken as soon as the datagram is
+/// read and t2 right before the reply goes out; anything that isn't a probe is dropped.
+pub async fn serve(socket: UdpSocket, clock: Arc<dyn Clock>) -> std::io::Result<()> {
+    // One spare byte so oversized datagrams show up as the wrong length instead of being truncated to fit
+    let mut buf = [0u8; PACKET_LEN + 1];
+    loop {
+        let (len, from) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            // ICMP errors from earlier replies surface here on some platforms; they're not fatal
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionReset => continue,
+            Err(e) => return Err(e),
+        };
+        let t1 = clock.now_micros();
+        let Some(probe) = TimeProbe::decode(&buf[..len]) else {
+            tracing::debug!("Ignoring {} byte datagram from {}", len, from);
+            continue;
+        };
+        let reply = probe.reply(t1, clock.now_micros());
+        if let Err(e) = socket.send_to(&reply.encode(), from).await {
+            tracing::debug!("UDP time reply to {} failed: {}", from, e);
+        }
+    }
+}
@@ -0,0 +1,143 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::*;
+use rust_core::messages::{ClientKind, ClientMessage, ControlCommand, ErrorCode, Role, ServerMessage, PROTOCOL_VERSION};
+use server::app_state::{AppState, Peer};
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, Message};
+
+fn welcome_role(msg: &ServerMessage) -> Role {
+    match msg {
+        ServerMessage::Welcome { role, .. } => *role,
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn creator_sets_pin_and_wrong_pin_is_refused() {
+    let addr = spawn_server(AppState::new()).await;
+    let (_host, reply) = join_with(addr, "?room=party", Some("1234")).await;
+    assert_eq!(welcome_role(&reply), Role::Host);
+
+    let (mut ws, reply) = join_with(addr, "?room=party", Some("0000")).await;
+    match reply {
+        ServerMessage::Error { code, .. } => assert_eq!(code, ErrorCode::Unauthorized),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    match next_frame(&mut ws).await {
+        Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Policy),
+        other => panic!("expected Close, got {:?}", other),
+    }
+
+    let (_guest, reply) = join_with(addr, "?room=party", Some("1234")).await;
+    assert_eq!(welcome_role(&reply), Role::Listener);
+}
+
+#[test]
+fn simultaneous_joiners_cannot_both_claim_a_room() {
+    let state = AppState::new();
+    let peer = |device: &str| {
+        let addr = "127.0.0.1:9".parse().unwrap();
+        Arc::new(Peer::new(addr, device.into(), 0, ClientKind::Cli, PROTOCOL_VERSION, 0))
+    };
+    // Both are in the room before either is admitted, as when they race through the handshake
+    let room = state.join_room("race", "a", peer("CLI-a"));
+    state.join_room("race", "b", peer("CLI-b"));
+
+    assert_eq!(room.admit("a", Some("1234")), Some(Role::Host));
+    assert_eq!(room.admit("b", Some("0000")), None);
+    assert_eq!(room.admit("b", Some("1234")), Some(Role::Listener));
+    assert_eq!(room.role_of("a"), Some(Role::Host));
+}
+
+#[tokio::test]
+async fn listener_cannot_control_until_promoted() {
+    let state = AppState::new();
+    state.default_room().playback_state.write().unwrap().track_url = "http://example.com/a.mp3".into();
+    let addr = spawn_server(state).await;
+    let mut host = join(addr, "").await;
+    let (mut guest, reply) = join_with(addr, "", None).await;
+    let ServerMessage::Welcome { session_id: guest_id, role, control_token, .. } = reply else {
+        panic!("expected Welcome, got {:?}", reply);
+    };
+    assert_eq!(role, Role::Listener);
+    assert!(control_token.is_none());
+
+    send(&mut guest, &ClientMessage::CommandRequest { id: 1, cmd: ControlCommand::Pause }).await;
+    match recv(&mut guest).await {
+        ServerMessage::Error { id, code, .. } => {
+            assert_eq!(id, 1);
+            assert_eq!(code, ErrorCode::Forbidden);
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+    // Listeners can't hand out roles either
+    send(&mut guest, &ClientMessage::SetRole { id: 2, session_id: guest_id.clone(), role: Role::CoHost }).await;
+    assert!(matches!(recv(&mut guest).await, ServerMessage::Error { id: 2, code: ErrorCode::Forbidden, .. }));
+
+    send(&mut host, &ClientMessage::SetRole { id: 3, session_id: guest_id.clone(), role: Role::CoHost }).await;
+    // Everyone hears about the change
+    match recv(&mut guest).await {
+        ServerMessage::RoleChanged { session_id, role } => {
+            assert_eq!(session_id, guest_id);
+            assert_eq!(role, Role::CoHost);
+        }
+        other => panic!("expected RoleChanged, got {:?}", other),
+    }
+
+    send(&mut guest, &ClientMessage::CommandRequest { id: 4, cmd: ControlCommand::Pause }).await;
+    let mut acked = false;
+    for _ in 0..2 {
+        match recv(&mut guest).await {
+            ServerMessage::Ack { id } => acked = id == 4,
+            ServerMessage::PauseCommand { .. } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert!(acked);
+}
+
+#[tokio::test]
+async fn co_host_takes_over_when_host_leaves() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let host = join(addr, "?room=party").await;
+    let (mut guest, reply) = join_with(addr, "?room=party", None).await;
+    let ServerMessage::Welcome { session_id: guest_id, .. } = reply else {
+        panic!("expected Welcome, got {:?}", reply);
+    };
+    let room = state.room("party").unwrap();
+    room.set_role(&guest_id, Role::CoHost);
+    assert!(matches!(recv(&mut guest).await, ServerMessage::RoleChanged { role: Role::CoHost, .. }));
+
+    leave(host).await;
+    match recv(&mut guest).await {
+        ServerMessage::RoleChanged { session_id, role } => {
+            assert_eq!(session_id, guest_id);
+            assert_eq!(role, Role::Host);
+        }
+        other => panic!("expected RoleChanged, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn rest_control_needs_token() {
+    let state = AppState::new();
+    let body = serde_json::to_string(&ControlCommand::Pause).unwrap();
+
+    let (status, _, response) = http(state.clone(), json_request("POST", "/control", &body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert!(String::from_utf8(response).unwrap().contains("Unauthorized"));
+
+    let mut req = json_request("POST", "/control", &body);
+    req.headers_mut().insert("authorization", "Bearer nope".parse().unwrap());
+    let (status, _, _) = http(state.clone(), req).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let mut req = json_request("POST", "/control", &body);
+    let token = format!("Bearer {}", state.default_room().control_token);
+    req.headers_mut().insert("authorization", token.parse().unwrap());
+    let (status, _, _) = http(state, req).await;
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,127 @@
+//! Helpers for tests that run the real router on a local port
+
+#![allow(dead_code)]
+
+use axum::body::Body;
+use axum::http::{HeaderMap, Request, StatusCode};
+use futures::{SinkExt, StreamExt};
+use tower::ServiceExt;
+use rust_core::messages::{ClientKind, ClientMessage, ServerMessage, PROTOCOL_VERSION};
+use server::app_state::SharedState;
+use std::net::SocketAddr;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+
+pub type Ws = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Serve `create_router(state)` on an ephemeral port
+pub async fn spawn_server(state: SharedState) -> SocketAddr {
+    let app = server::routes::create_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
+    addr
+}
+
+pub async fn connect(addr: SocketAddr, query: &str) -> Ws {
+    let url = format!("ws://{}/ws{}", addr, query);
+    let (ws, _) = connect_async(url).await.expect("connect failed");
+    ws
+}
+
+/// Connect and send a current-version CLI Join; returns the server's first reply
+pub async fn join_with(addr: SocketAddr, query: &str, pin: Option<&str>) -> (Ws, ServerMessage) {
+    send_join(addr, query, pin, None).await
+}
+
+/// Reconnect presenting a resume token from an earlier Welcome
+pub async fn resume(addr: SocketAddr, query: &str, token: &str) -> (Ws, ServerMessage) {
+    send_join(addr, query, None, Some(token)).await
+}
+
+async fn send_join(addr: SocketAddr, query: &str, pin: Option<&str>, resume_token: Option<&str>) -> (Ws, ServerMessage) {
+    let mut ws = connect(addr, query).await;
+    let join = ClientMessage::Join {
+        device_id: "CLI-test".into(),
+        protocol_version: PROTOCOL_VERSION,
+        client_kind: ClientKind::Cli,
+        capabilities: 0,
+        pin: pin.map(str::to_string),
+        resume_token: resume_token.map(str::to_string),
+    };
+    send(&mut ws, &join).await;
+    let reply = recv(&mut ws).await;
+    (ws, reply)
+}
+
+/// Connect and complete the handshake
+pub async fn join(addr: SocketAddr, query: &str) -> Ws {
+    let (ws, reply) = join_with(addr, query, None).await;
+    assert!(matches!(reply, ServerMessage::Welcome { .. }), "expected Welcome, got {:?}", reply);
+    ws
+}
+
+/// Close the socket cleanly so the server drops the session right away
+pub async fn leave(mut ws: Ws) {
+    let _ = ws.close(None).await;
+}
+
+pub async fn send(ws: &mut Ws, msg: &ClientMessage) {
+    ws.send(Message::Binary(bincode::serialize(msg).unwrap())).await.unwrap();
+}
+
+/// Next data frame, skipping pings. Panics after a few seconds of silence.
+pub async fn next_frame(ws: &mut Ws) -> Message {
+    loop {
+        let msg = tokio::time::timeout(std::time::Duration::from_secs(5), ws.next())
+            .await
+            .expect("timed out waiting for frame")
+            .expect("stream ended")
+            .expect("read error");
+        match msg {
+            Message::Ping(_) | Message::Pong(_) => continue,
+            other => return other,
+        }
+    }
+}
+
+/// Next server message of any kind
+pub async fn recv_any(ws: &mut Ws) -> ServerMessage {
+    match next_frame(ws).await {
+        Message::Binary(bytes) => bincode::deserialize(&bytes).expect("undecodable server message"),
+        other => panic!("expected binary frame, got {:?}", other),
+    }
+}
+
+/// Next server message, skipping roster traffic (PeerList/PeerUpdate) that
+/// every join, leave and role change generates
+pub async fn recv(ws: &mut Ws) -> ServerMessage {
+    loop {
+        match recv_any(ws).await {
+            ServerMessage::PeerList { .. } | ServerMessage::PeerUpdate { .. } => continue,
+            other => return other,
+        }
+    }
+}
+
+/// Run one HTTP request through the router without a socket
+pub async fn http(state: SharedState, req: Request<Body>) -> (StatusCode, HeaderMap, Vec<u8>) {
+    let res = server::routes::create_router(state).oneshot(req).await.unwrap();
+    let status = res.status();
+    let headers = res.headers().clone();
+    let body = axum::body::to_bytes(res.into_body(), usize::MAX).await.unwrap();
+    (status, headers, body.to_vec())
+}
+
+pub fn json_request(method: &str, uri: &str, body: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("content-type", "application/json")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
@@ -0,0 +1,138 @@
+mod common;
+
+use common::*;
+use futures::SinkExt;
+use rust_core::messages::{
+    capability, v1, ClientKind, ClientMessage, ErrorCode, ServerMessage, LEGACY_PROTOCOL_VERSION, NO_REQUEST,
+    PROTOCOL_VERSION,
+};
+use server::app_state::AppState;
+use tokio_tungstenite::tungstenite::protocol::{frame::coding::CloseCode, Message};
+
+fn join(protocol_version: u16, capabilities: u32) -> ClientMessage {
+    ClientMessage::Join {
+        device_id: "CLI-test".into(),
+        protocol_version,
+        client_kind: ClientKind::Cli,
+        capabilities,
+        pin: None,
+        resume_token: None,
+    }
+}
+
+#[tokio::test]
+async fn current_client_gets_negotiated_welcome() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    send(&mut ws, &join(PROTOCOL_VERSION, capability::HOSTED_STREAM | 1 << 31)).await;
+
+    match recv(&mut ws).await {
+        ServerMessage::Welcome { protocol_version, capabilities, .. } => {
+            assert_eq!(protocol_version, PROTOCOL_VERSION);
+            // Unknown bits are dropped
+            assert_eq!(capabilities, capability::HOSTED_STREAM);
+        }
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn intermediate_version_without_downgrade_is_rejected() {
+    let addr = spawn_server(AppState::new()).await;
+    // Above the legacy layout but below the current one: not a range, so still refused
+    for version in [2, PROTOCOL_VERSION / 2, PROTOCOL_VERSION - 1] {
+        let mut ws = connect(addr, "").await;
+        send(&mut ws, &join(version, 0)).await;
+
+        match recv(&mut ws).await {
+            ServerMessage::Incompatible { reason, server_version, legacy_version } => {
+                assert_eq!((server_version, legacy_version), (PROTOCOL_VERSION, LEGACY_PROTOCOL_VERSION));
+                assert!(reason.contains(&format!("v{} and v{}", LEGACY_PROTOCOL_VERSION, PROTOCOL_VERSION)), "{}", reason);
+            }
+            other => panic!("v{}: expected Incompatible, got {:?}", version, other),
+        }
+    }
+}
+
+#[tokio::test]
+async fn newer_client_is_downgraded() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    send(&mut ws, &join(PROTOCOL_VERSION + 5, 0)).await;
+
+    match recv(&mut ws).await {
+        ServerMessage::Welcome { protocol_version, .. } => assert_eq!(protocol_version, PROTOCOL_VERSION),
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn v1_client_is_served_v1_layout() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    let old_join = v1::ClientMessage::Join { device_id: "ANDROID-legacy".into() };
+    ws.send(Message::Binary(bincode::serialize(&old_join).unwrap())).await.unwrap();
+
+    let Message::Binary(bytes) = next_frame(&mut ws).await else { panic!("expected binary frame") };
+    let welcome: v1::ServerMessage = bincode::deserialize(&bytes).unwrap();
+    assert!(matches!(welcome, v1::ServerMessage::Welcome { .. }));
+
+    // Subsequent v1 frames are decoded with the v1 layout too
+    let req = v1::ClientMessage::TimeRequest { t0: 7, seq: 3 };
+    ws.send(Message::Binary(bincode::serialize(&req).unwrap())).await.unwrap();
+    let Message::Binary(bytes) = next_frame(&mut ws).await else { panic!("expected binary frame") };
+    match bincode::deserialize::<v1::ServerMessage>(&bytes).unwrap() {
+        v1::ServerMessage::TimeResponse { t0, seq, .. } => assert_eq!((t0, seq), (7, 3)),
+        other => panic!("expected TimeResponse, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unsupported_version_is_rejected_with_reason() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    send(&mut ws, &join(0, 0)).await;
+
+    match recv(&mut ws).await {
+        ServerMessage::Incompatible { reason, server_version, .. } => {
+            assert!(reason.contains("not supported"), "{}", reason);
+            assert_eq!(server_version, PROTOCOL_VERSION);
+        }
+        other => panic!("expected Incompatible, got {:?}", other),
+    }
+    match next_frame(&mut ws).await {
+        Message::Close(Some(frame)) => {
+            assert_eq!(frame.code, CloseCode::Protocol);
+            assert!(frame.reason.contains("not supported"));
+        }
+        other => panic!("expected Close, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn undecodable_frame_gets_explicit_error() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    send(&mut ws, &join(PROTOCOL_VERSION, 0)).await;
+    let _welcome = recv(&mut ws).await;
+
+    ws.send(Message::Binary(vec![0xff, 0xff, 0xff, 0xff, 0x01])).await.unwrap();
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, .. } => {
+            assert_eq!(id, NO_REQUEST);
+            assert_eq!(code, ErrorCode::MalformedMessage);
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn json_dashboard_without_version_is_v1() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "").await;
+    ws.send(Message::Text(r#"{"Join":{"device_id":"DASHBOARD"}}"#.into())).await.unwrap();
+
+    let Message::Text(text) = next_frame(&mut ws).await else { panic!("expected text frame") };
+    let welcome: v1::ServerMessage = serde_json::from_str(&text).unwrap();
+    assert!(matches!(welcome, v1::ServerMessage::Welcome { .. }));
+}
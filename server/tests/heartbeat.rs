@@ -0,0 +1,80 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::Request;
+use common::*;
+use futures::SinkExt;
+use rust_core::messages::{DisconnectReason, ServerMessage};
+use server::app_state::{AppState, AppStateConfig, HeartbeatConfig};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+fn fast_heartbeat() -> server::app_state::SharedState {
+    let heartbeat = HeartbeatConfig {
+        interval: Duration::from_millis(50),
+        timeout: Duration::from_millis(200),
+        resume_grace: Duration::ZERO,
+    };
+    AppState::with_config(AppStateConfig { heartbeat, ..AppStateConfig::default() })
+}
+
+#[tokio::test]
+async fn silent_client_is_evicted() {
+    let state = fast_heartbeat();
+    let addr = spawn_server(state.clone()).await;
+    let mut watcher = join(addr, "").await;
+    // Never read again, so pings go unanswered
+    let _silent = join(addr, "").await;
+    assert_eq!(state.default_room().peers.len(), 2);
+
+    // The watcher keeps reading (and so answering pings) and hears why the other left
+    let reason = loop {
+        if let ServerMessage::PeerLeft { reason, .. } = recv(&mut watcher).await {
+            break reason;
+        }
+    };
+    assert_eq!(reason, DisconnectReason::TimedOut);
+    assert_eq!(state.default_room().peers.len(), 1);
+
+    let departed = state.default_room().departures();
+    assert_eq!(departed.len(), 1);
+    assert_eq!(departed[0].reason, DisconnectReason::TimedOut);
+}
+
+#[tokio::test]
+async fn responsive_client_survives_timeout() {
+    let state = fast_heartbeat();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    // Reading answers the pings; nothing else is sent
+    let quiet = tokio::time::timeout(Duration::from_millis(600), recv(&mut ws)).await;
+    assert!(quiet.is_err(), "unexpected {:?}", quiet);
+    assert_eq!(state.default_room().peers.len(), 1);
+}
+
+#[tokio::test]
+async fn close_reason_is_recorded_and_listed() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut watcher = join(addr, "").await;
+    let mut leaver = join(addr, "").await;
+    leaver.send(Message::Close(None)).await.unwrap();
+
+    let reason = loop {
+        if let ServerMessage::PeerLeft { reason, .. } = recv(&mut watcher).await {
+            break reason;
+        }
+    };
+    assert_eq!(reason, DisconnectReason::Closed);
+
+    let req = Request::builder()
+        .uri("/peers/departed")
+        .header("authorization", format!("Bearer {}", state.default_room().control_token))
+        .body(Body::empty())
+        .unwrap();
+    let (status, _, body) = http(state, req).await;
+    assert_eq!(status, 200);
+    let body = String::from_utf8(body).unwrap();
+    assert!(body.contains("\"Closed\""), "{}", body);
+}
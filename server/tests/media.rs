@@ -0,0 +1,258 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, Request, StatusCode};
+use axum::response::IntoResponse;
+use axum::routing::get;
+use common::*;
+use rust_core::messages::{ClientMessage, ControlCommand, Role, ServerMessage};
+use futures::StreamExt;
+use rust_core::track::{SourceKind, Track};
+use server::app_state::{AppState, AppStateConfig, SharedState};
+use server::control::process_control_command;
+use server::media::{MediaConfig, MediaProxy};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceExt;
+
+fn song() -> Vec<u8> {
+    (0..64 * 1024).map(|i| (i % 251) as u8).collect()
+}
+
+/// Stand-in for a CDN: /song.mp3 serves `song()`, /broken.mp3 fails, /stalled.mp3 sends
+/// one chunk and then nothing. Counts every request.
+async fn spawn_upstream() -> (SocketAddr, Arc<AtomicUsize>) {
+    let hits = Arc::new(AtomicUsize::new(0));
+    let counted = |hits: Arc<AtomicUsize>, ok: bool| {
+        move || async move {
+            hits.fetch_add(1, Ordering::SeqCst);
+            if ok {
+                ([(header::CONTENT_TYPE, "audio/mpeg")], song()).into_response()
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            }
+        }
+    };
+    let app = axum::Router::new()
+        .route("/song.mp3", get(counted(hits.clone(), true)))
+        .route("/broken.mp3", get(counted(hits.clone(), false)))
+        .route("/stalled.mp3", get(|| async {
+            let first = futures::stream::once(async { Ok::<_, std::io::Error>(vec![0u8; 4096]) });
+            Body::from_stream(first.chain(futures::stream::pending()))
+        }));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    (addr, hits)
+}
+
+fn cache_dir(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("sonicsync-media-test-{}-{}", std::process::id(), name))
+}
+
+fn proxied_state(name: &str) -> SharedState {
+    proxied_state_with(MediaConfig { enabled: true, cache_dir: cache_dir(name), allow_private: true, ..MediaConfig::default() })
+}
+
+fn proxied_state_with(config: MediaConfig) -> SharedState {
+    let _ = std::fs::remove_dir_all(&config.cache_dir);
+    AppState::with_config(AppStateConfig { media: MediaProxy::new(config), ..AppStateConfig::default() })
+}
+
+/// Next PlayCommand's URL and track, skipping everything else
+async fn next_play(ws: &mut Ws) -> (String, Track) {
+    loop {
+        if let ServerMessage::PlayCommand { track_url, track, .. } = recv(ws).await {
+            return (track_url, track);
+        }
+    }
+}
+
+fn get_media(path: &str, range: Option<&str>) -> Request<Body> {
+    let mut req = Request::builder().uri(format!("/{}", path));
+    if let Some(range) = range {
+        req = req.header(header::RANGE, range);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn clients_are_pointed_at_the_server_and_upstream_is_fetched_once() {
+    let (upstream, hits) = spawn_upstream().await;
+    let state = proxied_state("once");
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let url = format!("http://{}/song.mp3", upstream);
+    send(&mut ws, &ClientMessage::PlayRequest { id: 1, track_url: url, delay_ms: 500 }).await;
+    let (track_url, track) = next_play(&mut ws).await;
+    assert_eq!(track_url, format!("media/{}", track.id));
+
+    let (status, headers, body) = http(state.clone(), get_media(&track_url, None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "audio/mpeg");
+    assert_eq!(body, song());
+
+    let (status, headers, body) = http(state.clone(), get_media(&track_url, Some("bytes=1000-1999"))).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(headers[header::CONTENT_RANGE], format!("bytes 1000-1999/{}", song().len()));
+    assert_eq!(body, song()[1000..2000]);
+
+    // A late joiner is sent the same server URL
+    let mut late = join(addr, "").await;
+    assert_eq!(next_play(&mut late).await.0, track_url);
+    http(state, get_media(&track_url, None)).await;
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn queue_entries_play_through_the_proxy() {
+    let (upstream, hits) = spawn_upstream().await;
+    let state = proxied_state("queue");
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+
+    let add = ControlCommand::QueueAdd { track_url: format!("http://{}/song.mp3", upstream), duration_ms: None };
+    process_control_command(&room, Role::Host, add).unwrap();
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    let (track_url, _) = next_play(&mut ws).await;
+    assert!(track_url.starts_with("media/"), "{}", track_url);
+
+    let (status, _, body) = http(state, get_media(&track_url, None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body.len(), song().len());
+    assert_eq!(hits.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn failed_upstream_is_a_bad_gateway() {
+    let (upstream, _) = spawn_upstream().await;
+    let state = proxied_state("broken");
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let url = format!("http://{}/broken.mp3", upstream);
+    send(&mut ws, &ClientMessage::PlayRequest { id: 1, track_url: url, delay_ms: 0 }).await;
+    let (track_url, _) = next_play(&mut ws).await;
+    let (status, _, _) = http(state.clone(), get_media(&track_url, None)).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+
+    let (status, _, _) = http(state, get_media("media/0123456789abcdef", None)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn a_track_still_downloading_is_streamed_as_it_arrives() {
+    let (upstream, _) = spawn_upstream().await;
+    let state = proxied_state("stalled");
+    let url = format!("http://{}/stalled.mp3", upstream);
+    let path = state.media.proxy(&url, &Track::from_url(&url, SourceKind::Url));
+
+    // Upstream never finishes, but the chunk it did send is playable now
+    let res = server::routes::create_router(state).oneshot(get_media(&path, None)).await.unwrap();
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "audio/mpeg");
+    let mut body = res.into_body().into_data_stream();
+    let mut received = Vec::new();
+    while received.len() < 4096 {
+        let chunk = tokio::time::timeout(Duration::from_secs(2), body.next()).await.unwrap().unwrap().unwrap();
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, vec![0u8; 4096]);
+}
+
+/// File names in a cache directory, sorted
+fn cached_files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| entries.map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+#[tokio::test]
+async fn evicting_a_download_in_flight_leaves_nothing_behind() {
+    let (upstream, _) = spawn_upstream().await;
+    let dir = cache_dir("evict");
+    let state = proxied_state_with(MediaConfig { enabled: true, cache_dir: dir.clone(), max_entries: 1, allow_private: true, ..MediaConfig::default() });
+
+    let stalled_url = format!("http://{}/stalled.mp3", upstream);
+    let stalled = Track::from_url(&stalled_url, SourceKind::Url);
+    state.media.proxy(&stalled_url, &stalled);
+    // Wait for the first chunk to land in its part file
+    for _ in 0..100 {
+        if !cached_files(&dir).is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert_eq!(cached_files(&dir), vec![format!("{}.0.part", stalled.id)]);
+
+    // Only one entry fits: the stalled download is dropped for this one
+    let song_url = format!("http://{}/song.mp3", upstream);
+    let next = Track::from_url(&song_url, SourceKind::Url);
+    let path = state.media.proxy(&song_url, &next);
+    let (status, _, body) = http(state.clone(), get_media(&path, None)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, song());
+    assert_eq!(http(state.clone(), get_media(&format!("media/{}", stalled.id), None)).await.0, StatusCode::NOT_FOUND);
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(cached_files(&dir), vec![next.id]);
+}
+
+#[tokio::test]
+async fn the_least_recently_used_track_is_evicted() {
+    let (upstream, _) = spawn_upstream().await;
+    let dir = cache_dir("lru");
+    let state = proxied_state_with(MediaConfig { enabled: true, cache_dir: dir.clone(), max_entries: 2, allow_private: true, ..MediaConfig::default() });
+    let fetch = |n: u32| {
+        let url = format!("http://{}/song.mp3?{}", upstream, n);
+        let track = Track::from_url(&url, SourceKind::Url);
+        (state.media.proxy(&url, &track), url, track)
+    };
+
+    let (playing, playing_url, playing_track) = fetch(1);
+    let (prefetched, _, _) = fetch(2);
+    // Playing the first track again makes the prefetched one the least recently used
+    assert_eq!(http(state.clone(), get_media(&playing, None)).await.0, StatusCode::OK);
+    fetch(3);
+
+    assert_eq!(http(state.clone(), get_media(&prefetched, None)).await.0, StatusCode::NOT_FOUND);
+    assert_eq!(state.media.proxy(&playing_url, &playing_track), playing);
+    assert_eq!(http(state.clone(), get_media(&playing, None)).await.0, StatusCode::OK);
+    assert!(cached_files(&dir).contains(&playing_track.id));
+}
+
+#[tokio::test]
+async fn private_upstreams_are_not_fetched_by_default() {
+    let (upstream, hits) = spawn_upstream().await;
+    let state = proxied_state_with(MediaConfig { enabled: true, cache_dir: cache_dir("private"), ..MediaConfig::default() });
+
+    // A literal private address is left for clients to fetch themselves
+    let url = format!("http://{}/song.mp3", upstream);
+    assert_eq!(state.media.proxy(&url, &Track::from_url(&url, SourceKind::Url)), url);
+
+    // A name only shows where it points once resolved
+    let url = format!("http://localhost:{}/song.mp3", upstream.port());
+    let path = state.media.proxy(&url, &Track::from_url(&url, SourceKind::Url));
+    assert_eq!(http(state, get_media(&path, None)).await.0, StatusCode::BAD_GATEWAY);
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+}
+
+#[tokio::test]
+async fn proxy_is_off_by_default() {
+    let (upstream, hits) = spawn_upstream().await;
+    let state = AppState::new();
+    let addr = spawn_server(state).await;
+    let mut ws = join(addr, "").await;
+
+    let url = format!("http://{}/song.mp3", upstream);
+    send(&mut ws, &ClientMessage::PlayRequest { id: 1, track_url: url.clone(), delay_ms: 0 }).await;
+    assert_eq!(next_play(&mut ws).await.0, url);
+    assert_eq!(hits.load(Ordering::SeqCst), 0);
+}
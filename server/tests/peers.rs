@@ -0,0 +1,135 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use common::*;
+use futures::SinkExt;
+use rust_core::messages::{ClientMessage, PeerInfo, ServerMessage, SyncState, PROTOCOL_VERSION};
+use rust_core::clock::{Clock, FakeClock};
+use server::app_state::{AppState, AppStateConfig, DRIFT_HISTORY_LEN};
+use std::sync::Arc;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+async fn next_roster(ws: &mut Ws) -> Vec<PeerInfo> {
+    loop {
+        if let ServerMessage::PeerList { peers } = recv_any(ws).await {
+            return peers;
+        }
+    }
+}
+
+#[tokio::test]
+async fn roster_follows_joins_telemetry_and_leaves() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut a = join(addr, "").await;
+    assert_eq!(next_roster(&mut a).await.len(), 1);
+
+    let (mut b, reply) = join_with(addr, "", None).await;
+    let ServerMessage::Welcome { session_id: b_id, .. } = reply else {
+        panic!("expected Welcome, got {:?}", reply);
+    };
+    let peers = next_roster(&mut a).await;
+    assert_eq!(peers.len(), 2);
+    assert_eq!(peers[1].session_id, b_id);
+
+    let telemetry = ClientMessage::Telemetry {
+        rtt: 4_000,
+        offset: -1_500,
+        drift: -3,
+        status: "playing".into(),
+        sync_state: SyncState::Locked,
+    };
+    send(&mut b, &telemetry).await;
+    let peer = loop {
+        if let ServerMessage::PeerUpdate { peer } = recv_any(&mut a).await {
+            break peer;
+        }
+    };
+    assert_eq!(peer.session_id, b_id);
+    // Negative offsets survive the round trip
+    assert_eq!(peer.offset, -1_500);
+    assert_eq!(peer.rtt, 4_000);
+    assert_eq!(peer.drift, -3);
+    assert_eq!(peer.status, "playing");
+    assert_eq!(peer.sync_state, SyncState::Locked);
+
+    leave(b).await;
+    assert_eq!(next_roster(&mut a).await.len(), 1);
+}
+
+#[tokio::test]
+async fn dashboard_gets_roster_as_json() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = connect(addr, "?type=dashboard").await;
+    let join = format!(
+        r#"{{"Join":{{"device_id":"DASHBOARD","protocol_version":{},"client_kind":"Dashboard","capabilities":0}}}}"#,
+        PROTOCOL_VERSION
+    );
+    ws.send(Message::Text(join)).await.unwrap();
+
+    loop {
+        let Message::Text(text) = next_frame(&mut ws).await else {
+            panic!("expected text frame");
+        };
+        if let ServerMessage::PeerList { peers } = serde_json::from_str(&text).unwrap() {
+            assert_eq!(peers[0].device_id, "DASHBOARD");
+            break;
+        }
+    }
+}
+
+#[tokio::test]
+async fn rest_lists_peers_with_token() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let _ws = join(addr, "").await;
+
+    let get = |auth: Option<String>| {
+        let mut req = Request::builder().uri("/peers");
+        if let Some(auth) = auth {
+            req = req.header("authorization", auth);
+        }
+        req.body(Body::empty()).unwrap()
+    };
+    let (status, _, _) = http(state.clone(), get(None)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    let token = format!("Bearer {}", state.default_room().control_token);
+    let (status, _, body) = http(state, get(Some(token))).await;
+    assert_eq!(status, StatusCode::OK);
+    let peers: Vec<PeerInfo> = serde_json::from_slice(&body).unwrap();
+    assert_eq!(peers.len(), 1);
+    assert_eq!(peers[0].device_id, "CLI-test");
+    assert!(peers[0].addr.starts_with("127.0.0.1:"));
+}
+
+#[tokio::test]
+async fn telemetry_keeps_drift_history_and_last_seen() {
+    let clock = Arc::new(FakeClock::new(1_000_000));
+    let state = AppState::with_config(AppStateConfig { clock: clock.clone(), ..AppStateConfig::default() });
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    for drift in 0..(DRIFT_HISTORY_LEN as i64 + 3) {
+        clock.advance(10_000);
+        let telemetry = ClientMessage::Telemetry {
+            rtt: 2_000,
+            offset: -250,
+            drift: -drift,
+            status: "playing".into(),
+            sync_state: SyncState::Locked,
+        };
+        send(&mut ws, &telemetry).await;
+        // Our own update comes back once it's applied
+        while !matches!(recv_any(&mut ws).await, ServerMessage::PeerUpdate { .. }) {}
+    }
+
+    let peer = state.default_room().roster().remove(0);
+    assert_eq!(peer.device_id, "CLI-test");
+    assert_eq!(peer.offset, -250);
+    assert_eq!(peer.last_seen, clock.now_micros());
+    // Bounded, oldest dropped first
+    assert_eq!(peer.drift_history.len(), DRIFT_HISTORY_LEN);
+    assert_eq!(peer.drift_history.first(), Some(&-3));
+    assert_eq!(peer.drift_history.last(), Some(&-(DRIFT_HISTORY_LEN as i64 + 2)));
+}
@@ -0,0 +1,244 @@
+mod common;
+
+use common::*;
+use rust_core::messages::{ClientMessage, ControlCommand, ErrorCode, QueueEntry, RepeatMode, Role, ServerMessage};
+use server::app_state::AppState;
+use server::control::process_control_command;
+use rust_core::track::Track;
+use server::queue::PlayQueue;
+use std::time::Duration;
+
+/// Next QueueUpdate, skipping everything else
+async fn next_queue(ws: &mut Ws) -> (Vec<QueueEntry>, Option<u32>, RepeatMode) {
+    loop {
+        if let ServerMessage::QueueUpdate { entries, current_entry, repeat } = recv(ws).await {
+            return (entries, current_entry, repeat);
+        }
+    }
+}
+
+/// Next PlayCommand's track and start time, skipping everything else
+async fn next_play(ws: &mut Ws) -> (String, u64) {
+    loop {
+        if let ServerMessage::PlayCommand { track_url, start_at_server_time, .. } = recv(ws).await {
+            return (track_url, start_at_server_time);
+        }
+    }
+}
+
+fn add(url: &str, duration_ms: Option<u64>) -> ControlCommand {
+    ControlCommand::QueueAdd { track_url: url.into(), duration_ms }
+}
+
+fn urls(entries: &[QueueEntry]) -> Vec<&str> {
+    entries.iter().map(|e| e.track_url.as_str()).collect()
+}
+
+#[tokio::test]
+async fn queue_edits_are_broadcast() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+
+    for url in ["a", "b", "c"] {
+        process_control_command(&room, Role::Host, add(url, None)).unwrap();
+    }
+    let (entries, current, _) = next_queue(&mut ws).await;
+    assert_eq!(urls(&entries), ["a"]);
+    assert_eq!(current, None);
+    next_queue(&mut ws).await;
+    let (entries, _, _) = next_queue(&mut ws).await;
+    assert_eq!(urls(&entries), ["a", "b", "c"]);
+
+    let c = entries[2].entry_id;
+    process_control_command(&room, Role::Host, ControlCommand::QueueMove { entry_id: c, to_index: 0 }).unwrap();
+    assert_eq!(urls(&next_queue(&mut ws).await.0), ["c", "a", "b"]);
+
+    let a = entries[0].entry_id;
+    process_control_command(&room, Role::Host, ControlCommand::QueueRemove { entry_id: a }).unwrap();
+    assert_eq!(urls(&next_queue(&mut ws).await.0), ["c", "b"]);
+
+    process_control_command(&room, Role::Host, ControlCommand::SetRepeat { mode: RepeatMode::All }).unwrap();
+    assert_eq!(next_queue(&mut ws).await.2, RepeatMode::All);
+
+    process_control_command(&room, Role::Host, ControlCommand::Shuffle).unwrap();
+    let mut shuffled = urls(&next_queue(&mut ws).await.0).into_iter().map(String::from).collect::<Vec<_>>();
+    shuffled.sort();
+    assert_eq!(shuffled, ["b", "c"]);
+
+    let err = process_control_command(&room, Role::Host, ControlCommand::QueueRemove { entry_id: a }).unwrap_err();
+    assert_eq!(err.code, ErrorCode::NotFound);
+}
+
+#[tokio::test]
+async fn listeners_cant_edit_the_queue() {
+    let addr = spawn_server(AppState::new()).await;
+    let _host = join(addr, "").await;
+    let mut guest = join(addr, "").await;
+
+    send(&mut guest, &ClientMessage::CommandRequest { id: 5, cmd: add("a", None) }).await;
+    match recv(&mut guest).await {
+        ServerMessage::Error { id, code, .. } => {
+            assert_eq!(id, 5);
+            assert_eq!(code, ErrorCode::Forbidden);
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn play_starts_the_queue_and_skips_move_through_it() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    process_control_command(&room, Role::Host, add("a", None)).unwrap();
+    process_control_command(&room, Role::Host, add("b", None)).unwrap();
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "a");
+    let (entries, current, _) = next_queue(&mut ws).await;
+    assert_eq!(current, Some(entries[0].entry_id));
+
+    process_control_command(&room, Role::Host, ControlCommand::Next).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "b");
+    let (entries, current, _) = next_queue(&mut ws).await;
+    assert_eq!(current, Some(entries[1].entry_id));
+
+    // Without repeat there's nothing after the last entry
+    let err = process_control_command(&room, Role::Host, ControlCommand::Next).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidRequest);
+
+    process_control_command(&room, Role::Host, ControlCommand::Previous).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "a");
+}
+
+#[tokio::test]
+async fn an_ad_hoc_track_takes_over_from_the_queue() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    process_control_command(&room, Role::Host, add("a", None)).unwrap();
+    process_control_command(&room, Role::Host, add("b", None)).unwrap();
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "a");
+    assert!(next_queue(&mut ws).await.1.is_some());
+
+    let url = "http://example.com/c.mp3";
+    send(&mut ws, &ClientMessage::PlayRequest { id: 1, track_url: url.into(), delay_ms: 100 }).await;
+    assert_eq!(next_play(&mut ws).await.0, url);
+    let (entries, current, _) = next_queue(&mut ws).await;
+    assert_eq!(urls(&entries), ["a", "b"]);
+    assert_eq!(current, None);
+
+    // The queue picks up after the entry that was playing
+    process_control_command(&room, Role::Host, ControlCommand::Next).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "b");
+}
+
+#[tokio::test]
+async fn finished_track_hands_over_to_the_next_without_a_gap() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    process_control_command(&room, Role::Host, add("a", Some(300))).unwrap();
+    process_control_command(&room, Role::Host, add("b", Some(200))).unwrap();
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    let (track, a_start) = next_play(&mut ws).await;
+    assert_eq!(track, "a");
+    // Announced ahead of time, starting exactly where "a" ends
+    let (track, b_start) = next_play(&mut ws).await;
+    assert_eq!(track, "b");
+    assert_eq!(b_start, a_start + 300_000);
+    let (entries, current, _) = next_queue(&mut ws).await;
+    assert_eq!(current, Some(entries[1].entry_id));
+
+    // End of the queue: playback stops once "b" is over
+    let b_end = b_start + 200_000;
+    tokio::time::sleep(Duration::from_micros(b_end.saturating_sub(state.clock.now_micros())) + Duration::from_millis(100)).await;
+    let pb = room.playback_state.read().unwrap().clone();
+    assert!(!pb.is_playing);
+    assert_eq!(pb.track_url, "b");
+}
+
+#[tokio::test]
+async fn repeat_one_replays_the_track() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    process_control_command(&room, Role::Host, add("a", Some(250))).unwrap();
+    process_control_command(&room, Role::Host, add("b", Some(250))).unwrap();
+    process_control_command(&room, Role::Host, ControlCommand::SetRepeat { mode: RepeatMode::One }).unwrap();
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 0 }).unwrap();
+    let (_, start) = next_play(&mut ws).await;
+    assert_eq!(next_play(&mut ws).await, ("a".to_string(), start + 250_000));
+    assert_eq!(next_play(&mut ws).await, ("a".to_string(), start + 500_000));
+}
+
+#[tokio::test]
+async fn pause_stops_the_queue_from_moving_on() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    // Long enough that the next entry isn't announced straight away
+    process_control_command(&room, Role::Host, add("a", Some(2_500))).unwrap();
+    process_control_command(&room, Role::Host, add("b", None)).unwrap();
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 0 }).unwrap();
+    assert_eq!(next_play(&mut ws).await.0, "a");
+    process_control_command(&room, Role::Host, ControlCommand::Pause).unwrap();
+
+    let late = tokio::time::timeout(Duration::from_millis(1_000), next_play(&mut ws)).await;
+    assert!(late.is_err(), "queue moved on while paused: {:?}", late);
+}
+
+#[tokio::test]
+async fn newcomers_get_the_queue() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    process_control_command(&state.default_room(), Role::Host, add("a", None)).unwrap();
+
+    let mut ws = join(addr, "").await;
+    assert_eq!(urls(&next_queue(&mut ws).await.0), ["a"]);
+}
+
+#[test]
+fn removing_the_current_entry_moves_on_to_its_successor() {
+    let mut queue = PlayQueue::default();
+    let a = queue.add("a".into(), Track::default());
+    let b = queue.add("b".into(), Track::default());
+    queue.add("c".into(), Track::default());
+
+    assert_eq!(queue.advance(false).unwrap().entry_id, a);
+    assert_eq!(queue.advance(false).unwrap().entry_id, b);
+    assert!(queue.remove(b));
+    assert!(queue.current().is_none());
+    assert_eq!(queue.advance(true).unwrap().track_url, "c");
+    assert!(queue.advance(true).is_none());
+
+    queue.set_repeat(RepeatMode::All);
+    assert_eq!(queue.advance(true).unwrap().entry_id, a);
+    assert_eq!(queue.back().unwrap().track_url, "c");
+}
+
+#[test]
+fn moving_entries_keeps_the_current_one() {
+    let mut queue = PlayQueue::default();
+    let a = queue.add("a".into(), Track::default());
+    queue.add("b".into(), Track::default());
+    let c = queue.add("c".into(), Track::default());
+    queue.advance(false);
+
+    assert!(queue.move_entry(c, 0));
+    assert!(!queue.move_entry(99, 0));
+    assert_eq!(queue.current().unwrap().entry_id, a);
+    assert_eq!(urls(queue.entries()), ["c", "a", "b"]);
+    assert_eq!(queue.advance(false).unwrap().track_url, "b");
+}
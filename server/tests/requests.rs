@@ -0,0 +1,96 @@
+mod common;
+
+use common::*;
+use rust_core::messages::{ClientMessage, ControlCommand, ErrorCode, ServerMessage};
+use server::app_state::AppState;
+
+#[tokio::test]
+async fn command_request_is_acked() {
+    let state = AppState::new();
+    state.default_room().playback_state.write().unwrap().track_url = "http://example.com/a.mp3".into();
+    let addr = spawn_server(state).await;
+    let mut ws = join(addr, "").await;
+
+    send(&mut ws, &ClientMessage::CommandRequest { id: 7, cmd: ControlCommand::Pause }).await;
+    // The broadcast and the ack may arrive in either order
+    let mut acked = false;
+    for _ in 0..2 {
+        match recv(&mut ws).await {
+            ServerMessage::Ack { id } => {
+                assert_eq!(id, 7);
+                acked = true;
+            }
+            ServerMessage::PauseCommand { .. } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert!(acked);
+}
+
+#[tokio::test]
+async fn failed_command_reports_error_with_id() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = join(addr, "").await;
+
+    let cmd = ControlCommand::Play { start_at_ms: 0, delay_ms: 500 };
+    send(&mut ws, &ClientMessage::CommandRequest { id: 42, cmd }).await;
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, message } => {
+            assert_eq!(id, 42);
+            assert_eq!(code, ErrorCode::NotFound);
+            assert!(!message.is_empty());
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn absurd_delay_is_refused() {
+    let state = AppState::new();
+    state.default_room().playback_state.write().unwrap().track_url = "http://example.com/a.mp3".into();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let cmd = ControlCommand::Play { start_at_ms: 0, delay_ms: u64::MAX };
+    send(&mut ws, &ClientMessage::CommandRequest { id: 8, cmd }).await;
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, .. } => assert_eq!((id, code), (8, ErrorCode::InvalidRequest)),
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(!state.default_room().playback_state.read().unwrap().is_playing);
+
+    let req = ClientMessage::PlayRequest { id: 9, track_url: "http://example.com/b.mp3".into(), delay_ms: u64::MAX / 2 };
+    send(&mut ws, &req).await;
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, .. } => assert_eq!((id, code), (9, ErrorCode::InvalidRequest)),
+        other => panic!("expected Error, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn play_request_is_acked_after_broadcast() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = join(addr, "").await;
+
+    let req = ClientMessage::PlayRequest { id: 3, track_url: "http://example.com/a.mp3".into(), delay_ms: 1000 };
+    send(&mut ws, &req).await;
+    let mut seen = Vec::new();
+    for _ in 0..2 {
+        seen.push(recv(&mut ws).await);
+    }
+    assert!(seen.iter().any(|m| matches!(m, ServerMessage::PlayCommand { .. })));
+    assert!(seen.iter().any(|m| matches!(m, ServerMessage::Ack { id: 3 })));
+}
+
+#[tokio::test]
+async fn rest_control_reports_error() {
+    let state = AppState::new();
+    let body = serde_json::to_string(&ControlCommand::Seek { position_ms: 1000 }).unwrap();
+    let mut req = json_request("POST", "/control", &body);
+    let token = format!("Bearer {}", state.default_room().control_token);
+    req.headers_mut().insert("authorization", token.parse().unwrap());
+    let (status, _, response) = http(state, req).await;
+    assert_eq!(status, 400);
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.contains("InvalidRequest"), "{}", response);
+}
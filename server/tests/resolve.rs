@@ -0,0 +1,234 @@
+mod common;
+
+use common::*;
+use futures::future::BoxFuture;
+use rust_core::messages::{ClientMessage, ControlCommand, ErrorCode, ServerMessage};
+use rust_core::track::{SourceKind, Track};
+use server::app_state::{AppState, AppStateConfig, SharedState};
+use server::resolve::{ResolveError, Resolved, Resolver, ResolverConfig, UrlResolver, YtDlp};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
+
+/// Stands in for yt-dlp: URLs containing "slow" hang, "missing" fails like an
+/// unavailable video, anything else resolves. Every call is logged to calls.log.
+const FAKE_YTDLP: &str = r#"#!/bin/sh
+for arg; do url=$arg; done
+echo "$url" >> "$(dirname "$0")/calls.log"
+case "$url" in
+  *slow*) sleep 5 ;;
+  *missing*) echo "ERROR: [generic] Video unavailable" >&2; exit 1 ;;
+esac
+printf '{"url":"https://media.example.com/%s.webm","title":"Fake Title","uploader":"Fake Artist","duration":212.5,"thumbnail":"https://img.example.com/t.jpg","ext":"webm"}\n' "${url##*/}"
+"#;
+
+/// Write the fake yt-dlp (once per test binary); returns its path
+fn fake_ytdlp() -> &'static PathBuf {
+    static DIR: OnceLock<PathBuf> = OnceLock::new();
+    DIR.get_or_init(|| {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("sonicsync-fake-ytdlp-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let _ = std::fs::remove_file(dir.join("calls.log"));
+        let program = dir.join("yt-dlp");
+        std::fs::write(&program, FAKE_YTDLP).unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        program
+    })
+}
+
+/// How many times the fake was asked to resolve `url`
+fn calls(url: &str) -> usize {
+    let log = std::fs::read_to_string(fake_ytdlp().with_file_name("calls.log")).unwrap_or_default();
+    log.lines().filter(|line| *line == url).count()
+}
+
+fn state_with(config: ResolverConfig) -> SharedState {
+    let resolver = Resolver::new(Arc::new(YtDlp::with_program(fake_ytdlp())), config);
+    AppState::with_config(AppStateConfig { resolver, ..AppStateConfig::default() })
+}
+
+#[tokio::test]
+async fn play_request_plays_the_resolved_stream() {
+    let state = state_with(ResolverConfig::default());
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let page = "https://video.example.com/watch-abc";
+    send(&mut ws, &ClientMessage::PlayRequest { id: 4, track_url: page.into(), delay_ms: 500 }).await;
+    let mut acked = false;
+    let mut played = None;
+    while !acked || played.is_none() {
+        match recv(&mut ws).await {
+            ServerMessage::Ack { id } => {
+                assert_eq!(id, 4);
+                acked = true;
+            }
+            ServerMessage::PlayCommand { track_url, track, .. } => played = Some((track_url, track)),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    let (track_url, track) = played.unwrap();
+    assert_eq!(track_url, "https://media.example.com/watch-abc.webm");
+    assert_eq!(track.title.as_deref(), Some("Fake Title"));
+    assert_eq!(track.artist.as_deref(), Some("Fake Artist"));
+    assert_eq!(track.duration_ms, Some(212_500));
+    assert_eq!(track.mime_type.as_deref(), Some("audio/webm"));
+    assert_eq!(track.artwork_url.as_deref(), Some("https://img.example.com/t.jpg"));
+    assert_eq!(track.source, SourceKind::Resolved);
+    assert_eq!(track.id, Track::from_url(page, SourceKind::Resolved).id);
+    assert_eq!(state.default_room().playback_state.read().unwrap().track_url, track_url);
+}
+
+#[tokio::test]
+async fn direct_audio_links_skip_the_resolver() {
+    let state = state_with(ResolverConfig::default());
+    let addr = spawn_server(state).await;
+    let mut ws = join(addr, "").await;
+
+    let url = "https://cdn.example.com/direct.mp3";
+    send(&mut ws, &ClientMessage::PlayRequest { id: 1, track_url: url.into(), delay_ms: 0 }).await;
+    loop {
+        if let ServerMessage::PlayCommand { track_url, track, .. } = recv(&mut ws).await {
+            assert_eq!(track_url, url);
+            assert_eq!(track.source, SourceKind::Url);
+            break;
+        }
+    }
+    assert_eq!(calls(url), 0);
+}
+
+#[tokio::test]
+async fn resolution_failure_is_reported_to_the_requester() {
+    let state = state_with(ResolverConfig::default());
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    send(&mut ws, &ClientMessage::PlayRequest { id: 9, track_url: "https://video.example.com/missing".into(), delay_ms: 0 }).await;
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, message } => {
+            assert_eq!(id, 9);
+            assert_eq!(code, ErrorCode::NotFound);
+            assert!(message.contains("Video unavailable"), "{}", message);
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(!state.default_room().playback_state.read().unwrap().is_playing);
+}
+
+#[tokio::test]
+async fn queued_page_urls_play_the_resolved_stream() {
+    let state = state_with(ResolverConfig::default());
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let page = "https://video.example.com/queued";
+    let add = ControlCommand::QueueAdd { track_url: page.into(), duration_ms: None };
+    send(&mut ws, &ClientMessage::CommandRequest { id: 3, cmd: add }).await;
+    loop {
+        match recv(&mut ws).await {
+            ServerMessage::Ack { id } => break assert_eq!(id, 3),
+            ServerMessage::QueueUpdate { .. } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    let play = ControlCommand::Play { start_at_ms: 0, delay_ms: 100 };
+    send(&mut ws, &ClientMessage::CommandRequest { id: 4, cmd: play }).await;
+    let (track_url, track) = loop {
+        if let ServerMessage::PlayCommand { track_url, track, .. } = recv(&mut ws).await {
+            break (track_url, track);
+        }
+    };
+    assert_eq!(track_url, "https://media.example.com/queued.webm");
+    assert_eq!(track.title.as_deref(), Some("Fake Title"));
+    assert_eq!(track.duration_ms, Some(212_500));
+    assert_eq!(track.source, SourceKind::Resolved);
+
+    // A page that won't resolve is refused to whoever queued it and never reaches the queue
+    let add = ControlCommand::QueueAdd { track_url: "https://video.example.com/missing-queued".into(), duration_ms: None };
+    send(&mut ws, &ClientMessage::CommandRequest { id: 5, cmd: add }).await;
+    loop {
+        match recv(&mut ws).await {
+            ServerMessage::Error { id, code, .. } => {
+                assert_eq!((id, code), (5, ErrorCode::NotFound));
+                break;
+            }
+            ServerMessage::Ack { id: 4 } | ServerMessage::QueueUpdate { .. } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+    assert_eq!(state.default_room().queue.lock().unwrap().entries().len(), 1);
+}
+
+#[tokio::test]
+async fn slow_resolution_times_out() {
+    let state = state_with(ResolverConfig { timeout: Duration::from_millis(300), ..ResolverConfig::default() });
+    let addr = spawn_server(state).await;
+    let mut ws = join(addr, "").await;
+
+    let started = Instant::now();
+    send(&mut ws, &ClientMessage::PlayRequest { id: 2, track_url: "https://video.example.com/slow".into(), delay_ms: 0 }).await;
+    match recv(&mut ws).await {
+        ServerMessage::Error { id, code, .. } => {
+            assert_eq!(id, 2);
+            assert_eq!(code, ErrorCode::Internal);
+        }
+        other => panic!("expected Error, got {:?}", other),
+    }
+    assert!(started.elapsed() < Duration::from_secs(3), "took {:?}", started.elapsed());
+}
+
+#[tokio::test]
+async fn resolved_urls_are_cached_until_they_expire() {
+    let state = state_with(ResolverConfig::default());
+    let page = "https://video.example.com/cached";
+    let first = state.resolver.resolve(page).await.unwrap();
+    let second = state.resolver.resolve(page).await.unwrap();
+    assert_eq!(first.stream_url, second.stream_url);
+    assert_eq!(calls(page), 1);
+
+    let state = state_with(ResolverConfig { cache_ttl: Duration::ZERO, ..ResolverConfig::default() });
+    let page = "https://video.example.com/uncached";
+    state.resolver.resolve(page).await.unwrap();
+    state.resolver.resolve(page).await.unwrap();
+    assert_eq!(calls(page), 2);
+}
+
+/// Resolves anything after a short wait, tracking how many resolutions overlap
+#[derive(Default)]
+struct Counting {
+    running: AtomicUsize,
+    most: AtomicUsize,
+}
+
+impl UrlResolver for Counting {
+    fn resolve<'a>(&'a self, url: &'a str) -> BoxFuture<'a, Result<Resolved, ResolveError>> {
+        Box::pin(async move {
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.most.fetch_max(running, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            Ok(Resolved { stream_url: format!("{}.ogg", url), track: Track::default() })
+        })
+    }
+}
+
+#[tokio::test]
+async fn resolutions_are_limited_in_number() {
+    let backend = Arc::new(Counting::default());
+    let config = ResolverConfig { max_concurrent: 2, ..ResolverConfig::default() };
+    let resolver = Arc::new(Resolver::new(backend.clone(), config));
+
+    let tasks: Vec<_> = (0..6)
+        .map(|i| {
+            let resolver = resolver.clone();
+            tokio::spawn(async move { resolver.resolve(&format!("https://video.example.com/{}", i)).await })
+        })
+        .collect();
+    for task in tasks {
+        task.await.unwrap().unwrap();
+    }
+    assert_eq!(backend.most.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,137 @@
+mod common;
+
+use common::*;
+use rust_core::messages::{DisconnectReason, Role, ServerMessage};
+use server::app_state::{AppState, AppStateConfig, HeartbeatConfig, SharedState};
+use std::time::Duration;
+use tokio_tungstenite::tungstenite::protocol::Message;
+
+fn with_grace(resume_grace: Duration) -> SharedState {
+    let heartbeat = HeartbeatConfig { resume_grace, ..HeartbeatConfig::default() };
+    AppState::with_config(AppStateConfig { heartbeat, ..AppStateConfig::default() })
+}
+
+fn welcome(reply: ServerMessage) -> (String, String, bool, Role) {
+    match reply {
+        ServerMessage::Welcome { session_id, resume_token, resumed, role, .. } => (session_id, resume_token, resumed, role),
+        other => panic!("expected Welcome, got {:?}", other),
+    }
+}
+
+async fn wait_until(mut done: impl FnMut() -> bool) {
+    for _ in 0..50 {
+        if done() {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(done());
+}
+
+#[tokio::test]
+async fn dropped_host_resumes_same_session() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let (ws, reply) = join_with(addr, "?room=party", None).await;
+    let (id, token, resumed, role) = welcome(reply);
+    assert!(!resumed);
+    assert_eq!(role, Role::Host);
+
+    drop(ws);
+    let room = state.room("party").unwrap();
+    wait_until(|| room.peers.get(&id).is_some_and(|p| !p.is_online())).await;
+
+    let (_ws, reply) = resume(addr, "?room=party", &token).await;
+    let (resumed_id, resumed_token, resumed, role) = welcome(reply);
+    assert_eq!(resumed_id, id);
+    assert_eq!(resumed_token, token);
+    assert!(resumed);
+    assert_eq!(role, Role::Host);
+    assert_eq!(room.peers.len(), 1);
+    assert!(room.departures().is_empty());
+}
+
+#[tokio::test]
+async fn resumed_client_gets_current_playback() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let (ws, reply) = join_with(addr, "", None).await;
+    let (_, token, _, _) = welcome(reply);
+    drop(ws);
+
+    let room = state.default_room();
+    {
+        let mut pb = room.playback_state.write().unwrap();
+        pb.track_url = "http://example.com/a.mp3".into();
+        pb.position_ms = 12_000;
+    }
+    let (mut ws, reply) = resume(addr, "", &token).await;
+    assert!(welcome(reply).2);
+    // Paused with a track loaded: a resumed client is told to stay paused
+    assert!(matches!(recv(&mut ws).await, ServerMessage::PauseCommand { .. }));
+    drop(ws);
+
+    room.playback_state.write().unwrap().is_playing = true;
+    let (mut ws, reply) = resume(addr, "", &token).await;
+    assert!(welcome(reply).2);
+    match recv(&mut ws).await {
+        ServerMessage::PlayCommand { track_url, .. } => assert_eq!(track_url, "http://example.com/a.mp3"),
+        other => panic!("expected PlayCommand, got {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn unknown_token_starts_fresh_session() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let (_ws, reply) = resume(addr, "", "not-a-token").await;
+    let (id, token, resumed, _) = welcome(reply);
+    assert!(!resumed);
+    assert_ne!(token, "not-a-token");
+    assert!(state.default_room().peers.contains_key(&id));
+}
+
+#[tokio::test]
+async fn suspended_session_expires_after_grace() {
+    let state = with_grace(Duration::from_millis(100));
+    let addr = spawn_server(state.clone()).await;
+    let (ws, reply) = join_with(addr, "", None).await;
+    let (id, token, _, _) = welcome(reply);
+    drop(ws);
+
+    let room = state.default_room();
+    wait_until(|| room.peers.is_empty()).await;
+    let departed = room.departures();
+    assert_eq!(departed.len(), 1);
+    assert_eq!(departed[0].session_id, id);
+    assert_eq!(departed[0].reason, DisconnectReason::ConnectionLost);
+
+    // Too late: the token no longer matches anything
+    let (_ws, reply) = resume(addr, "", &token).await;
+    let (new_id, _, resumed, _) = welcome(reply);
+    assert!(!resumed);
+    assert_ne!(new_id, id);
+}
+
+#[tokio::test]
+async fn resume_takes_over_live_connection() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let (mut old, reply) = join_with(addr, "", None).await;
+    let (id, token, _, _) = welcome(reply);
+
+    let (_new, reply) = resume(addr, "", &token).await;
+    let (resumed_id, _, resumed, _) = welcome(reply);
+    assert_eq!(resumed_id, id);
+    assert!(resumed);
+
+    // The superseded socket is closed rather than left half-alive
+    loop {
+        match next_frame(&mut old).await {
+            Message::Close(_) => break,
+            _ => continue,
+        }
+    }
+    assert_eq!(state.default_room().peers.len(), 1);
+    assert!(state.default_room().departures().is_empty());
+}
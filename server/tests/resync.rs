@@ -0,0 +1,89 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{Request, StatusCode};
+use common::*;
+use rust_core::messages::{ClientMessage, ServerMessage, SyncState};
+use server::app_state::{AppState, AppStateConfig, SharedState, SyncTolerance};
+use std::time::Duration;
+
+fn resync_request(state: &SharedState, query: &str) -> Request<Body> {
+    Request::builder()
+        .method("POST")
+        .uri(format!("/resync{}", query))
+        .header("authorization", format!("Bearer {}", state.default_room().control_token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Next SyncRequired, skipping everything else; false if none arrives within `wait`
+async fn sync_required_within(ws: &mut Ws, wait: Duration) -> bool {
+    tokio::time::timeout(wait, async {
+        while !matches!(recv_any(ws).await, ServerMessage::SyncRequired) {}
+    })
+    .await
+    .is_ok()
+}
+
+fn telemetry(offset: i64, drift: i64) -> ClientMessage {
+    ClientMessage::Telemetry { rtt: 2_000, offset, drift, status: "playing".into(), sync_state: SyncState::Locked }
+}
+
+#[tokio::test]
+async fn drift_out_of_tolerance_asks_that_peer_to_resync() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut good = join(addr, "").await;
+    let mut bad = join(addr, "").await;
+
+    send(&mut good, &telemetry(1_000, 3)).await;
+    send(&mut bad, &telemetry(1_000, 120)).await;
+    assert!(sync_required_within(&mut bad, Duration::from_secs(2)).await);
+    assert!(!sync_required_within(&mut good, Duration::from_millis(200)).await);
+
+    // Still drifting, but inside the cooldown: no repeat
+    send(&mut bad, &telemetry(1_000, 150)).await;
+    assert!(!sync_required_within(&mut bad, Duration::from_millis(200)).await);
+}
+
+#[tokio::test]
+async fn offset_step_between_reports_asks_for_resync() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut ws = join(addr, "").await;
+
+    // The first report has nothing to compare against
+    send(&mut ws, &telemetry(-40_000, 0)).await;
+    send(&mut ws, &telemetry(-39_000, 0)).await;
+    assert!(!sync_required_within(&mut ws, Duration::from_millis(200)).await);
+    send(&mut ws, &telemetry(-20_000, 0)).await;
+    assert!(sync_required_within(&mut ws, Duration::from_secs(2)).await);
+}
+
+#[tokio::test]
+async fn rest_resync_targets_one_member_or_the_room() {
+    let tolerance = SyncTolerance { cooldown: Duration::ZERO, ..SyncTolerance::default() };
+    let state = AppState::with_config(AppStateConfig { sync_tolerance: tolerance, ..AppStateConfig::default() });
+    let addr = spawn_server(state.clone()).await;
+    let mut a = join(addr, "").await;
+    let (mut b, reply) = join_with(addr, "", None).await;
+    let ServerMessage::Welcome { session_id: b_id, .. } = reply else {
+        panic!("expected Welcome, got {:?}", reply);
+    };
+
+    let (status, _, body) = http(state.clone(), resync_request(&state, &format!("?session={}", b_id))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_slice::<serde_json::Value>(&body).unwrap()["requested"], 1);
+    assert!(sync_required_within(&mut b, Duration::from_secs(2)).await);
+    assert!(!sync_required_within(&mut a, Duration::from_millis(200)).await);
+
+    let (status, _, _) = http(state.clone(), resync_request(&state, "")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(sync_required_within(&mut a, Duration::from_secs(2)).await);
+    assert!(sync_required_within(&mut b, Duration::from_secs(2)).await);
+
+    let (status, _, _) = http(state.clone(), resync_request(&state, "?session=nobody")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let mut unauthorized = resync_request(&state, "");
+    unauthorized.headers_mut().insert("authorization", "Bearer nope".parse().unwrap());
+    let (status, _, _) = http(state.clone(), unauthorized).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
@@ -0,0 +1,58 @@
+mod common;
+
+use axum::http::StatusCode;
+use common::*;
+use rust_core::messages::{ClientMessage, ControlCommand, ServerMessage};
+use server::app_state::AppState;
+use std::time::Duration;
+
+#[tokio::test]
+async fn commands_stay_in_their_room() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut a = join(addr, "?room=a").await;
+    let mut b = join(addr, "?room=b").await;
+    state.room("a").unwrap().playback_state.write().unwrap().track_url = "http://example.com/a.mp3".into();
+
+    send(&mut a, &ClientMessage::CommandRequest { id: 1, cmd: ControlCommand::Pause }).await;
+    for _ in 0..2 {
+        match recv(&mut a).await {
+            ServerMessage::Ack { .. } | ServerMessage::PauseCommand { .. } => {}
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    // Room b heard nothing
+    let quiet = tokio::time::timeout(Duration::from_millis(200), recv(&mut b)).await;
+    assert!(quiet.is_err(), "room b received {:?}", quiet);
+}
+
+#[tokio::test]
+async fn empty_room_is_removed() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let ws = join(addr, "?room=party").await;
+    assert!(state.room("party").is_some());
+
+    leave(ws).await;
+    for _ in 0..50 {
+        if state.room("party").is_none() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    assert!(state.room("party").is_none());
+    // The default room is never cleaned up
+    assert!(state.room("default").is_some());
+}
+
+#[tokio::test]
+async fn unknown_or_invalid_rooms_are_rejected() {
+    let state = AppState::new();
+    let (status, _, _) = http(state.clone(), json_request("POST", "/control?room=nope", r#""Pause""#)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let addr = spawn_server(state).await;
+    let url = format!("ws://{}/ws?room=not%20valid", addr);
+    assert!(tokio_tungstenite::connect_async(url).await.is_err());
+}
@@ -0,0 +1,130 @@
+mod common;
+
+use axum::body::Body;
+use axum::http::{header, HeaderValue, Request, StatusCode};
+use common::*;
+use server::app_state::{AppState, HostedFile, SharedState};
+use std::path::PathBuf;
+
+fn audio() -> Vec<u8> {
+    let mut bytes = b"ID3\x04\x00".to_vec();
+    bytes.extend((0..10_000u32).map(|i| (i % 251) as u8));
+    bytes
+}
+
+/// Host `bytes` as `name` in the default room
+fn hosting(name: &str, bytes: &[u8]) -> (SharedState, PathBuf) {
+    let dir = std::env::temp_dir().join(format!("sonicsync-stream-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(name);
+    std::fs::write(&path, bytes).unwrap();
+    let state = AppState::new();
+    *state.default_room().hosted_file.write().unwrap() = Some(HostedFile::new(path.to_string_lossy().into_owned()));
+    (state, path)
+}
+
+fn get(headers: &[(header::HeaderName, &str)]) -> Request<Body> {
+    let mut req = Request::builder().uri("/stream");
+    for (name, value) in headers {
+        req = req.header(name, *value);
+    }
+    req.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn full_download_has_validators_and_type() {
+    let (state, _) = hosting("full.mp3", &audio());
+    let (status, headers, body) = http(state, get(&[])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, audio());
+    assert_eq!(headers[header::CONTENT_TYPE], "audio/mpeg");
+    assert_eq!(headers[header::ACCEPT_RANGES], "bytes");
+    assert!(headers.contains_key(header::ETAG));
+    assert!(headers.contains_key(header::LAST_MODIFIED));
+}
+
+#[tokio::test]
+async fn range_requests_seek_into_the_file() {
+    let (state, _) = hosting("seek.mp3", &audio());
+    let len = audio().len();
+
+    let (status, headers, body) = http(state.clone(), get(&[(header::RANGE, "bytes=100-199")])).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(headers[header::CONTENT_RANGE], format!("bytes 100-199/{}", len));
+    assert_eq!(headers[header::CONTENT_LENGTH], "100");
+    assert_eq!(body, audio()[100..200]);
+
+    // Open-ended, as players do when seeking
+    let (status, _, body) = http(state.clone(), get(&[(header::RANGE, "bytes=9000-")])).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, audio()[9000..]);
+
+    // Last bytes, as players do when looking for trailing tags
+    let (status, _, body) = http(state.clone(), get(&[(header::RANGE, "bytes=-128")])).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, audio()[len - 128..]);
+
+    let (status, headers, _) = http(state, get(&[(header::RANGE, "bytes=20000-20100")])).await;
+    assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+    assert_eq!(headers[header::CONTENT_RANGE], format!("bytes */{}", len));
+}
+
+#[tokio::test]
+async fn unchanged_files_are_not_sent_again() {
+    let (state, _) = hosting("cached.mp3", &audio());
+    let (_, headers, _) = http(state.clone(), get(&[])).await;
+    let etag = headers[header::ETAG].to_str().unwrap().to_string();
+    let last_modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+
+    let (status, headers, body) = http(state.clone(), get(&[(header::IF_NONE_MATCH, &etag)])).await;
+    assert_eq!(status, StatusCode::NOT_MODIFIED);
+    assert_eq!(headers[header::ETAG], HeaderValue::from_str(&etag).unwrap());
+    assert!(body.is_empty());
+
+    let weak = format!("\"other\", W/{}", etag);
+    assert_eq!(http(state.clone(), get(&[(header::IF_NONE_MATCH, &weak)])).await.0, StatusCode::NOT_MODIFIED);
+    assert_eq!(http(state.clone(), get(&[(header::IF_MODIFIED_SINCE, &last_modified)])).await.0, StatusCode::NOT_MODIFIED);
+    assert_eq!(http(state.clone(), get(&[(header::IF_NONE_MATCH, "\"stale\"")])).await.0, StatusCode::OK);
+    assert_eq!(http(state, get(&[(header::IF_MATCH, "\"stale\"")])).await.0, StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn if_range_only_resumes_the_same_file() {
+    let (state, path) = hosting("resume.mp3", &audio());
+    let (_, headers, _) = http(state.clone(), get(&[])).await;
+    let etag = headers[header::ETAG].to_str().unwrap().to_string();
+    let last_modified = headers[header::LAST_MODIFIED].to_str().unwrap().to_string();
+
+    let (status, _, body) = http(state.clone(), get(&[(header::RANGE, "bytes=10-19"), (header::IF_RANGE, &etag)])).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+    assert_eq!(body, audio()[10..20]);
+    let (status, _, _) = http(state.clone(), get(&[(header::RANGE, "bytes=10-19"), (header::IF_RANGE, &last_modified)])).await;
+    assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+
+    // The host swapped the file: the client's partial copy is useless, send everything
+    let replaced = vec![7u8; 4_000];
+    std::fs::write(&path, &replaced).unwrap();
+    let (status, _, body) = http(state, get(&[(header::RANGE, "bytes=10-19"), (header::IF_RANGE, &etag)])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, replaced);
+}
+
+#[tokio::test]
+async fn type_is_sniffed_when_the_name_says_nothing() {
+    let mut flac = b"fLaC\x00\x00\x00\x22".to_vec();
+    flac.extend([0u8; 64]);
+    let (state, _) = hosting("recording", &flac);
+    let (status, headers, _) = http(state, get(&[])).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers[header::CONTENT_TYPE], "audio/flac");
+}
+
+#[tokio::test]
+async fn nothing_hosted_is_not_found() {
+    let (status, _, _) = http(AppState::new(), get(&[])).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (state, path) = hosting("gone.mp3", &audio());
+    std::fs::remove_file(path).unwrap();
+    assert_eq!(http(state, get(&[(header::RANGE, "bytes=0-9")])).await.0, StatusCode::NOT_FOUND);
+}
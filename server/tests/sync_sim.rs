@@ -0,0 +1,375 @@
+//! Deterministic virtual-time simulation of the sync protocol.
+//!
+//! Runs the real `handle_client_message` against N simulated clients, each with
+//! its own clock offset, skew, asymmetric latency, jitter and packet loss. All
+//! time is virtual, so results are reproducible from the seed.
+
+use rust_core::clock::{ClockFilter, FakeClock, SkewEstimator};
+use rust_core::messages::{ClientKind, ClientMessage, ControlCommand, ServerMessage, PROTOCOL_VERSION};
+use rust_core::pid::PidController;
+use server::app_state::{AppState, AppStateConfig, Peer, SharedState};
+use server::handlers::Session;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+
+const SERVER_EPOCH: u64 = 1_700_000_000_000_000;
+const LOCAL_EPOCH: u64 = 1_699_000_000_000_000;
+const SYNC_INTERVAL_US: u64 = 2_000_000; // Matches the Android sync heartbeat
+const PLAYBACK_TICK_US: u64 = 100_000; // Matches the Android drift correction loop
+const SOFT_RESYNC_MS: f64 = 15.0; // Android: PID speed correction above this drift
+const HARD_RESYNC_MS: f64 = 200.0; // Android: seek above this drift
+
+/// xorshift64*, so runs don't depend on an RNG crate's algorithm
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        if n == 0 { 0 } else { self.next() % n }
+    }
+
+    fn chance(&mut self, p: f64) -> bool {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64 <= p
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Link {
+    offset_us: i64,
+    skew_ppm: f64,
+    uplink_us: u64,
+    downlink_us: u64,
+    jitter_us: u64,
+    loss: f64,
+}
+
+impl Link {
+    fn lan(offset_us: i64, skew_ppm: f64) -> Self {
+        Self { offset_us, skew_ppm, uplink_us: 3_000, downlink_us: 3_000, jitter_us: 1_000, loss: 0.0 }
+    }
+}
+
+struct Playback {
+    start_at_server_time: u64,
+    start_at_position_ms: u64,
+    position_ms: f64,
+    speed: f64,
+    pid: PidController,
+}
+
+struct SimClient {
+    link: Link,
+    session: Session,
+    rx: broadcast::Receiver<ServerMessage>,
+    outbox_rx: mpsc::UnboundedReceiver<ServerMessage>,
+    filter: ClockFilter,
+    skew: SkewEstimator,
+    seq: u8,
+    /// True time at which this client started playing
+    started_at: Option<u64>,
+    playback: Option<Playback>,
+}
+
+impl SimClient {
+    fn local_time(&self, true_us: u64) -> u64 {
+        let drifted = true_us as f64 * (1.0 + self.link.skew_ppm / 1_000_000.0);
+        (LOCAL_EPOCH as i64 + drifted as i64 + self.link.offset_us) as u64
+    }
+
+    fn true_time(&self, local_us: u64) -> u64 {
+        let drifted = local_us as i64 - LOCAL_EPOCH as i64 - self.link.offset_us;
+        (drifted as f64 / (1.0 + self.link.skew_ppm / 1_000_000.0)).round() as u64
+    }
+
+    /// Same policy as the clients: drift-compensated once skew is fitted
+    fn server_time_at(&self, local_us: u64) -> u64 {
+        match self.skew.estimate() {
+            Some(est) => est.server_time_at(local_us),
+            None => {
+                let offset = self.filter.estimate().map(|e| e.offset).unwrap_or(0);
+                (local_us as i64 + offset) as u64
+            }
+        }
+    }
+}
+
+enum Event {
+    ToServer { client: usize, msg: ClientMessage },
+    ToClient { client: usize, msg: ServerMessage },
+    SyncTick { client: usize },
+    PlaybackTick { client: usize },
+}
+
+struct Sim {
+    now: u64,
+    seq: u64,
+    rng: Rng,
+    clock: Arc<FakeClock>,
+    state: SharedState,
+    clients: Vec<SimClient>,
+    queue: BTreeMap<(u64, u64), Event>,
+}
+
+impl Sim {
+    fn new(seed: u64, links: &[Link]) -> Self {
+        let clock = Arc::new(FakeClock::new(SERVER_EPOCH));
+        let state = AppState::with_config(AppStateConfig { clock: clock.clone(), ..AppStateConfig::default() });
+        let room = state.default_room();
+        let clients = links
+            .iter()
+            .enumerate()
+            .map(|(i, link)| {
+                // Client 0 joins first, so it is the room's host
+                let id = format!("sim-{}", i);
+                let peer = Peer::new("127.0.0.1:0".parse().unwrap(), id.clone(), 0, ClientKind::Cli, PROTOCOL_VERSION, 0);
+                room.peers.insert(id.clone(), Arc::new(peer));
+                room.admit(&id, None).unwrap();
+                let (outbox, outbox_rx) = mpsc::unbounded_channel();
+                SimClient {
+                    link: *link,
+                    session: Session { state: state.clone(), room: room.clone(), id, outbox },
+                    rx: room.tx.subscribe(),
+                    outbox_rx,
+                    filter: ClockFilter::default(),
+                    skew: SkewEstimator::default(),
+                    seq: 0,
+                    started_at: None,
+                    playback: None,
+                }
+            })
+            .collect();
+
+        let mut sim = Self { now: 0, seq: 0, rng: Rng(seed), clock, state, clients, queue: BTreeMap::new() };
+        for client in 0..links.len() {
+            // Stagger the first probes so clients don't sync in lockstep
+            let at = sim.rng.below(SYNC_INTERVAL_US);
+            sim.schedule(at, Event::SyncTick { client });
+        }
+        sim
+    }
+
+    fn schedule(&mut self, at: u64, event: Event) {
+        self.seq += 1;
+        self.queue.insert((at, self.seq), event);
+    }
+
+    fn send_to_server(&mut self, client: usize, msg: ClientMessage) {
+        let link = self.clients[client].link;
+        if self.rng.chance(link.loss) {
+            return;
+        }
+        let delay = link.uplink_us + self.rng.below(link.jitter_us);
+        self.schedule(self.now + delay, Event::ToServer { client, msg });
+    }
+
+    fn send_to_client(&mut self, client: usize, msg: ServerMessage) {
+        let link = self.clients[client].link;
+        if self.rng.chance(link.loss) {
+            return;
+        }
+        let delay = link.downlink_us + self.rng.below(link.jitter_us);
+        self.schedule(self.now + delay, Event::ToClient { client, msg });
+    }
+
+    async fn run_until(&mut self, until: u64) {
+        while let Some(entry) = self.queue.first_entry() {
+            let (at, _) = *entry.key();
+            if at > until {
+                break;
+            }
+            let event = entry.remove();
+            self.now = at;
+            self.clock.set(SERVER_EPOCH + at);
+            self.dispatch(event).await;
+        }
+        self.now = until;
+        self.clock.set(SERVER_EPOCH + until);
+    }
+
+    async fn dispatch(&mut self, event: Event) {
+        match event {
+            Event::ToServer { client, msg } => {
+                server::handlers::handle_client_message(msg, &self.clients[client].session).await;
+                // Let any task spawned by the handler run at this instant
+                tokio::task::yield_now().await;
+                for i in 0..self.clients.len() {
+                    while let Ok(msg) = self.clients[i].outbox_rx.try_recv() {
+                        self.send_to_client(i, msg);
+                    }
+                    while let Ok(msg) = self.clients[i].rx.try_recv() {
+                        self.send_to_client(i, msg);
+                    }
+                }
+            }
+            Event::ToClient { client, msg } => self.client_receive(client, msg),
+            Event::SyncTick { client } => {
+                let c = &mut self.clients[client];
+                let t0 = c.local_time(self.now);
+                let seq = c.seq;
+                c.seq = c.seq.wrapping_add(1);
+                self.send_to_server(client, ClientMessage::TimeRequest { t0, seq });
+                self.schedule(self.now + SYNC_INTERVAL_US, Event::SyncTick { client });
+            }
+            Event::PlaybackTick { client } => {
+                let local_dt = self.clients[client].local_time(self.now)
+                    - self.clients[client].local_time(self.now - PLAYBACK_TICK_US);
+                let server_now = {
+                    let c = &self.clients[client];
+                    c.server_time_at(c.local_time(self.now))
+                };
+                let c = &mut self.clients[client];
+                if let Some(pb) = c.playback.as_mut() {
+                    // The audio clock runs off the local oscillator
+                    pb.position_ms += local_dt as f64 / 1000.0 * pb.speed;
+                    let expected = pb.start_at_position_ms as f64
+                        + (server_now as f64 - pb.start_at_server_time as f64) / 1000.0;
+                    let drift = pb.position_ms - expected;
+                    if drift.abs() > HARD_RESYNC_MS {
+                        pb.position_ms = expected;
+                    } else if drift.abs() > SOFT_RESYNC_MS {
+                        let correction = pb.pid.next(-drift, PLAYBACK_TICK_US as f64 / 1_000_000.0);
+                        pb.speed = (1.0 + correction).clamp(0.95, 1.05);
+                    } else {
+                        pb.speed = 1.0;
+                    }
+                    self.schedule(self.now + PLAYBACK_TICK_US, Event::PlaybackTick { client });
+                }
+            }
+        }
+    }
+
+    fn client_receive(&mut self, client: usize, msg: ServerMessage) {
+        let c = &mut self.clients[client];
+        let local_now = c.local_time(self.now);
+        match msg {
+            ServerMessage::TimeResponse { t0, t1, t2, .. } => {
+                c.filter.add_timestamps(t0, t1, t2, local_now);
+                c.skew.add_timestamps(t0, t1, t2, local_now);
+            }
+            ServerMessage::PlayCommand { start_at_server_time, start_at_position_ms, .. } => {
+                // Local instant at which the server clock reaches the start time
+                let server_now = c.server_time_at(local_now);
+                let local_start = local_now as i64 + (start_at_server_time as i64 - server_now as i64);
+                let started_at = c.true_time(local_start as u64).max(self.now);
+                c.started_at = Some(started_at);
+                c.playback = Some(Playback {
+                    start_at_server_time,
+                    start_at_position_ms,
+                    position_ms: start_at_position_ms as f64,
+                    speed: 1.0,
+                    pid: PidController::new(0.005, 0.0001, 0.001), // Same tuning as the bridge
+                });
+                self.schedule(started_at + PLAYBACK_TICK_US, Event::PlaybackTick { client });
+            }
+            _ => {}
+        }
+    }
+
+    fn start_spread_us(&self) -> u64 {
+        let starts: Vec<u64> = self
+            .clients
+            .iter()
+            .map(|c| c.started_at.expect("client never received PlayCommand"))
+            .collect();
+        starts.iter().max().unwrap() - starts.iter().min().unwrap()
+    }
+
+    /// Spread of playback positions across clients at the current true time
+    fn position_spread_ms(&self) -> f64 {
+        let positions: Vec<f64> = self
+            .clients
+            .iter()
+            .map(|c| c.playback.as_ref().expect("client not playing").position_ms)
+            .collect();
+        let max = positions.iter().cloned().fold(f64::MIN, f64::max);
+        let min = positions.iter().cloned().fold(f64::MAX, f64::min);
+        max - min
+    }
+
+    fn host_play(&mut self, host: usize, delay_ms: u64) {
+        self.state.default_room().playback_state.write().unwrap().track_url = "http://example.com/a.mp3".into();
+        let cmd = ControlCommand::Play { start_at_ms: 0, delay_ms };
+        self.send_to_server(host, ClientMessage::CommandRequest { id: 1, cmd });
+    }
+}
+
+fn party_links() -> Vec<Link> {
+    vec![
+        Link::lan(0, 0.0),
+        Link::lan(-2_500_000, 35.0),
+        Link::lan(4_000_000_000, -20.0),
+        Link::lan(120_000, 50.0),
+        Link::lan(-80_000_000, -45.0),
+    ]
+}
+
+#[tokio::test]
+async fn clean_lan_clients_start_together() {
+    let mut sim = Sim::new(7, &party_links());
+    sim.run_until(20_000_000).await;
+    sim.host_play(0, 1_500);
+    sim.run_until(25_000_000).await;
+
+    let spread = sim.start_spread_us();
+    assert!(spread < 2_000, "start spread {}us", spread);
+}
+
+#[tokio::test]
+async fn asymmetric_lossy_links_start_within_tolerance() {
+    let mut links = party_links();
+    for (i, link) in links.iter_mut().enumerate() {
+        link.uplink_us = 2_000 + i as u64 * 2_000;
+        link.downlink_us = 2_000;
+        link.jitter_us = 8_000;
+        link.loss = 0.15;
+    }
+    let mut sim = Sim::new(1234, &links);
+    sim.run_until(30_000_000).await;
+    // Lossy links: retry until every client has the command
+    for _ in 0..10 {
+        sim.host_play(0, 2_000);
+        sim.run_until(sim.now + 3_000_000).await;
+        if sim.clients.iter().all(|c| c.started_at.is_some()) {
+            break;
+        }
+        for c in sim.clients.iter_mut() {
+            c.started_at = None;
+            c.playback = None;
+        }
+    }
+
+    // Worst-case asymmetry is (10ms - 2ms) / 2 = 4ms of bias on one client
+    let spread = sim.start_spread_us();
+    assert!(spread < 6_000, "start spread {}us", spread);
+}
+
+#[tokio::test]
+async fn play_request_path_starts_together() {
+    let mut sim = Sim::new(99, &party_links());
+    sim.run_until(20_000_000).await;
+    sim.send_to_server(0, ClientMessage::PlayRequest { id: 1, track_url: "http://example.com/a.mp3".into(), delay_ms: 1_500 });
+    sim.run_until(25_000_000).await;
+
+    let spread = sim.start_spread_us();
+    assert!(spread < 2_000, "start spread {}us", spread);
+}
+
+#[tokio::test]
+async fn pid_holds_drifting_clients_in_sync() {
+    let mut sim = Sim::new(42, &party_links());
+    sim.run_until(20_000_000).await;
+    sim.host_play(0, 1_500);
+
+    // Ten minutes with +-50ppm oscillators drifts ~57ms apart uncorrected;
+    // the correction loop keeps every client inside its soft-resync band
+    sim.run_until(620_000_000).await;
+    let spread = sim.position_spread_ms();
+    assert!(spread < 2.0 * SOFT_RESYNC_MS + 2.0, "position spread {}ms", spread);
+}
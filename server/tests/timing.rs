@@ -0,0 +1,57 @@
+mod common;
+
+use common::*;
+use rust_core::messages::{ClientMessage, ServerMessage};
+use server::app_state::AppState;
+
+#[tokio::test]
+async fn time_response_stamps_fall_between_send_and_receive() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    // Same process, same clock: the client's t0/t3 bracket the server's t1/t2
+    for seq in 0..5 {
+        let t0 = state.clock.now_micros();
+        send(&mut ws, &ClientMessage::TimeRequest { t0, seq }).await;
+        let (t1, t2) = loop {
+            if let ServerMessage::TimeResponse { t0: echoed, t1, t2, seq: got } = recv(&mut ws).await {
+                assert_eq!((echoed, got), (t0, seq));
+                break (t1, t2);
+            }
+        };
+        let t3 = state.clock.now_micros();
+        assert!(t0 <= t1 && t1 <= t2 && t2 <= t3, "t0={} t1={} t2={} t3={}", t0, t1, t2, t3);
+    }
+}
+
+#[tokio::test]
+async fn time_requests_are_answered_while_room_is_busy() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+
+    let room = state.default_room();
+    let flood = tokio::spawn(async move {
+        for _ in 0..500 {
+            let _ = room.tx.send(ServerMessage::PauseCommand { server_time: 0 });
+            tokio::task::yield_now().await;
+        }
+    });
+    for seq in 0..20 {
+        send(&mut ws, &ClientMessage::TimeRequest { t0: state.clock.now_micros(), seq }).await;
+    }
+
+    let mut answered = Vec::new();
+    let mut last_t1 = 0;
+    while answered.len() < 20 {
+        if let ServerMessage::TimeResponse { t1, t2, seq, .. } = recv(&mut ws).await {
+            assert!(t1 <= t2);
+            assert!(t1 >= last_t1, "arrival stamps went backwards");
+            last_t1 = t1;
+            answered.push(seq);
+        }
+    }
+    assert_eq!(answered, (0..20).collect::<Vec<u8>>());
+    flood.await.unwrap();
+}
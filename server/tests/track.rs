@@ -0,0 +1,141 @@
+mod common;
+
+use common::*;
+use rust_core::messages::{ControlCommand, ErrorCode, Role, ServerMessage};
+use rust_core::track::{SourceKind, Track};
+use server::app_state::{AppState, HostedFile, PlaybackState};
+use server::control::process_control_command;
+
+/// Next PlayCommand's start position and track, skipping everything else
+async fn next_play(ws: &mut Ws) -> (u64, Track) {
+    loop {
+        if let ServerMessage::PlayCommand { start_at_position_ms, track, .. } = recv(ws).await {
+            return (start_at_position_ms, track);
+        }
+    }
+}
+
+#[tokio::test]
+async fn play_command_carries_the_track() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    let add = ControlCommand::QueueAdd { track_url: "http://example.com/My%20Song.ogg".into(), duration_ms: Some(180_000) };
+    process_control_command(&room, Role::Host, add).unwrap();
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    let (_, track) = next_play(&mut ws).await;
+    assert_eq!(track.title.as_deref(), Some("My Song"));
+    assert_eq!(track.mime_type.as_deref(), Some("audio/ogg"));
+    assert_eq!(track.duration_ms, Some(180_000));
+    assert_eq!(track.source, SourceKind::Url);
+    assert_eq!(room.playback_state.read().unwrap().track, track);
+}
+
+#[tokio::test]
+async fn hosted_file_is_described_from_its_path() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let mut ws = join(addr, "").await;
+    let room = state.default_room();
+    *room.hosted_file.write().unwrap() = Some(HostedFile::new("/sdcard/Music/Intro.flac".into()));
+
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 100 }).unwrap();
+    let (_, track) = next_play(&mut ws).await;
+    assert_eq!(track.source, SourceKind::Hosted);
+    assert_eq!(track.title.as_deref(), Some("Intro"));
+    assert_eq!(track.mime_type.as_deref(), Some("audio/flac"));
+}
+
+/// A WAV file holding `ms` of 16-bit stereo at 44.1kHz
+fn wav(ms: u32) -> Vec<u8> {
+    let data_len = 176_400 * ms / 1000;
+    let mut bytes = b"RIFF".to_vec();
+    bytes.extend((36 + data_len).to_le_bytes());
+    bytes.extend(b"WAVEfmt ");
+    bytes.extend(16u32.to_le_bytes());
+    bytes.extend([1, 0, 2, 0]); // PCM, stereo
+    bytes.extend(44_100u32.to_le_bytes());
+    bytes.extend(176_400u32.to_le_bytes());
+    bytes.extend([4, 0, 16, 0]);
+    bytes.extend(b"data");
+    bytes.extend(data_len.to_le_bytes());
+    bytes.resize(bytes.len() + data_len as usize, 0);
+    bytes
+}
+
+/// A FLAC file's header claiming `ms` of 44.1kHz stereo
+fn flac(ms: u32) -> Vec<u8> {
+    let mut bytes = b"fLaC".to_vec();
+    bytes.extend([0x80, 0, 0, 34]); // Last metadata block: STREAMINFO
+    bytes.extend([0; 10]);
+    bytes.extend([0x0A, 0xC4, 0x42, 0xF0]); // 44100Hz, 2 channels, 16 bits
+    bytes.extend((44_100 * ms / 1000).to_be_bytes());
+    bytes.extend([0; 16]);
+    bytes
+}
+
+/// An MP3 of `ms` at a constant 128kbps, after an ID3v2 tag
+fn mp3(ms: u32) -> Vec<u8> {
+    let mut bytes = b"ID3\x04\x00\x00\x00\x00\x00\x0A".to_vec();
+    bytes.extend([0; 10]);
+    let audio = bytes.len() + 16 * ms as usize;
+    bytes.extend([0xFF, 0xFB, 0x90, 0x44]); // MPEG-1 Layer III, 128kbps, 44.1kHz
+    bytes.resize(audio, 0);
+    bytes
+}
+
+#[test]
+fn hosted_file_duration_is_read_from_its_headers() {
+    let dir = std::env::temp_dir().join(format!("sonicsync-track-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    for (name, bytes, duration_ms) in [("a.wav", wav(2_000), 2_000), ("b.flac", flac(3_000), 3_000), ("c.mp3", mp3(4_000), 4_000)] {
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        let track = HostedFile::new(path.to_string_lossy().into_owned()).track;
+        assert_eq!(track.duration_ms, Some(duration_ms), "{}", name);
+    }
+
+    // No extension, so the type comes from the bytes
+    let path = dir.join("untitled");
+    std::fs::write(&path, mp3(1_000)).unwrap();
+    let track = HostedFile::new(path.to_string_lossy().into_owned()).track;
+    assert_eq!(track.mime_type.as_deref(), Some("audio/mpeg"));
+    assert_eq!(track.duration_ms, Some(1_000));
+}
+
+#[tokio::test]
+async fn seeking_past_the_end_is_refused() {
+    let state = AppState::new();
+    let room = state.default_room();
+    let add = ControlCommand::QueueAdd { track_url: "a.mp3".into(), duration_ms: Some(60_000) };
+    process_control_command(&room, Role::Host, add).unwrap();
+
+    let err = process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 61_000, delay_ms: 0 }).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidRequest);
+    process_control_command(&room, Role::Host, ControlCommand::Play { start_at_ms: 0, delay_ms: 0 }).unwrap();
+
+    let err = process_control_command(&room, Role::Host, ControlCommand::Seek { position_ms: 90_000 }).unwrap_err();
+    assert_eq!(err.code, ErrorCode::InvalidRequest);
+    process_control_command(&room, Role::Host, ControlCommand::Seek { position_ms: 59_000 }).unwrap();
+    assert_eq!(room.playback_state.read().unwrap().position_ms, 59_000);
+}
+
+#[test]
+fn position_never_runs_past_the_end() {
+    let mut pb = PlaybackState {
+        is_playing: true,
+        position_ms: 50_000,
+        last_update_time: 1_000_000,
+        track: Track { duration_ms: Some(60_000), ..Track::default() },
+        ..PlaybackState::default()
+    };
+    assert_eq!(pb.position_at(6_000_000), 55_000);
+    assert_eq!(pb.position_at(100_000_000), 60_000);
+    // Not started yet: still at the start position
+    assert_eq!(pb.position_at(500_000), 50_000);
+
+    pb.is_playing = false;
+    assert_eq!(pb.position_at(100_000_000), 50_000);
+}
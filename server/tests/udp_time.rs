@@ -0,0 +1,57 @@
+mod common;
+
+use common::*;
+use rust_core::udp_time::{TimeProbe, TimeReply, PACKET_LEN};
+use rust_core::messages::ServerMessage;
+use server::app_state::AppState;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+async fn recv_reply(socket: &UdpSocket, wait: Duration) -> Option<TimeReply> {
+    let mut buf = [0u8; 64];
+    let len = tokio::time::timeout(wait, socket.recv(&mut buf)).await.ok()?.ok()?;
+    TimeReply::decode(&buf[..len])
+}
+
+#[tokio::test]
+async fn probe_gets_stamped_reply_and_port_is_advertised() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let (_, welcome) = join_with(addr, "", None).await;
+    assert!(matches!(welcome, ServerMessage::Welcome { udp_time_port: None, .. }));
+
+    let udp = server::udp_time::start(state.clone(), "127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let (_, welcome) = join_with(addr, "", None).await;
+    let ServerMessage::Welcome { udp_time_port, .. } = welcome else {
+        panic!("expected Welcome, got {:?}", welcome);
+    };
+    assert_eq!(udp_time_port, Some(udp.port()));
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(udp).await.unwrap();
+    let t0 = state.clock.now_micros();
+    socket.send(&TimeProbe { t0, seq: 42 }.encode()).await.unwrap();
+    let reply = recv_reply(&socket, Duration::from_secs(2)).await.expect("no reply");
+    let t3 = state.clock.now_micros();
+    assert_eq!((reply.t0, reply.seq), (t0, 42));
+    assert!(t0 <= reply.t1 && reply.t1 <= reply.t2 && reply.t2 <= t3);
+}
+
+#[tokio::test]
+async fn anything_but_a_probe_is_ignored() {
+    let state = AppState::new();
+    let udp = server::udp_time::start(state, "127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(udp).await.unwrap();
+
+    let probe = TimeProbe { t0: 1, seq: 1 };
+    socket.send(b"hello").await.unwrap();
+    // Replies aren't reflected, and short or padded probes don't get answered
+    socket.send(&probe.reply(2, 3).encode()).await.unwrap();
+    socket.send(&probe.encode()[..PACKET_LEN - 1]).await.unwrap();
+    socket.send(&[probe.encode().as_slice(), &[0; 64]].concat()).await.unwrap();
+    assert_eq!(recv_reply(&socket, Duration::from_millis(200)).await, None);
+
+    socket.send(&TimeProbe { t0: 5, seq: 2 }.encode()).await.unwrap();
+    assert_eq!(recv_reply(&socket, Duration::from_secs(2)).await.map(|r| r.seq), Some(2));
+}
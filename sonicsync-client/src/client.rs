@@ -0,0 +1,852 @@
+use crate::error::ClientError;
+use crate::reconnect::{Backoff, ConnectionState};
+use crate::resync::{SyncPacer, SyncQuality, SyncSchedule, SyncStep};
+use futures::{SinkExt, StreamExt};
+use rust_core::{
+    clock::{Clock, ClockEstimate, ClockFilter, ClockOffset, MonotonicClock, SkewEstimator},
+    messages::{
+        capability, ClientKind, ClientMessage, ControlCommand, RequestId, Role, ServerMessage, SyncState, PROTOCOL_VERSION,
+    },
+    pid::PidController,
+    quality::{SyncMetrics, SyncMonitor},
+    udp_time::{TimeProbe, TimeReply, PACKET_LEN},
+};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, Notify};
+use tokio::task::JoinHandle;
+use tokio_tungstenite::{connect_async, tungstenite::protocol::Message, MaybeTlsStream, WebSocketStream};
+use url::{Host, Url};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+/// A socket that got through the handshake, and the Welcome that let it in
+type Joined = (WsStream, ServerMessage);
+
+/// A joined socket whose outgoing channel is live
+struct Attached {
+    ws: WsStream,
+    rx: mpsc::Receiver<ClientMessage>,
+}
+
+/// Events a slow subscriber can fall behind by before it starts missing them
+const EVENT_BUFFER: usize = 64;
+/// How long `sync` waits for its TimeResponse
+const SYNC_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long `disconnect` waits for the server to acknowledge our Close
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(1);
+/// How long the first UDP probe gets to prove the server's UDP port is reachable
+const UDP_CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Consecutive unanswered UDP probes before falling back to WebSocket sync
+const UDP_MAX_MISSES: u32 = 3;
+
+/// How to reach the server and who we say we are
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    pub url: Url,
+    pub pin: Option<String>, // Required if the room has one; the room's creator sets it
+    pub resume_token: Option<String>, // From an earlier client's `resume_token()`, to pick its session back up
+    pub device_id: String,
+    pub client_kind: ClientKind,
+    pub capabilities: u32,
+    pub backoff: Backoff,
+    pub connect_timeout: Duration,
+    // Several server ping intervals without a single frame: the link is dead even if TCP hasn't noticed
+    pub stale_after: Duration,
+    // Pacing of the background sync loop that runs while connected
+    pub sync: SyncSchedule,
+    // Sync over the server's UDP time port when it offers one and it answers
+    pub udp_time: bool,
+}
+
+impl ClientConfig {
+    pub fn new(url: Url, client_kind: ClientKind) -> Self {
+        let prefix = match client_kind {
+            ClientKind::Android => "ANDROID",
+            ClientKind::Cli => "CLI",
+            ClientKind::Dashboard => "DASHBOARD",
+            ClientKind::Other => "CLIENT",
+        };
+        Self {
+            url,
+            pin: None,
+            resume_token: None,
+            device_id: format!("{}-{}", prefix, uuid::Uuid::new_v4()),
+            client_kind,
+            capabilities: capability::HOSTED_STREAM,
+            backoff: Backoff::default(),
+            connect_timeout: Duration::from_secs(10),
+            stale_after: Duration::from_secs(20),
+            sync: SyncSchedule::default(),
+            udp_time: true,
+        }
+    }
+}
+
+/// What subscribers hear about
+#[derive(Debug, Clone)]
+pub enum Event {
+    State(ConnectionState),
+    /// Our own role, on joining and whenever the host changes it
+    Role(Role),
+    /// Every server message except TimeResponse, which the client consumes itself
+    Message(ServerMessage),
+    /// After every background sync burst or probe that left us with an estimate
+    Sync(SyncQuality),
+}
+
+#[derive(Default)]
+struct Session {
+    session_id: Option<String>,
+    resume_token: Option<String>, // Sent in the next Join to keep our session across reconnects
+    role: Option<Role>,
+    control_token: Option<String>,
+}
+
+struct ClockSync {
+    filter: ClockFilter,
+    skew: SkewEstimator,
+    monitor: SyncMonitor,
+    // Survives filter resets, so time queries stay sensible while a resync is in flight
+    last: Option<ClockEstimate>,
+    pid: PidController,
+}
+
+impl ClockSync {
+    /// Offset at the given local time, extrapolated with the skew estimate when available
+    fn offset_at(&self, local_micros: u64) -> i64 {
+        match self.skew.estimate() {
+            Some(est) => est.offset_at(local_micros),
+            None => self.last.map_or(0, |est| est.offset),
+        }
+    }
+}
+
+struct Inner {
+    config: ClientConfig,
+    clock: MonotonicClock,
+    sync: Mutex<ClockSync>,
+    session: Mutex<Session>,
+    outgoing: Mutex<Option<mpsc::Sender<ClientMessage>>>,
+    pending_syncs: Mutex<HashMap<u8, oneshot::Sender<ClockOffset>>>,
+    quality: Mutex<Option<SyncQuality>>,
+    // Wakes the sync loop for a burst (SyncRequired, or the app asking)
+    resync: Notify,
+    // The server asked for the burst, so follow it with fresh Telemetry
+    report_after_burst: AtomicBool,
+    // Drift (ms) and status from the app's last `report`, reused for those follow-ups
+    last_report: Mutex<(i64, String)>,
+    // Set while time probes go over UDP instead of the WebSocket
+    udp: Mutex<Option<Arc<UdpSocket>>>,
+    udp_misses: AtomicU32,
+    events: broadcast::Sender<Event>,
+    next_request_id: AtomicU32,
+    next_seq: AtomicU8,
+    shutdown: watch::Sender<bool>,
+    supervisor: Mutex<Option<JoinHandle<()>>>,
+}
+
+// A panic elsewhere must not take every later call down with it
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A connection to one room. Cheap to clone; clones share the connection.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+impl Client {
+    pub fn new(config: ClientConfig) -> Self {
+        let session = Session { resume_token: config.resume_token.clone(), ..Session::default() };
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            inner: Arc::new(Inner {
+                config,
+                clock: MonotonicClock::new(),
+                sync: Mutex::new(ClockSync {
+                    filter: ClockFilter::default(),
+                    skew: SkewEstimator::default(),
+                    monitor: SyncMonitor::default(),
+                    last: None,
+                    pid: PidController::new(0.005, 0.0001, 0.001), // Tuned for audio
+                }),
+                session: Mutex::new(session),
+                outgoing: Mutex::new(None),
+                pending_syncs: Mutex::new(HashMap::new()),
+                quality: Mutex::new(None),
+                resync: Notify::new(),
+                report_after_burst: AtomicBool::new(false),
+                last_report: Mutex::new((0, "synced".to_string())),
+                udp: Mutex::new(None),
+                udp_misses: AtomicU32::new(0),
+                events,
+                next_request_id: AtomicU32::new(1),
+                next_seq: AtomicU8::new(0),
+                shutdown,
+                supervisor: Mutex::new(None),
+            }),
+        }
+    }
+
+    pub fn config(&self) -> &ClientConfig {
+        &self.inner.config
+    }
+
+    /// Subscribe before `connect` to see every event from the first attempt on
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.inner.events.subscribe()
+    }
+
+    /// Connect and join, retrying failed attempts with the configured backoff.
+    /// Once joined, a background task keeps the session alive (reconnecting and
+    /// resuming it) until `disconnect` or a refusal. Replaces any existing connection.
+    pub async fn connect(&self) -> Result<(), ClientError> {
+        self.disconnect().await;
+        self.inner.shutdown.send_replace(false);
+
+        let mut backoff = self.inner.config.backoff.clone();
+        self.inner.emit(Event::State(ConnectionState::Connecting));
+        let joined = establish(&self.inner, &mut backoff).await?;
+        // Attach before returning, so the caller can send and query the session at once
+        let attached = attach(&self.inner, joined);
+        let handle = tokio::spawn(supervise(self.inner.clone(), attached, backoff));
+        *lock(&self.inner.supervisor) = Some(handle);
+        Ok(())
+    }
+
+    /// Close the connection cleanly and stop reconnecting
+    pub async fn disconnect(&self) {
+        self.inner.shutdown.send_replace(true);
+        let handle = lock(&self.inner.supervisor).take();
+        if let Some(handle) = handle {
+            let _ = handle.await;
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        lock(&self.inner.outgoing).is_some()
+    }
+
+    /// Queue a message for the server
+    pub async fn send(&self, msg: ClientMessage) -> Result<(), ClientError> {
+        self.inner.send(msg).await
+    }
+
+    /// One round trip to the server clock; the sample also feeds the running estimate.
+    /// The background loop already keeps the estimate fresh, so this is rarely needed.
+    pub async fn sync(&self) -> Result<ClockOffset, ClientError> {
+        self.inner.round_trip().await
+    }
+
+    /// Have the background loop run a full burst now rather than wait for its next probe
+    pub fn resync(&self) {
+        self.inner.resync.notify_one();
+    }
+
+    /// Whether clock sync currently runs over the server's UDP time port
+    pub fn uses_udp_time(&self) -> bool {
+        lock(&self.inner.udp).is_some()
+    }
+
+    /// How well synced we are, as of the last background burst or probe
+    pub fn sync_quality(&self) -> Option<SyncQuality> {
+        *lock(&self.inner.quality)
+    }
+
+    /// Reserve an id for a request sent later with `send` (the helpers below do this themselves)
+    pub fn next_request_id(&self) -> RequestId {
+        self.inner.next_request_id.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Ask the room to play a URL. Returns the id echoed in the Ack/Error.
+    pub async fn request_play(&self, track_url: impl Into<String>, delay_ms: u64) -> Result<RequestId, ClientError> {
+        let id = self.next_request_id();
+        self.send(ClientMessage::PlayRequest { id, track_url: track_url.into(), delay_ms }).await?;
+        Ok(id)
+    }
+
+    /// Play/Pause/Seek. Returns the id echoed in the Ack/Error.
+    pub async fn command(&self, cmd: ControlCommand) -> Result<RequestId, ClientError> {
+        let id = self.next_request_id();
+        self.send(ClientMessage::CommandRequest { id, cmd }).await?;
+        Ok(id)
+    }
+
+    /// Host only: change another member's role. Returns the id echoed in the Ack/Error.
+    pub async fn set_role(&self, session_id: impl Into<String>, role: Role) -> Result<RequestId, ClientError> {
+        let id = self.next_request_id();
+        self.send(ClientMessage::SetRole { id, session_id: session_id.into(), role }).await?;
+        Ok(id)
+    }
+
+    /// Report our sync quality and playback drift (ms) to the room
+    pub async fn report(&self, drift_ms: i64, status: impl Into<String>) -> Result<(), ClientError> {
+        *lock(&self.inner.last_report) = (drift_ms, status.into());
+        self.inner.report().await
+    }
+
+    /// Quality of the current sample window; the state goes out with every Telemetry
+    pub fn sync_metrics(&self) -> SyncMetrics {
+        self.inner.sync_metrics()
+    }
+
+    /// Latest filtered clock estimate, if any sync has completed
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        lock(&self.inner.sync).last
+    }
+
+    /// Server time minus local time, in micros
+    pub fn offset(&self) -> i64 {
+        lock(&self.inner.sync).offset_at(self.inner.clock.now_micros())
+    }
+
+    pub fn rtt(&self) -> u64 {
+        self.estimate().map_or(0, |est| est.rtt)
+    }
+
+    /// Current server time (micros), as best we know it
+    pub fn server_time(&self) -> u64 {
+        let now = self.inner.clock.now_micros();
+        (now as i64 + lock(&self.inner.sync).offset_at(now)).max(0) as u64
+    }
+
+    /// Playback speed multiplier that steers the given drift (ms) back to zero
+    pub fn correction(&self, drift_ms: i64, dt_seconds: f64) -> f64 {
+        let correction = lock(&self.inner.sync).pid.next(-(drift_ms as f64), dt_seconds);
+        // Clamp to safe limits (0.95 to 1.05) to avoid audio artifacts, though PID should be tighter
+        (1.0 + correction).clamp(0.95, 1.05)
+    }
+
+    pub fn session_id(&self) -> Option<String> {
+        lock(&self.inner.session).session_id.clone()
+    }
+
+    pub fn role(&self) -> Option<Role> {
+        lock(&self.inner.session).role
+    }
+
+    /// Token that reattaches a new connection (or a new `Client`) to this session
+    pub fn resume_token(&self) -> Option<String> {
+        lock(&self.inner.session).resume_token.clone()
+    }
+
+    /// Bearer token for the REST API; only the host gets one
+    pub fn control_token(&self) -> Option<String> {
+        lock(&self.inner.session).control_token.clone()
+    }
+}
+
+/// How a relayed connection ended
+enum SessionEnd {
+    Lost,
+    Shutdown,
+}
+
+impl Inner {
+    fn emit(&self, event: Event) {
+        if let Event::State(state) = &event {
+            log::info!("Connection state: {:?}", state);
+        }
+        let _ = self.events.send(event);
+    }
+
+    async fn send(&self, msg: ClientMessage) -> Result<(), ClientError> {
+        let tx = lock(&self.outgoing).clone().ok_or(ClientError::NotConnected)?;
+        tx.send(msg).await.map_err(|_| ClientError::NotConnected)
+    }
+
+    /// One TimeRequest, over UDP if it's up and the WebSocket otherwise
+    async fn round_trip(&self) -> Result<ClockOffset, ClientError> {
+        let seq = self.next_seq.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        lock(&self.pending_syncs).insert(seq, tx);
+        let udp = lock(&self.udp).clone();
+        let result = async {
+            let t0 = self.clock.now_micros();
+            match &udp {
+                Some(socket) if socket.send(&TimeProbe { t0, seq }.encode()).await.is_ok() => {}
+                _ => self.send(ClientMessage::TimeRequest { t0, seq }).await?,
+            }
+            match tokio::time::timeout(SYNC_TIMEOUT, rx).await {
+                Ok(Ok(sample)) => Ok(sample),
+                Ok(Err(_)) => Err(ClientError::NotConnected),
+                Err(_) => Err(ClientError::Timeout),
+            }
+        }
+        .await;
+        lock(&self.pending_syncs).remove(&seq);
+        if let Some(socket) = udp {
+            self.note_udp_result(&socket, result.is_ok());
+        }
+        result
+    }
+
+    /// Give up on UDP after a run of unanswered probes (a firewall or NAT may have started dropping them)
+    fn note_udp_result(&self, socket: &Arc<UdpSocket>, answered: bool) {
+        if answered {
+            self.udp_misses.store(0, Ordering::Relaxed);
+            return;
+        }
+        if self.udp_misses.fetch_add(1, Ordering::Relaxed) + 1 >= UDP_MAX_MISSES {
+            log::warn!("UDP time probes going unanswered, syncing over WebSocket");
+            self.drop_udp(socket);
+        }
+    }
+
+    /// Stop using `socket` for time probes, unless a newer connection has already replaced it
+    fn drop_udp(&self, socket: &Arc<UdpSocket>) {
+        let mut udp = lock(&self.udp);
+        if udp.as_ref().is_some_and(|current| Arc::ptr_eq(current, socket)) {
+            *udp = None;
+        }
+    }
+
+    /// Feed one answered time request (from either transport) to the estimators and its waiter
+    fn on_time_response(&self, t0: u64, t1: u64, t2: u64, seq: u8, t3: u64) {
+        let sample = {
+            let mut sync = lock(&self.sync);
+            let sample = sync.filter.add_timestamps(t0, t1, t2, t3);
+            sync.skew.add_timestamps(t0, t1, t2, t3);
+            sync.monitor.add_timestamps(t0, t1, t2, t3);
+            if let Some(est) = sync.filter.estimate() {
+                log::debug!("Sync Updated: Offset={}us RTT={}us (+/- {}us)", est.offset, est.rtt, est.error_bound);
+                sync.last = Some(est);
+            }
+            sample
+        };
+        if let Some(waiter) = lock(&self.pending_syncs).remove(&seq) {
+            let _ = waiter.send(sample);
+        }
+    }
+
+    /// Telemetry with the current clock estimate and the app's last drift and status
+    async fn report(&self) -> Result<(), ClientError> {
+        let now = self.clock.now_micros();
+        let (rtt, offset, sync_state) = {
+            let sync = lock(&self.sync);
+            (sync.last.map_or(0, |est| est.rtt), sync.offset_at(now), sync.monitor.metrics(now).state)
+        };
+        let (drift, status) = lock(&self.last_report).clone();
+        self.send(ClientMessage::Telemetry { rtt, offset, drift, status, sync_state }).await
+    }
+
+    fn sync_metrics(&self) -> SyncMetrics {
+        lock(&self.sync).monitor.metrics(self.clock.now_micros())
+    }
+
+    fn is_shut_down(&self) -> bool {
+        *self.shutdown.borrow()
+    }
+
+    /// Sleep for `delay` unless shut down first; false if shut down
+    async fn wait(&self, delay: Duration) -> bool {
+        let mut shutdown = self.shutdown.subscribe();
+        if *shutdown.borrow_and_update() {
+            return false;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => true,
+            _ = shutdown.changed() => false,
+        }
+    }
+
+    fn on_welcome(&self, msg: &ServerMessage) {
+        let ServerMessage::Welcome { session_id, protocol_version, role, control_token, resume_token, resumed, .. } = msg else {
+            return;
+        };
+        log::info!(
+            "{} session {} as {:?} (protocol v{})",
+            if *resumed { "Resumed" } else { "Joined" }, session_id, role, protocol_version
+        );
+        {
+            let mut session = lock(&self.session);
+            session.session_id = Some(session_id.clone());
+            session.resume_token = Some(resume_token.clone());
+            session.role = Some(*role);
+            session.control_token = control_token.clone();
+        }
+        // The network path, or the server, may have changed since the last estimate
+        {
+            let mut sync = lock(&self.sync);
+            sync.filter.reset();
+            sync.monitor.reset();
+            if !resumed {
+                sync.skew.reset();
+            }
+        }
+        self.emit(Event::State(ConnectionState::Connected));
+        self.emit(Event::Message(msg.clone()));
+        self.emit(Event::Role(*role));
+    }
+
+    /// Apply one post-handshake server message and pass it on to subscribers.
+    /// `received_at` is when its frame came off the socket, before decoding.
+    fn dispatch(&self, msg: ServerMessage, received_at: u64) {
+        match &msg {
+            ServerMessage::TimeResponse { t0, t1, t2, seq } => {
+                self.on_time_response(*t0, *t1, *t2, *seq, received_at);
+                return;
+            }
+            ServerMessage::SyncRequired => {
+                log::info!("Server asked for a resync");
+                self.report_after_burst.store(true, Ordering::Relaxed);
+                self.resync.notify_one();
+            }
+            ServerMessage::RoleChanged { session_id, role } => {
+                let mut session = lock(&self.session);
+                if session.session_id.as_deref() == Some(session_id.as_str()) {
+                    log::info!("Our role is now {:?}", role);
+                    session.role = Some(*role);
+                    drop(session);
+                    self.emit(Event::Role(*role));
+                }
+            }
+            _ => {}
+        }
+        self.emit(Event::Message(msg));
+    }
+}
+
+async fn send_frame(ws: &mut WsStream, msg: &ClientMessage) -> Result<(), ClientError> {
+    let bytes = bincode::serialize(msg).map_err(|e| ClientError::Transport(e.to_string()))?;
+    ws.send(Message::Binary(bytes)).await.map_err(|e| ClientError::Transport(e.to_string()))
+}
+
+/// Next decodable server message during the handshake
+async fn next_message(ws: &mut WsStream) -> Result<ServerMessage, ClientError> {
+    loop {
+        match ws.next().await {
+            Some(Ok(Message::Binary(bytes))) => {
+                return bincode::deserialize(&bytes).map_err(|e| ClientError::Transport(format!("undecodable reply: {}", e)));
+            }
+            Some(Ok(Message::Close(frame))) => {
+                return Err(ClientError::Transport(format!("closed during handshake: {:?}", frame)));
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return Err(ClientError::Transport(e.to_string())),
+            None => return Err(ClientError::Transport("closed during handshake".into())),
+        }
+    }
+}
+
+/// One attempt: connect, Join (presenting our resume token) and wait for the verdict
+async fn open(inner: &Inner) -> Result<Joined, ClientError> {
+    let config = &inner.config;
+    let (mut ws, _) = match tokio::time::timeout(config.connect_timeout, connect_async(config.url.clone())).await {
+        Ok(Ok(conn)) => conn,
+        Ok(Err(e)) => return Err(ClientError::Transport(e.to_string())),
+        Err(_) => return Err(ClientError::Timeout),
+    };
+    let join = ClientMessage::Join {
+        device_id: config.device_id.clone(),
+        protocol_version: PROTOCOL_VERSION,
+        client_kind: config.client_kind,
+        capabilities: config.capabilities,
+        pin: config.pin.clone(),
+        resume_token: lock(&inner.session).resume_token.clone(),
+    };
+    send_frame(&mut ws, &join).await?;
+
+    let reply = tokio::time::timeout(config.connect_timeout, next_message(&mut ws))
+        .await
+        .map_err(|_| ClientError::Timeout)??;
+    match reply {
+        ServerMessage::Welcome { .. } => Ok((ws, reply)),
+        ServerMessage::Incompatible { reason, server_version, legacy_version } => {
+            Err(ClientError::Incompatible { reason, server_version, legacy_version })
+        }
+        // Before Welcome, an Error is the server refusing the Join
+        ServerMessage::Error { message, .. } => Err(ClientError::Refused(message)),
+        other => Err(ClientError::Transport(format!("unexpected handshake reply: {:?}", other))),
+    }
+}
+
+/// Keep trying `open` until it succeeds, is refused, the backoff runs out or we're shut down
+async fn establish(inner: &Inner, backoff: &mut Backoff) -> Result<Joined, ClientError> {
+    loop {
+        let err = match open(inner).await {
+            Ok(joined) => {
+                backoff.reset();
+                return Ok(joined);
+            }
+            Err(e) if e.is_fatal() => e,
+            Err(e) => {
+                log::warn!("Connection attempt failed: {}", e);
+                match backoff.next_delay() {
+                    Some(delay) => {
+                        inner.emit(Event::State(ConnectionState::Reconnecting));
+                        if !inner.wait(delay).await {
+                            return Err(ClientError::NotConnected);
+                        }
+                        continue;
+                    }
+                    None => ClientError::GaveUp,
+                }
+            }
+        };
+        log::error!("Not reconnecting: {}", err);
+        inner.emit(Event::State(ConnectionState::Failed));
+        return Err(err);
+    }
+}
+
+/// Open the outgoing channel for a joined socket, then announce the Welcome
+fn attach(inner: &Arc<Inner>, (ws, welcome): Joined) -> Attached {
+    let (tx, rx) = mpsc::channel::<ClientMessage>(32);
+    *lock(&inner.outgoing) = Some(tx.clone());
+    // Only now, so subscribers reacting to Connected can already send
+    inner.on_welcome(&welcome);
+
+    // Keep the estimate fresh for as long as this connection lasts, over UDP if we can
+    let udp_port = match &welcome {
+        ServerMessage::Welcome { udp_time_port, .. } if inner.config.udp_time => *udp_time_port,
+        _ => None,
+    };
+    let sync_inner = inner.clone();
+    tokio::spawn(async move {
+        let inner = &sync_inner;
+        let mut udp = None;
+        tokio::select! {
+            _ = tx.closed() => {}
+            _ = async {
+                udp = match udp_port {
+                    Some(port) => open_udp(inner, port).await,
+                    None => None,
+                };
+                match &udp {
+                    Some(socket) => {
+                        *lock(&inner.udp) = Some(socket.clone());
+                        tokio::join!(receive_udp(inner, socket), sync_loop(inner));
+                    }
+                    None => sync_loop(inner).await,
+                }
+            } => {}
+        }
+        if let Some(socket) = udp {
+            inner.drop_udp(&socket);
+        }
+    });
+    Attached { ws, rx }
+}
+
+/// Set up a UDP socket to the server's time port and check it answers a probe.
+/// None means sync stays on the WebSocket.
+async fn open_udp(inner: &Inner, port: u16) -> Option<Arc<UdpSocket>> {
+    let server = match inner.config.url.host()? {
+        Host::Domain(name) => tokio::net::lookup_host((name, port)).await.ok()?.next()?,
+        Host::Ipv4(ip) => (ip, port).into(),
+        Host::Ipv6(ip) => (ip, port).into(),
+    };
+    let local = if server.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local).await.ok()?;
+    socket.connect(server).await.ok()?;
+
+    let seq = inner.next_seq.fetch_add(1, Ordering::Relaxed);
+    let t0 = inner.clock.now_micros();
+    socket.send(&TimeProbe { t0, seq }.encode()).await.ok()?;
+    let mut buf = [0u8; PACKET_LEN + 1];
+    let reply = tokio::time::timeout(UDP_CHECK_TIMEOUT, async {
+        loop {
+            let len = socket.recv(&mut buf).await.ok()?;
+            let t3 = inner.clock.now_micros();
+            match TimeReply::decode(&buf[..len]) {
+                Some(reply) if reply.seq == seq && reply.t0 == t0 => return Some((reply, t3)),
+                _ => continue,
+            }
+        }
+    });
+    match reply.await {
+        Ok(Some((reply, t3))) => {
+            log::info!("Syncing over UDP to {}", server);
+            inner.on_time_response(reply.t0, reply.t1, reply.t2, reply.seq, t3);
+            inner.udp_misses.store(0, Ordering::Relaxed);
+            Some(Arc::new(socket))
+        }
+        _ => {
+            log::info!("No answer on UDP {}, syncing over WebSocket", server);
+            None
+        }
+    }
+}
+
+/// Hand UDP time replies to the estimators, stamped the moment they're read
+async fn receive_udp(inner: &Inner, socket: &UdpSocket) {
+    let mut buf = [0u8; PACKET_LEN + 1];
+    loop {
+        let len = match socket.recv(&mut buf).await {
+            Ok(len) => len,
+            // An ICMP error from an earlier probe; the miss counter decides when to give up
+            Err(e) if e.kind() == std::io::ErrorKind::ConnectionRefused => continue,
+            Err(e) => {
+                log::warn!("UDP time socket failed: {}", e);
+                return;
+            }
+        };
+        let t3 = inner.clock.now_micros();
+        if let Some(TimeReply { t0, t1, t2, seq }) = TimeReply::decode(&buf[..len]) {
+            inner.on_time_response(t0, t1, t2, seq, t3);
+        }
+    }
+}
+
+/// Burst on joining, then probe at an interval that widens while the offset holds still.
+/// Bursts again on request or when a probe's RTT says the path changed.
+async fn sync_loop(inner: &Inner) {
+    let mut pacer = SyncPacer::new(inner.config.sync.clone());
+    // Last state the room heard from us; a change is worth a Telemetry of its own
+    let mut reported = SyncState::Unsynced;
+    loop {
+        let mut report = false;
+        match pacer.next_step() {
+            SyncStep::Burst => {
+                let schedule = pacer.schedule().clone();
+                for i in 0..schedule.burst {
+                    if i > 0 {
+                        tokio::time::sleep(schedule.burst_spacing).await;
+                    }
+                    if let Err(ClientError::NotConnected) = inner.round_trip().await {
+                        return;
+                    }
+                }
+                let estimate = lock(&inner.sync).last;
+                pacer.burst_done(estimate);
+                report = inner.report_after_burst.swap(false, Ordering::Relaxed);
+            }
+            SyncStep::Probe(delay) => {
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = inner.resync.notified() => {
+                        pacer.request_burst();
+                        continue;
+                    }
+                }
+                let sample = match inner.round_trip().await {
+                    Ok(sample) => Some(sample),
+                    Err(ClientError::NotConnected) => return,
+                    Err(_) => None,
+                };
+                let estimate = lock(&inner.sync).last;
+                pacer.probe_done(sample, estimate);
+            }
+        }
+        let metrics = inner.sync_metrics();
+        if let Some(estimate) = lock(&inner.sync).last {
+            let quality = pacer.quality(estimate, metrics);
+            *lock(&inner.quality) = Some(quality);
+            inner.emit(Event::Sync(quality));
+        }
+        if report || metrics.state != reported {
+            if metrics.state != reported {
+                log::info!("Sync state {:?} -> {:?}", reported, metrics.state);
+            }
+            reported = metrics.state;
+            if let Err(e) = inner.report().await {
+                log::debug!("Telemetry after sync not sent: {}", e);
+            }
+        }
+    }
+}
+
+/// Relay one attached connection until it drops or we're shut down
+async fn relay(inner: &Arc<Inner>, Attached { ws, mut rx }: Attached) -> SessionEnd {
+    let (mut write, mut read) = ws.split();
+    let mut shutdown = inner.shutdown.subscribe();
+    let stale = tokio::time::sleep(inner.config.stale_after);
+    tokio::pin!(stale);
+
+    let end = loop {
+        tokio::select! {
+            // Handle outgoing messages from channel
+            Some(client_msg) = rx.recv() => {
+                if let Ok(bytes) = bincode::serialize(&client_msg) {
+                    if write.send(Message::Binary(bytes)).await.is_err() {
+                        break SessionEnd::Lost;
+                    }
+                }
+            }
+
+            // A clean Close lets the server drop us at once instead of holding the session
+            _ = async { drop(shutdown.wait_for(|stop| *stop).await) } => {
+                let _ = write.send(Message::Close(None)).await;
+                // Hang up only once the server has seen the Close; a socket dropped while it's
+                // still writing to us reads as a lost connection and gets held for resumption
+                let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                    while let Some(Ok(msg)) = read.next().await {
+                        if let Message::Close(_) = msg {
+                            break;
+                        }
+                    }
+                })
+                .await;
+                break SessionEnd::Shutdown;
+            }
+
+            _ = &mut stale => {
+                log::warn!("No traffic from server for {}ms, reconnecting", inner.config.stale_after.as_millis());
+                break SessionEnd::Lost;
+            }
+
+            // Handle incoming messages from socket
+            msg = read.next() => {
+                let received_at = inner.clock.now_micros();
+                let msg = match msg {
+                    Some(Ok(m)) => m,
+                    Some(Err(e)) => {
+                        log::error!("WebSocket read error: {:?}", e);
+                        break SessionEnd::Lost;
+                    }
+                    None => {
+                        log::info!("WebSocket stream ended.");
+                        break SessionEnd::Lost;
+                    }
+                };
+                stale.as_mut().reset(tokio::time::Instant::now() + inner.config.stale_after);
+                match msg {
+                    Message::Binary(bytes) => match bincode::deserialize::<ServerMessage>(&bytes) {
+                        Ok(server_msg) => inner.dispatch(server_msg, received_at),
+                        Err(e) => log::warn!("Undecodable server message: {}", e),
+                    },
+                    Message::Close(_) => {
+                        log::info!("WebSocket received close frame.");
+                        break SessionEnd::Lost;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    };
+    *lock(&inner.outgoing) = None;
+    *lock(&inner.udp) = None;
+    // Nobody will answer these on this connection
+    lock(&inner.pending_syncs).clear();
+    end
+}
+
+/// Background task behind a connected `Client`: relay, and on a drop reconnect with backoff
+async fn supervise(inner: Arc<Inner>, mut attached: Attached, mut backoff: Backoff) {
+    loop {
+        if let SessionEnd::Shutdown = relay(&inner, attached).await {
+            return;
+        }
+        if inner.is_shut_down() {
+            return;
+        }
+        inner.emit(Event::State(ConnectionState::Reconnecting));
+        let Some(delay) = backoff.next_delay() else {
+            inner.emit(Event::State(ConnectionState::Failed));
+            return;
+        };
+        if !inner.wait(delay).await {
+            return;
+        }
+        attached = match establish(&inner, &mut backoff).await {
+            Ok(joined) => attach(&inner, joined),
+            Err(_) => return,
+        };
+    }
+}
@@ -0,0 +1,42 @@
+use std::fmt;
+
+/// Why a client operation failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClientError {
+    /// Not currently connected (or still reconnecting)
+    NotConnected,
+    /// Couldn't reach the server or the connection dropped mid-handshake
+    Transport(String),
+    /// The server turned us away (wrong PIN, bad room); retrying won't help
+    Refused(String),
+    /// The server doesn't speak our protocol version
+    Incompatible { reason: String, server_version: u16, legacy_version: u16 },
+    /// Reconnect attempts were used up
+    GaveUp,
+    /// No reply within the allotted time
+    Timeout,
+}
+
+impl ClientError {
+    /// Errors that a retry can't fix
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, ClientError::Refused(_) | ClientError::Incompatible { .. } | ClientError::GaveUp)
+    }
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::NotConnected => write!(f, "not connected"),
+            ClientError::Transport(e) => write!(f, "connection failed: {}", e),
+            ClientError::Refused(reason) => write!(f, "server refused us: {}", reason),
+            ClientError::Incompatible { reason, server_version, legacy_version } => {
+                write!(f, "{} (server speaks v{} and v{})", reason, legacy_version, server_version)
+            }
+            ClientError::GaveUp => write!(f, "gave up after repeated failures"),
+            ClientError::Timeout => write!(f, "timed out"),
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
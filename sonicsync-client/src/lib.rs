@@ -0,0 +1,13 @@
+//! Platform-neutral SonicSync client: connection supervision, clock sync,
+//! drift correction and message dispatch. The Android bridge and the CLI
+//! are thin adapters over `Client`.
+
+pub mod client;
+pub mod error;
+pub mod reconnect;
+pub mod resync;
+
+pub use client::{Client, ClientConfig, Event};
+pub use error::ClientError;
+pub use reconnect::{Backoff, ConnectionState};
+pub use resync::{SyncQuality, SyncSchedule};
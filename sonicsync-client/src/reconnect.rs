@@ -0,0 +1,96 @@
+use rand::Rng;
+use std::time::Duration;
+
+/// Lifecycle of the client connection, reported as `Event::State`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Reconnecting,
+    /// Gave up: rejected by the server or out of attempts
+    Failed,
+}
+
+/// Exponential backoff with full jitter between reconnect attempts
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    max: Duration,
+    max_attempts: u32,
+    attempt: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(500), Duration::from_secs(30), 12)
+    }
+}
+
+impl Backoff {
+    pub fn new(base: Duration, max: Duration, max_attempts: u32) -> Self {
+        Self { base, max, max_attempts, attempt: 0 }
+    }
+
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self { max_attempts, ..self }
+    }
+
+    /// Call once a connection is established so the next outage starts from `base`
+    pub fn reset(&mut self) {
+        self.attempt = 0;
+    }
+
+    /// Upper bound for the next wait, without jitter
+    pub fn ceiling(&self) -> Duration {
+        let factor = 1u32.checked_shl(self.attempt.min(16)).unwrap_or(u32::MAX);
+        self.base.saturating_mul(factor).min(self.max)
+    }
+
+    /// Delay before the next attempt, or None once the attempts are used up.
+    /// The delay is drawn uniformly from [0, ceiling] so many phones dropped by
+    /// the same Wi-Fi blip don't all come back at once.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.attempt >= self.max_attempts {
+            return None;
+        }
+        let ceiling = self.ceiling();
+        self.attempt += 1;
+        Some(ceiling.mul_f64(rand::thread_rng().gen_range(0.0..=1.0)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ceiling_doubles_up_to_max() {
+        let mut b = Backoff::new(Duration::from_millis(100), Duration::from_millis(500), 10);
+        let mut ceilings = Vec::new();
+        for _ in 0..5 {
+            ceilings.push(b.ceiling().as_millis());
+            b.next_delay();
+        }
+        assert_eq!(ceilings, vec![100, 200, 400, 500, 500]);
+    }
+
+    #[test]
+    fn delay_is_jittered_within_ceiling() {
+        let mut b = Backoff::new(Duration::from_millis(100), Duration::from_secs(1), 50);
+        for _ in 0..50 {
+            let ceiling = b.ceiling();
+            assert!(b.next_delay().unwrap() <= ceiling);
+        }
+    }
+
+    #[test]
+    fn gives_up_after_max_attempts_until_reset() {
+        let mut b = Backoff::new(Duration::from_millis(1), Duration::from_millis(10), 2);
+        assert!(b.next_delay().is_some());
+        assert!(b.next_delay().is_some());
+        assert!(b.next_delay().is_none());
+        b.reset();
+        assert_eq!(b.ceiling(), Duration::from_millis(1));
+        assert!(b.next_delay().is_some());
+    }
+}
@@ -0,0 +1,214 @@
+use rust_core::clock::{ClockEstimate, ClockOffset};
+use rust_core::quality::SyncMetrics;
+use std::time::Duration;
+
+/// A probe RTT only counts as a spike if it's also this far (micros) above the best,
+/// so sub-millisecond LAN jitter doesn't set off bursts
+const RTT_SPIKE_FLOOR_US: u64 = 2_000;
+
+/// How the background sync loop paces itself
+#[derive(Debug, Clone)]
+pub struct SyncSchedule {
+    // TimeRequests sent on (re)joining, on SyncRequired and after an RTT spike
+    pub burst: u8,
+    pub burst_spacing: Duration,
+    // Probe interval after a burst or whenever the offset moves; doubles while it holds still
+    pub min_interval: Duration,
+    pub max_interval: Duration,
+    // Offset moving less than this (micros) between probes counts as holding still
+    pub stable_within: u64,
+    // A probe RTT this many times the best one means the path changed: burst
+    pub rtt_spike: f64,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        Self {
+            burst: 8,
+            burst_spacing: Duration::from_millis(50),
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(30),
+            stable_within: 1_000,
+            rtt_spike: 3.0,
+        }
+    }
+}
+
+/// What the sync loop should do next
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncStep {
+    Burst,
+    /// Send one TimeRequest after waiting this long
+    Probe(Duration),
+}
+
+/// Snapshot of how well we're synced, reported as `Event::Sync` after every burst and probe
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncQuality {
+    pub estimate: ClockEstimate,
+    /// Sample window quality, including whether we're locked
+    pub metrics: SyncMetrics,
+    /// Wait before the next probe
+    pub next_probe: Duration,
+    /// The offset has held still for at least one probe
+    pub settled: bool,
+}
+
+/// Decides when to burst and how far apart to space probes. Pure bookkeeping:
+/// the client feeds it results and does the sending.
+#[derive(Debug, Clone)]
+pub struct SyncPacer {
+    schedule: SyncSchedule,
+    interval: Duration,
+    burst_pending: bool,
+    // Offset as of the last burst or probe, to tell whether it has moved since
+    reference: Option<i64>,
+    best_rtt: Option<u64>,
+}
+
+impl SyncPacer {
+    /// Starts with a burst, since there's no estimate yet
+    pub fn new(schedule: SyncSchedule) -> Self {
+        let interval = schedule.min_interval;
+        Self { schedule, interval, burst_pending: true, reference: None, best_rtt: None }
+    }
+
+    pub fn schedule(&self) -> &SyncSchedule {
+        &self.schedule
+    }
+
+    /// Resync at the next opportunity
+    pub fn request_burst(&mut self) {
+        self.burst_pending = true;
+    }
+
+    pub fn next_step(&mut self) -> SyncStep {
+        if std::mem::take(&mut self.burst_pending) {
+            SyncStep::Burst
+        } else {
+            SyncStep::Probe(self.interval)
+        }
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.interval > self.schedule.min_interval
+    }
+
+    pub fn quality(&self, estimate: ClockEstimate, metrics: SyncMetrics) -> SyncQuality {
+        SyncQuality { estimate, metrics, next_probe: self.interval, settled: self.is_settled() }
+    }
+
+    /// A burst finished; probing starts over at the shortest interval
+    pub fn burst_done(&mut self, estimate: Option<ClockEstimate>) {
+        self.interval = self.schedule.min_interval;
+        self.reference = estimate.map(|est| est.offset);
+        self.best_rtt = estimate.map(|est| est.rtt);
+    }
+
+    /// A probe came back (or didn't, if `sample` is None) and the filter now says `estimate`
+    pub fn probe_done(&mut self, sample: Option<ClockOffset>, estimate: Option<ClockEstimate>) {
+        let Some(sample) = sample else {
+            // Lost probe: keep a close eye on the link
+            self.interval = self.schedule.min_interval;
+            return;
+        };
+        if let Some(best) = self.best_rtt {
+            let spike = sample.rtt as f64 > best as f64 * self.schedule.rtt_spike;
+            if spike && sample.rtt > best + RTT_SPIKE_FLOOR_US {
+                log::info!("Sync RTT spiked to {}us (best {}us), resyncing", sample.rtt, best);
+                self.burst_pending = true;
+                return;
+            }
+        }
+        self.best_rtt = Some(self.best_rtt.map_or(sample.rtt, |best| best.min(sample.rtt)));
+
+        let offset = estimate.map(|est| est.offset);
+        let held = match (self.reference, offset) {
+            (Some(before), Some(now)) => before.abs_diff(now) <= self.schedule.stable_within,
+            _ => false,
+        };
+        self.interval = if held {
+            self.interval.saturating_mul(2).min(self.schedule.max_interval)
+        } else {
+            self.schedule.min_interval
+        };
+        self.reference = offset;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> SyncSchedule {
+        SyncSchedule {
+            min_interval: Duration::from_secs(1),
+            max_interval: Duration::from_secs(8),
+            ..SyncSchedule::default()
+        }
+    }
+
+    fn estimate(offset: i64, rtt: u64) -> Option<ClockEstimate> {
+        Some(ClockEstimate { offset, rtt, error_bound: rtt / 2, samples: 8 })
+    }
+
+    fn sample(offset: i64, rtt: u64) -> Option<ClockOffset> {
+        Some(ClockOffset { offset, rtt })
+    }
+
+    #[test]
+    fn starts_with_burst_then_widens_while_offset_holds() {
+        let mut pacer = SyncPacer::new(schedule());
+        assert_eq!(pacer.next_step(), SyncStep::Burst);
+        pacer.burst_done(estimate(5_000, 1_000));
+        let mut intervals = Vec::new();
+        for _ in 0..5 {
+            let SyncStep::Probe(interval) = pacer.next_step() else { panic!("unexpected burst") };
+            intervals.push(interval.as_secs());
+            pacer.probe_done(sample(5_100, 1_000), estimate(5_100, 1_000));
+        }
+        assert_eq!(intervals, vec![1, 2, 4, 8, 8]);
+        assert!(pacer.is_settled());
+    }
+
+    #[test]
+    fn offset_jump_tightens_interval() {
+        let mut pacer = SyncPacer::new(schedule());
+        pacer.next_step();
+        pacer.burst_done(estimate(0, 1_000));
+        pacer.probe_done(sample(0, 1_000), estimate(0, 1_000));
+        pacer.probe_done(sample(0, 1_000), estimate(0, 1_000));
+        assert_eq!(pacer.next_step(), SyncStep::Probe(Duration::from_secs(4)));
+        pacer.probe_done(sample(3_000, 1_000), estimate(3_000, 1_000));
+        assert_eq!(pacer.next_step(), SyncStep::Probe(Duration::from_secs(1)));
+        assert!(!pacer.is_settled());
+    }
+
+    #[test]
+    fn rtt_spike_and_requests_trigger_burst() {
+        let mut pacer = SyncPacer::new(schedule());
+        pacer.next_step();
+        pacer.burst_done(estimate(0, 1_000));
+        // Jitter below the floor is ignored
+        pacer.probe_done(sample(0, 2_900), estimate(0, 1_000));
+        assert!(matches!(pacer.next_step(), SyncStep::Probe(_)));
+        pacer.probe_done(sample(0, 20_000), estimate(0, 1_000));
+        assert_eq!(pacer.next_step(), SyncStep::Burst);
+
+        pacer.burst_done(estimate(0, 1_000));
+        pacer.request_burst();
+        assert_eq!(pacer.next_step(), SyncStep::Burst);
+        assert!(matches!(pacer.next_step(), SyncStep::Probe(_)));
+    }
+
+    #[test]
+    fn lost_probe_resets_interval() {
+        let mut pacer = SyncPacer::new(schedule());
+        pacer.next_step();
+        pacer.burst_done(estimate(0, 1_000));
+        pacer.probe_done(sample(0, 1_000), estimate(0, 1_000));
+        assert!(pacer.is_settled());
+        pacer.probe_done(None, estimate(0, 1_000));
+        assert_eq!(pacer.next_step(), SyncStep::Probe(Duration::from_secs(1)));
+    }
+}
@@ -0,0 +1,311 @@
+use rust_core::messages::{ClientKind, ErrorCode, Role, ServerMessage, SyncState};
+use server::app_state::{AppState, AppStateConfig, HeartbeatConfig, Peer, PeerTelemetry, SharedState};
+use sonicsync_client::{Backoff, Client, ClientConfig, ClientError, ConnectionState, Event, SyncSchedule};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use url::Url;
+
+/// Serve `create_router(state)` on an ephemeral port
+async fn spawn_server(state: SharedState) -> SocketAddr {
+    let app = server::routes::create_router(state);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
+    addr
+}
+
+fn config(addr: SocketAddr, query: &str) -> ClientConfig {
+    let url = Url::parse(&format!("ws://{}/ws{}", addr, query)).unwrap();
+    let mut config = ClientConfig::new(url, ClientKind::Cli);
+    config.backoff = Backoff::new(Duration::from_millis(10), Duration::from_millis(100), 3);
+    config
+}
+
+/// Next event matching `pick`, skipping the rest. Panics after a few seconds.
+async fn next_event<T>(events: &mut broadcast::Receiver<Event>, mut pick: impl FnMut(Event) -> Option<T>) -> T {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(found) = pick(events.recv().await.expect("event channel closed")) {
+                return found;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for event")
+}
+
+#[tokio::test]
+async fn connects_joins_and_syncs() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let client = Client::new(config(addr, "?room=party"));
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+
+    assert_eq!(next_event(&mut events, |e| matches!(e, Event::State(s) if s == ConnectionState::Connected).then_some(())).await, ());
+    assert_eq!(client.role(), Some(Role::Host));
+    assert!(client.control_token().is_some());
+    let session_id = client.session_id().unwrap();
+    assert!(state.room("party").unwrap().peers.contains_key(&session_id));
+
+    client.sync().await.unwrap();
+    assert!(client.estimate().is_some());
+    // Same machine, so the server clock estimate should be close
+    let error = client.server_time() as i64 - state.clock.now_micros() as i64;
+    assert!(error.abs() < 50_000, "server time off by {}us", error);
+}
+
+#[tokio::test]
+async fn commands_and_broadcasts_flow_through_events() {
+    let addr = spawn_server(AppState::new()).await;
+    let host = Client::new(config(addr, ""));
+    host.connect().await.unwrap();
+    let listener = Client::new(config(addr, ""));
+    let mut heard = listener.subscribe();
+    listener.connect().await.unwrap();
+    assert_eq!(listener.role(), Some(Role::Listener));
+
+    let mut host_events = host.subscribe();
+    let id = host.request_play("http://example.com/a.mp3", 1000).await.unwrap();
+    let acked = next_event(&mut host_events, |e| match e {
+        Event::Message(ServerMessage::Ack { id }) => Some(id),
+        _ => None,
+    })
+    .await;
+    assert_eq!(acked, id);
+    let url = next_event(&mut heard, |e| match e {
+        Event::Message(ServerMessage::PlayCommand { track_url, .. }) => Some(track_url),
+        _ => None,
+    })
+    .await;
+    assert_eq!(url, "http://example.com/a.mp3");
+
+    // Listeners can't drive playback
+    let id = listener.request_play("http://example.com/b.mp3", 1000).await.unwrap();
+    let (failed, code) = next_event(&mut heard, |e| match e {
+        Event::Message(ServerMessage::Error { id, code, .. }) => Some((id, code)),
+        _ => None,
+    })
+    .await;
+    assert_eq!((failed, code), (id, ErrorCode::Forbidden));
+
+    // Promotion shows up as our own Role event
+    host.set_role(listener.session_id().unwrap(), Role::CoHost).await.unwrap();
+    let role = next_event(&mut heard, |e| match e {
+        Event::Role(role) => Some(role),
+        _ => None,
+    })
+    .await;
+    assert_eq!(role, Role::CoHost);
+    assert_eq!(listener.role(), Some(Role::CoHost));
+}
+
+#[tokio::test]
+async fn wrong_pin_is_refused_without_retrying() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut owner = config(addr, "?room=locked");
+    owner.pin = Some("1234".into());
+    let owner = Client::new(owner);
+    owner.connect().await.unwrap();
+
+    let mut intruder = config(addr, "?room=locked");
+    intruder.pin = Some("0000".into());
+    let intruder = Client::new(intruder);
+    let mut events = intruder.subscribe();
+    let err = intruder.connect().await.unwrap_err();
+    assert!(matches!(err, ClientError::Refused(_)), "{:?}", err);
+
+    let mut states = Vec::new();
+    while let Ok(Event::State(s)) = events.try_recv() {
+        states.push(s);
+    }
+    assert_eq!(states, vec![ConnectionState::Connecting, ConnectionState::Failed]);
+}
+
+#[tokio::test]
+async fn gives_up_when_server_is_unreachable() {
+    // Bind then drop, so nothing is listening there
+    let addr = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap();
+    let client = Client::new(config(addr, ""));
+    assert_eq!(client.connect().await.unwrap_err(), ClientError::GaveUp);
+    assert!(!client.is_connected());
+}
+
+#[tokio::test]
+async fn background_sync_widens_probes_and_bursts_on_request() {
+    let addr = spawn_server(AppState::new()).await;
+    let mut cfg = config(addr, "");
+    cfg.sync = SyncSchedule {
+        burst: 4,
+        burst_spacing: Duration::from_millis(5),
+        min_interval: Duration::from_millis(20),
+        max_interval: Duration::from_millis(80),
+        // Localhost offsets wobble by well under this
+        stable_within: 5_000,
+        ..SyncSchedule::default()
+    };
+    let client = Client::new(cfg);
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+
+    let first = next_event(&mut events, |e| match e {
+        Event::Sync(quality) => Some(quality),
+        _ => None,
+    })
+    .await;
+    assert!(!first.settled);
+    assert_eq!(first.next_probe, Duration::from_millis(20));
+    assert!(first.estimate.samples > 0);
+    // Nothing else is talking, so the loop backs off to its ceiling
+    next_event(&mut events, |e| matches!(e, Event::Sync(q) if q.next_probe == Duration::from_millis(80)).then_some(())).await;
+    assert!(client.sync_quality().unwrap().settled);
+
+    // A requested burst starts probing over from the shortest interval
+    client.resync();
+    next_event(&mut events, |e| matches!(e, Event::Sync(q) if !q.settled).then_some(())).await;
+    client.disconnect().await;
+}
+
+/// The peer's first Telemetry reported after server time `since`. Panics after a second.
+async fn report_after(peer: &Peer, since: u64) -> PeerTelemetry {
+    for _ in 0..50 {
+        let telemetry = peer.telemetry.read().unwrap().clone();
+        if telemetry.reported_at > since {
+            return telemetry;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("no Telemetry after {}", since);
+}
+
+#[tokio::test]
+async fn reports_lock_once_the_opening_burst_converges() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let client = Client::new(config(addr, ""));
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+
+    let quality = next_event(&mut events, |e| match e {
+        Event::Sync(quality) => Some(quality),
+        _ => None,
+    })
+    .await;
+    // Loopback: a burst is plenty to lock
+    assert_eq!(quality.metrics.state, SyncState::Locked);
+    assert!(quality.metrics.good_samples >= 4);
+    assert!(quality.metrics.rtt_min <= quality.metrics.rtt_median);
+
+    let peer = state.default_room().peers.get(&client.session_id().unwrap()).unwrap().clone();
+    assert_eq!(report_after(&peer, 0).await.sync_state, SyncState::Locked);
+    let roster = state.default_room().roster();
+    assert_eq!(roster[0].sync_state, SyncState::Locked);
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn sync_required_brings_burst_and_fresh_telemetry() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let client = Client::new(config(addr, ""));
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+    next_event(&mut events, |e| matches!(e, Event::Sync(_)).then_some(())).await;
+    let peer = state.default_room().peers.get(&client.session_id().unwrap()).unwrap().clone();
+    // Locking is reported on its own; the resync must bring another report
+    let locked = report_after(&peer, 0).await;
+
+    assert_eq!(state.default_room().request_resync(None), 1);
+    next_event(&mut events, |e| matches!(e, Event::Message(ServerMessage::SyncRequired)).then_some(())).await;
+    next_event(&mut events, |e| matches!(e, Event::Sync(_)).then_some(())).await;
+    let telemetry = report_after(&peer, locked.reported_at).await;
+    assert_eq!(telemetry.status, "synced");
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn syncs_over_udp_when_the_server_offers_it() {
+    let state = AppState::new();
+    server::udp_time::start(state.clone(), "127.0.0.1:0".parse().unwrap()).await.unwrap();
+    let addr = spawn_server(state).await;
+    let client = Client::new(config(addr, ""));
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+
+    next_event(&mut events, |e| matches!(e, Event::Sync(_)).then_some(())).await;
+    assert!(client.uses_udp_time());
+    client.sync().await.unwrap();
+    client.disconnect().await;
+    assert!(!client.uses_udp_time());
+}
+
+#[tokio::test]
+async fn falls_back_to_websocket_when_udp_is_unreachable() {
+    let state = AppState::new();
+    // Advertise a port nobody answers on
+    let dead = std::net::UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    state.set_udp_time_port(Some(dead));
+    let addr = spawn_server(state).await;
+    let client = Client::new(config(addr, ""));
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+
+    let quality = next_event(&mut events, |e| match e {
+        Event::Sync(quality) => Some(quality),
+        _ => None,
+    })
+    .await;
+    assert!(!client.uses_udp_time());
+    assert!(quality.estimate.samples > 0);
+}
+
+#[tokio::test]
+async fn silent_link_reconnects_and_resumes_session() {
+    // The server only pings every 5s, so a short stale_after makes the client give up on the link
+    let heartbeat = HeartbeatConfig { interval: Duration::from_secs(5), ..HeartbeatConfig::default() };
+    let state = AppState::with_config(AppStateConfig { heartbeat, ..AppStateConfig::default() });
+    let addr = spawn_server(state.clone()).await;
+    let mut cfg = config(addr, "");
+    cfg.stale_after = Duration::from_millis(300);
+    cfg.sync.burst = 1;
+    let client = Client::new(cfg);
+    let mut events = client.subscribe();
+    client.connect().await.unwrap();
+    let session_id = client.session_id().unwrap();
+
+    next_event(&mut events, |e| matches!(e, Event::State(ConnectionState::Reconnecting)).then_some(())).await;
+    let resumed = next_event(&mut events, |e| match e {
+        Event::Message(ServerMessage::Welcome { session_id, resumed, .. }) => Some((session_id, resumed)),
+        _ => None,
+    })
+    .await;
+    assert_eq!(resumed, (session_id, true));
+    assert_eq!(state.default_room().peers.len(), 1);
+    client.disconnect().await;
+}
+
+#[tokio::test]
+async fn disconnect_leaves_the_room() {
+    let state = AppState::new();
+    let addr = spawn_server(state.clone()).await;
+    let client = Client::new(config(addr, ""));
+    client.connect().await.unwrap();
+    assert_eq!(state.default_room().peers.len(), 1);
+
+    client.disconnect().await;
+    assert!(!client.is_connected());
+    assert_eq!(client.sync().await.unwrap_err(), ClientError::NotConnected);
+    for _ in 0..50 {
+        if state.default_room().peers.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    // A clean close ends the session at once rather than holding it for resumption
+    assert!(state.default_room().peers.is_empty());
+}
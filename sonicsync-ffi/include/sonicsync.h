@@ -0,0 +1,149 @@
+/*
+ * SonicSync C API: connect to a SonicSync server, keep the clock in sync
+ * and receive playback commands. Link against libsonicsync (.so/.a).
+ *
+ * All functions are safe to call from any thread. None of them panic or
+ * throw across the boundary; failures are reported as negative status codes.
+ */
+#ifndef SONICSYNC_H
+#define SONICSYNC_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Return codes. Zero is success; everything else is negative. */
+typedef enum {
+    SONICSYNC_OK = 0,
+    SONICSYNC_ERR_INVALID_ARGUMENT = -1, /* NULL handle, bad UTF-8 or unparsable URL */
+    SONICSYNC_ERR_NOT_CONNECTED = -2,
+    SONICSYNC_ERR_TRANSPORT = -3,        /* Couldn't reach the server */
+    SONICSYNC_ERR_REFUSED = -4,          /* Wrong PIN or similar; retrying won't help */
+    SONICSYNC_ERR_INCOMPATIBLE = -5,     /* Server speaks a different protocol version */
+    SONICSYNC_ERR_GAVE_UP = -6,          /* Reconnect attempts used up */
+    SONICSYNC_ERR_TIMEOUT = -7,
+    SONICSYNC_ERR_INTERNAL = -99         /* A bug in the library; details are logged */
+} sonicsync_status;
+
+/* Connection lifecycle, reported as SONICSYNC_EVENT_STATE */
+typedef enum {
+    SONICSYNC_STATE_CONNECTING = 0,
+    SONICSYNC_STATE_CONNECTED = 1,
+    SONICSYNC_STATE_RECONNECTING = 2,
+    SONICSYNC_STATE_FAILED = 3
+} sonicsync_state;
+
+/* Our role in the room */
+typedef enum {
+    SONICSYNC_ROLE_HOST = 0,
+    SONICSYNC_ROLE_CO_HOST = 1,
+    SONICSYNC_ROLE_LISTENER = 2
+} sonicsync_role;
+
+/* How far clock sync has come; only play once LOCKED */
+typedef enum {
+    SONICSYNC_SYNC_UNSYNCED = 0,
+    SONICSYNC_SYNC_CONVERGING = 1,
+    SONICSYNC_SYNC_LOCKED = 2
+} sonicsync_sync_state;
+
+typedef enum {
+    SONICSYNC_EVENT_STATE = 0, /* state */
+    SONICSYNC_EVENT_ROLE = 1,  /* role */
+    SONICSYNC_EVENT_PLAY = 2,  /* track_url, start_at_server_time, start_at_position_ms */
+    SONICSYNC_EVENT_PAUSE = 3, /* server_time */
+    SONICSYNC_EVENT_ACK = 4,   /* request_id */
+    SONICSYNC_EVENT_ERROR = 5, /* request_id, error_code, message */
+    SONICSYNC_EVENT_SYNC = 6   /* offset_us, rtt_us, error_bound_us, settled, sync_state */
+} sonicsync_event_kind;
+
+/*
+ * One event. Only the fields listed for its kind are meaningful; strings are
+ * NUL-terminated and valid only for the duration of the callback.
+ */
+typedef struct {
+    sonicsync_event_kind kind;
+    sonicsync_state state;
+    sonicsync_role role;
+    const char *track_url;
+    uint64_t start_at_server_time; /* us, server clock */
+    uint64_t start_at_position_ms;
+    uint64_t server_time;          /* us, server clock */
+    uint32_t request_id;
+    const char *error_code;
+    const char *message;
+    int64_t offset_us;             /* Server clock minus local clock */
+    uint64_t rtt_us;
+    uint64_t error_bound_us;       /* offset_us is accurate to +/- this */
+    bool settled;                  /* Offset has held still between background probes */
+    int32_t sync_state;            /* sonicsync_sync_state */
+} sonicsync_event;
+
+/* Called on a library thread. Must not call sonicsync_client_free. */
+typedef void (*sonicsync_event_cb)(const sonicsync_event *event, void *user_data);
+
+typedef struct sonicsync_client sonicsync_client;
+
+/* Optional settings; zero-initialise and fill in what you need */
+typedef struct {
+    const char *pin;      /* Room PIN, or NULL */
+    uint32_t max_retries; /* Reconnect attempts before giving up; 0 for the default */
+} sonicsync_options;
+
+/*
+ * Create a client for a server URL such as "ws://host:3000/ws?room=party".
+ * options and callback may be NULL. Returns NULL if the URL is invalid.
+ */
+sonicsync_client *sonicsync_client_new(const char *url, const sonicsync_options *options,
+                                       sonicsync_event_cb callback, void *user_data);
+
+/* Disconnect and release the client. NULL is ignored. */
+void sonicsync_client_free(sonicsync_client *client);
+
+/*
+ * Connect and join, retrying with backoff. Blocks until joined or given up.
+ * Once joined the library reconnects on its own until sonicsync_disconnect.
+ */
+int32_t sonicsync_connect(sonicsync_client *client);
+
+/* Close the connection cleanly and stop reconnecting. Blocks briefly. */
+void sonicsync_disconnect(sonicsync_client *client);
+
+/*
+ * The library syncs in the background while connected (SONICSYNC_EVENT_SYNC
+ * reports each round). This forces a full resync burst now.
+ */
+int32_t sonicsync_resync(const sonicsync_client *client);
+
+/* One clock round trip (blocking). Either out-pointer may be NULL. */
+int32_t sonicsync_sync(sonicsync_client *client, int64_t *offset_us, uint64_t *rtt_us);
+
+/* Server clock minus local clock, in microseconds (0 before the first sync) */
+int64_t sonicsync_offset_us(const sonicsync_client *client);
+
+/* Current server time in microseconds, as best the client knows it */
+uint64_t sonicsync_server_time_us(const sonicsync_client *client);
+
+/* Playback speed multiplier that steers the given drift (ms) back to zero */
+double sonicsync_correction(sonicsync_client *client, int64_t drift_ms, double dt_seconds);
+
+/* Our role, or -1 before joining */
+int32_t sonicsync_get_role(const sonicsync_client *client);
+
+/*
+ * Ask the room to play a URL after delay_ms. Returns the request id echoed in
+ * the ACK/ERROR event, or a negative status.
+ */
+int64_t sonicsync_request_play(sonicsync_client *client, const char *track_url, uint64_t delay_ms);
+
+/* Static description of a status code */
+const char *sonicsync_status_message(int32_t status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* SONICSYNC_H */
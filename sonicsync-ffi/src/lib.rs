@@ -0,0 +1,405 @@
+//! C ABI over `sonicsync_client::Client`, for desktop daemons, test rigs and
+//! anything else that can load a shared library. `include/sonicsync.h` is the
+//! matching header; keep the two in step.
+
+use once_cell::sync::Lazy;
+use rust_core::messages::{Role, ServerMessage, SyncState};
+use sonicsync_client::{Client, ClientConfig, ClientError, ConnectionState, Event};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+use tokio::runtime::Runtime;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use url::Url;
+
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| Runtime::new().expect("failed to start tokio runtime"));
+
+pub const SONICSYNC_OK: i32 = 0;
+pub const SONICSYNC_ERR_INVALID_ARGUMENT: i32 = -1;
+pub const SONICSYNC_ERR_NOT_CONNECTED: i32 = -2;
+pub const SONICSYNC_ERR_TRANSPORT: i32 = -3;
+pub const SONICSYNC_ERR_REFUSED: i32 = -4;
+pub const SONICSYNC_ERR_INCOMPATIBLE: i32 = -5;
+pub const SONICSYNC_ERR_GAVE_UP: i32 = -6;
+pub const SONICSYNC_ERR_TIMEOUT: i32 = -7;
+pub const SONICSYNC_ERR_INTERNAL: i32 = -99;
+
+fn status(e: &ClientError) -> i32 {
+    match e {
+        ClientError::NotConnected => SONICSYNC_ERR_NOT_CONNECTED,
+        ClientError::Transport(_) => SONICSYNC_ERR_TRANSPORT,
+        ClientError::Refused(_) => SONICSYNC_ERR_REFUSED,
+        ClientError::Incompatible { .. } => SONICSYNC_ERR_INCOMPATIBLE,
+        ClientError::GaveUp => SONICSYNC_ERR_GAVE_UP,
+        ClientError::Timeout => SONICSYNC_ERR_TIMEOUT,
+    }
+}
+
+/// Mirrors `sonicsync_event_kind`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    State = 0,
+    Role = 1,
+    Play = 2,
+    Pause = 3,
+    Ack = 4,
+    Error = 5,
+    Sync = 6,
+}
+
+/// Mirrors `sonicsync_event`
+#[repr(C)]
+pub struct FfiEvent {
+    pub kind: EventKind,
+    pub state: i32,
+    pub role: i32,
+    pub track_url: *const c_char,
+    pub start_at_server_time: u64,
+    pub start_at_position_ms: u64,
+    pub server_time: u64,
+    pub request_id: u32,
+    pub error_code: *const c_char,
+    pub message: *const c_char,
+    pub offset_us: i64,
+    pub rtt_us: u64,
+    pub error_bound_us: u64,
+    pub settled: bool,
+    pub sync_state: i32,
+}
+
+impl FfiEvent {
+    fn new(kind: EventKind) -> Self {
+        Self {
+            kind,
+            state: 0,
+            role: 0,
+            track_url: ptr::null(),
+            start_at_server_time: 0,
+            start_at_position_ms: 0,
+            server_time: 0,
+            request_id: 0,
+            error_code: ptr::null(),
+            message: ptr::null(),
+            offset_us: 0,
+            rtt_us: 0,
+            error_bound_us: 0,
+            settled: false,
+            sync_state: 0,
+        }
+    }
+}
+
+/// Mirrors `sonicsync_options`
+#[repr(C)]
+pub struct FfiOptions {
+    pub pin: *const c_char,
+    pub max_retries: u32,
+}
+
+pub type EventCallback = Option<extern "C" fn(event: *const FfiEvent, user_data: *mut c_void)>;
+
+fn state_code(state: ConnectionState) -> i32 {
+    match state {
+        ConnectionState::Connecting => 0,
+        ConnectionState::Connected => 1,
+        ConnectionState::Reconnecting => 2,
+        ConnectionState::Failed => 3,
+    }
+}
+
+fn role_code(role: Role) -> i32 {
+    match role {
+        Role::Host => 0,
+        Role::CoHost => 1,
+        Role::Listener => 2,
+    }
+}
+
+fn sync_state_code(state: SyncState) -> i32 {
+    match state {
+        SyncState::Unsynced => 0,
+        SyncState::Converging => 1,
+        SyncState::Locked => 2,
+    }
+}
+
+/// The caller's callback and context pointer. The header requires the callback
+/// to be callable from any thread, which is what makes moving it there sound.
+struct Listener {
+    callback: extern "C" fn(*const FfiEvent, *mut c_void),
+    user_data: *mut c_void,
+}
+
+unsafe impl Send for Listener {}
+
+impl Listener {
+    fn deliver(&self, event: Event) {
+        // Strings must outlive the call, so they're owned here
+        let cstring = |s: String| CString::new(s.replace('\0', "")).unwrap_or_default();
+        let mut out;
+        let mut owned: Vec<CString> = Vec::new();
+        match event {
+            Event::State(state) => {
+                out = FfiEvent::new(EventKind::State);
+                out.state = state_code(state);
+            }
+            Event::Role(role) => {
+                out = FfiEvent::new(EventKind::Role);
+                out.role = role_code(role);
+            }
+            Event::Message(ServerMessage::PlayCommand { track_url, start_at_server_time, start_at_position_ms, .. }) => {
+                out = FfiEvent::new(EventKind::Play);
+                owned.push(cstring(track_url));
+                out.track_url = owned[0].as_ptr();
+                out.start_at_server_time = start_at_server_time;
+                out.start_at_position_ms = start_at_position_ms;
+            }
+            Event::Message(ServerMessage::PauseCommand { server_time }) => {
+                out = FfiEvent::new(EventKind::Pause);
+                out.server_time = server_time;
+            }
+            Event::Message(ServerMessage::Ack { id }) => {
+                out = FfiEvent::new(EventKind::Ack);
+                out.request_id = id;
+            }
+            Event::Message(ServerMessage::Error { id, code, message }) => {
+                out = FfiEvent::new(EventKind::Error);
+                owned.push(cstring(format!("{:?}", code)));
+                owned.push(cstring(message));
+                out.request_id = id;
+                out.error_code = owned[0].as_ptr();
+                out.message = owned[1].as_ptr();
+            }
+            Event::Sync(quality) => {
+                out = FfiEvent::new(EventKind::Sync);
+                out.offset_us = quality.estimate.offset;
+                out.rtt_us = quality.estimate.rtt;
+                out.error_bound_us = quality.estimate.error_bound;
+                out.settled = quality.settled;
+                out.sync_state = sync_state_code(quality.metrics.state);
+            }
+            Event::Message(_) => return,
+        }
+        (self.callback)(&out, self.user_data);
+    }
+}
+
+async fn forward_events(mut events: broadcast::Receiver<Event>, listener: Listener) {
+    loop {
+        match events.recv().await {
+            Ok(event) => {
+                if catch_unwind(AssertUnwindSafe(|| listener.deliver(event))).is_err() {
+                    log::error!("sonicsync: panic while delivering an event");
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => log::warn!("sonicsync: dropped {} events", missed),
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Opaque handle behind `sonicsync_client *`
+pub struct FfiClient {
+    client: Client,
+    forwarder: Option<JoinHandle<()>>,
+}
+
+/// Run `f`, turning a panic into `fallback` so it never unwinds into C
+fn guard<T>(fallback: T, f: impl FnOnce() -> T) -> T {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|_| {
+        log::error!("sonicsync: internal panic caught at the C boundary");
+        fallback
+    })
+}
+
+/// # Safety
+/// `s` must be NULL or a valid NUL-terminated string
+unsafe fn opt_str(s: *const c_char) -> Result<Option<String>, ()> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    CStr::from_ptr(s).to_str().map(|s| Some(s.to_string())).map_err(|_| ())
+}
+
+/// Create a client. Returns NULL if `url` is NULL, not UTF-8 or unparsable.
+///
+/// # Safety
+/// `url` must be NULL or a valid NUL-terminated string; `options` must be NULL
+/// or point to a valid `sonicsync_options`. `user_data` is passed back to
+/// `callback` untouched, from a library thread.
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_client_new(
+    url: *const c_char,
+    options: *const FfiOptions,
+    callback: EventCallback,
+    user_data: *mut c_void,
+) -> *mut FfiClient {
+    guard(ptr::null_mut(), || {
+        let Ok(Some(url)) = opt_str(url) else {
+            return ptr::null_mut();
+        };
+        let Ok(url) = Url::parse(&url) else {
+            log::error!("sonicsync: invalid URL {:?}", url);
+            return ptr::null_mut();
+        };
+        let mut config = ClientConfig::new(url, rust_core::messages::ClientKind::Other);
+        if let Some(options) = options.as_ref() {
+            let Ok(pin) = opt_str(options.pin) else {
+                return ptr::null_mut();
+            };
+            config.pin = pin.filter(|p| !p.is_empty());
+            if options.max_retries > 0 {
+                config.backoff = config.backoff.with_max_attempts(options.max_retries);
+            }
+        }
+        let client = Client::new(config);
+        let forwarder = callback.map(|callback| {
+            let listener = Listener { callback, user_data };
+            RUNTIME.spawn(forward_events(client.subscribe(), listener))
+        });
+        Box::into_raw(Box::new(FfiClient { client, forwarder }))
+    })
+}
+
+/// # Safety
+/// `client` must be NULL or a pointer from `sonicsync_client_new` not yet freed
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_client_free(client: *mut FfiClient) {
+    if client.is_null() {
+        return;
+    }
+    guard((), || {
+        let handle = Box::from_raw(client);
+        RUNTIME.block_on(handle.client.disconnect());
+        if let Some(forwarder) = &handle.forwarder {
+            forwarder.abort();
+        }
+    })
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_connect(client: *mut FfiClient) -> i32 {
+    let Some(handle) = client.as_ref() else {
+        return SONICSYNC_ERR_INVALID_ARGUMENT;
+    };
+    guard(SONICSYNC_ERR_INTERNAL, || match RUNTIME.block_on(handle.client.connect()) {
+        Ok(()) => SONICSYNC_OK,
+        Err(e) => {
+            log::error!("sonicsync: connect failed: {}", e);
+            status(&e)
+        }
+    })
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_disconnect(client: *mut FfiClient) {
+    if let Some(handle) = client.as_ref() {
+        guard((), || RUNTIME.block_on(handle.client.disconnect()))
+    }
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_resync(client: *const FfiClient) -> i32 {
+    let Some(handle) = client.as_ref() else {
+        return SONICSYNC_ERR_INVALID_ARGUMENT;
+    };
+    guard(SONICSYNC_ERR_INTERNAL, || {
+        handle.client.resync();
+        SONICSYNC_OK
+    })
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`;
+/// `offset_us` and `rtt_us` must be NULL or valid for writes
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_sync(client: *mut FfiClient, offset_us: *mut i64, rtt_us: *mut u64) -> i32 {
+    let Some(handle) = client.as_ref() else {
+        return SONICSYNC_ERR_INVALID_ARGUMENT;
+    };
+    guard(SONICSYNC_ERR_INTERNAL, || match RUNTIME.block_on(handle.client.sync()) {
+        Ok(sample) => {
+            if let Some(out) = offset_us.as_mut() {
+                *out = sample.offset;
+            }
+            if let Some(out) = rtt_us.as_mut() {
+                *out = sample.rtt;
+            }
+            SONICSYNC_OK
+        }
+        Err(e) => status(&e),
+    })
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_offset_us(client: *const FfiClient) -> i64 {
+    client.as_ref().map_or(0, |handle| guard(0, || handle.client.offset()))
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_server_time_us(client: *const FfiClient) -> u64 {
+    client.as_ref().map_or(0, |handle| guard(0, || handle.client.server_time()))
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_correction(client: *mut FfiClient, drift_ms: i64, dt_seconds: f64) -> f64 {
+    client.as_ref().map_or(1.0, |handle| guard(1.0, || handle.client.correction(drift_ms, dt_seconds)))
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_get_role(client: *const FfiClient) -> i32 {
+    client
+        .as_ref()
+        .and_then(|handle| guard(None, || handle.client.role()))
+        .map_or(-1, role_code)
+}
+
+/// # Safety
+/// `client` must be NULL or a live pointer from `sonicsync_client_new`;
+/// `track_url` must be NULL or a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn sonicsync_request_play(client: *mut FfiClient, track_url: *const c_char, delay_ms: u64) -> i64 {
+    let Some(handle) = client.as_ref() else {
+        return SONICSYNC_ERR_INVALID_ARGUMENT as i64;
+    };
+    let Ok(Some(track_url)) = opt_str(track_url) else {
+        return SONICSYNC_ERR_INVALID_ARGUMENT as i64;
+    };
+    guard(SONICSYNC_ERR_INTERNAL as i64, || match RUNTIME.block_on(handle.client.request_play(track_url, delay_ms)) {
+        Ok(id) => id as i64,
+        Err(e) => status(&e) as i64,
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn sonicsync_status_message(status: i32) -> *const c_char {
+    let msg: &'static CStr = match status {
+        SONICSYNC_OK => c"ok",
+        SONICSYNC_ERR_INVALID_ARGUMENT => c"invalid argument",
+        SONICSYNC_ERR_NOT_CONNECTED => c"not connected",
+        SONICSYNC_ERR_TRANSPORT => c"could not reach the server",
+        SONICSYNC_ERR_REFUSED => c"refused by the server",
+        SONICSYNC_ERR_INCOMPATIBLE => c"incompatible protocol version",
+        SONICSYNC_ERR_GAVE_UP => c"gave up reconnecting",
+        SONICSYNC_ERR_TIMEOUT => c"timed out",
+        SONICSYNC_ERR_INTERNAL => c"internal error",
+        _ => c"unknown status",
+    };
+    msg.as_ptr()
+}
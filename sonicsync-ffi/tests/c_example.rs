@@ -0,0 +1,80 @@
+//! Builds examples/sync_demo.c against the shared library with the system C
+//! compiler and runs it against the real server.
+
+#![cfg(target_os = "linux")]
+
+use server::app_state::AppState;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Serve `create_router` on an ephemeral port from a runtime of its own,
+/// so it keeps running while the test thread blocks on the C program
+fn spawn_server() -> SocketAddr {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let app = server::routes::create_router(AppState::new());
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            tx.send(listener.local_addr().unwrap()).unwrap();
+            axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        });
+    });
+    rx.recv().unwrap()
+}
+
+/// target/<profile>/deps, where cargo builds libsonicsync.so alongside this test
+fn lib_dir() -> PathBuf {
+    let exe = std::env::current_exe().unwrap();
+    exe.parent().unwrap().to_path_buf()
+}
+
+/// Tests run in parallel, so each builds its own binary
+fn build_demo(name: &str) -> PathBuf {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let lib_dir = lib_dir();
+    assert!(lib_dir.join("libsonicsync.so").exists(), "cdylib not built in {}", lib_dir.display());
+    let out = lib_dir.join(name);
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".into());
+    let status = Command::new(compiler)
+        .args(["-std=c11", "-Wall", "-Werror"])
+        .arg("-I").arg(manifest.join("include"))
+        .arg(manifest.join("examples/sync_demo.c"))
+        .arg("-L").arg(&lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lsonicsync")
+        .arg("-o").arg(&out)
+        .status()
+        .expect("failed to run the C compiler");
+    assert!(status.success(), "sync_demo.c failed to compile");
+    out
+}
+
+#[test]
+fn c_example_joins_syncs_and_plays() {
+    let addr = spawn_server();
+    let demo = build_demo("sync_demo_play");
+    let output = Command::new(&demo)
+        .arg(format!("ws://{}/ws", addr))
+        .arg("http://example.com/a.mp3")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stdout:\n{}\nstderr:\n{}", stdout, String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("joined as role 0"), "{}", stdout);
+    assert!(stdout.contains("synced: server time"), "{}", stdout);
+    assert!(stdout.contains("[demo] play http://example.com/a.mp3"), "{}", stdout);
+}
+
+#[test]
+fn c_example_reports_unreachable_server() {
+    // Bind then drop, so nothing is listening there
+    let addr = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let demo = build_demo("sync_demo_unreachable");
+    let output = Command::new(&demo).arg(format!("ws://{}/ws", addr)).output().unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("connect failed"));
+}